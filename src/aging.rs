@@ -0,0 +1,379 @@
+use std::{
+    fmt,
+    time::{Duration, SystemTime},
+};
+
+use crate::Error;
+
+pub const SECONDS_PER_DAY: u64 = 60 * 60 * 24;
+
+/// Converts `time` to a day number, counted from the Unix epoch
+pub fn day_of(time: SystemTime) -> i64 {
+    match time.duration_since(SystemTime::UNIX_EPOCH) {
+        Ok(d) => (d.as_secs() / SECONDS_PER_DAY) as i64,
+        Err(e) => -(e.duration().as_secs().div_ceil(SECONDS_PER_DAY) as i64),
+    }
+}
+
+/// Converts a day number to the time at the start of that day
+pub fn start_of_day(day: i64) -> SystemTime {
+    let secs = Duration::from_secs(day.unsigned_abs() * SECONDS_PER_DAY);
+    if day < 0 {
+        SystemTime::UNIX_EPOCH - secs
+    } else {
+        SystemTime::UNIX_EPOCH + secs
+    }
+}
+
+/// The current day number
+pub fn today() -> i64 {
+    day_of(SystemTime::now())
+}
+
+/// Converts a proleptic Gregorian calendar date to a day number
+pub fn day_from_date(year: i64, month: u32, day: u32) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year.rem_euclid(400);
+    let month = i64::from(month);
+    let day_of_year =
+        (153 * (if month > 2 { month - 3 } else { month + 9 }) + 2) / 5 + i64::from(day) - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146097 + day_of_era - 719468
+}
+
+/// Converts a day number to a proleptic Gregorian calendar date, as `(year, month, day)`
+pub fn date_from_day(day: i64) -> (i64, u32, u32) {
+    let z = day + 719468;
+    let era = z.div_euclid(146097);
+    let day_of_era = z.rem_euclid(146097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let d = (day_of_year - (153 * mp + 2) / 5 + 1) as u32;
+    let m = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let y = year_of_era + era * 400 + if m <= 2 { 1 } else { 0 };
+    (y, m, d)
+}
+
+/// Parses a date given either as `YYYY-MM-DD`, or as a day number
+pub fn parse_date(s: &str) -> Option<i64> {
+    if let Ok(day) = s.parse() {
+        return Some(day);
+    }
+    let mut parts = s.splitn(3, '-');
+    let year = parts.next()?.parse().ok()?;
+    let month = parts.next()?.parse().ok()?;
+    let day = parts.next()?.parse().ok()?;
+    if !(1..=12).contains(&month) || !(1..=31).contains(&day) {
+        return None;
+    }
+    let result = day_from_date(year, month, day);
+    // Rejects days past the end of the month, like 2021-02-30
+    if date_from_day(result) == (year, month, day) {
+        Some(result)
+    } else {
+        None
+    }
+}
+
+/// Formats a day number as `YYYY-MM-DD`
+pub fn format_date(day: i64) -> String {
+    let (year, month, day) = date_from_day(day);
+    format!("{:04}-{:02}-{:02}", year, month, day)
+}
+
+/// Password aging metadata for a user, as in the last six fields of shadow(5).
+///
+/// Dates are day numbers counted from the Unix epoch, and periods are in days. `None` means the field is empty, which disables the corresponding rule.
+///
+/// This is stored in the `aging` file of the user directory, in the shadow(5) format: `last_change:min_days:max_days:warn_days:inactive_days:expire_date`.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct Aging {
+    /// The day the password was last changed. `Some(0)` requires the password to be changed at the next login
+    pub last_change: Option<i64>,
+    /// The number of days after a change before the user may change their password again
+    pub min_days: Option<i64>,
+    /// The number of days after a change after which the password must be changed
+    pub max_days: Option<i64>,
+    /// The number of days before the password expires during which the user is warned
+    pub warn_days: Option<i64>,
+    /// The number of days after the password expires during which it may still be changed at login
+    pub inactive_days: Option<i64>,
+    /// The day after which the account can no longer be used
+    pub expire_date: Option<i64>,
+}
+
+impl Aging {
+    pub fn parse(s: &str) -> crate::Result<Self> {
+        let fields = s
+            .trim_end_matches('\n')
+            .split(':')
+            .map(|field| match field {
+                "" => Ok(None),
+                field => field.parse().map(Some),
+            })
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|_| Error::Corrupt("Invalid aging file"))?;
+        match fields[..] {
+            [last_change, min_days, max_days, warn_days, inactive_days, expire_date] => Ok(Self {
+                last_change,
+                min_days,
+                max_days,
+                warn_days,
+                inactive_days,
+                expire_date,
+            }),
+            _ => Err(Error::Corrupt("Invalid aging file")),
+        }
+    }
+
+    /// The day the password expires, if it does.
+    ///
+    /// A `last_change` of 0 means the password is already expired.
+    pub fn password_expiry(&self) -> Option<i64> {
+        match (self.last_change, self.max_days) {
+            (Some(0), _) => Some(0),
+            (Some(last_change), Some(max_days)) if max_days >= 0 => Some(last_change + max_days),
+            _ => None,
+        }
+    }
+
+    /// Checks whether the minimum age allows the password to be changed on `today`
+    pub fn can_change(&self, today: i64) -> bool {
+        match (self.last_change, self.min_days) {
+            (Some(last_change), Some(min_days)) if last_change > 0 => {
+                today >= last_change + min_days
+            }
+            _ => true,
+        }
+    }
+
+    /// Checks whether the account has expired on `today`
+    pub fn is_account_expired(&self, today: i64) -> bool {
+        matches!(self.expire_date, Some(expire_date) if today >= expire_date)
+    }
+
+    /// Checks whether the password expired more than `inactive_days` before `today`, so that it can no longer be changed at login.
+    ///
+    /// A password that must be changed because `last_change` is 0 never becomes inactive.
+    pub fn is_inactive(&self, today: i64) -> bool {
+        if self.last_change == Some(0) {
+            return false;
+        }
+        match (self.password_expiry(), self.inactive_days) {
+            (Some(expiry), Some(inactive_days)) if inactive_days >= 0 => {
+                today >= expiry + inactive_days
+            }
+            _ => false,
+        }
+    }
+}
+
+impl fmt::Display for Aging {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let fields = [
+            self.last_change,
+            self.min_days,
+            self.max_days,
+            self.warn_days,
+            self.inactive_days,
+            self.expire_date,
+        ];
+        for (i, field) in fields.iter().enumerate() {
+            if i != 0 {
+                f.write_str(":")?;
+            }
+            if let Some(v) = field {
+                write!(f, "{}", v)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DATES: &[((i64, u32, u32), i64)] = &[
+        ((1970, 1, 1), 0),
+        ((1969, 12, 31), -1),
+        ((1970, 3, 1), 59),
+        ((2000, 1, 1), 10957),
+        ((2000, 2, 29), 11016),
+        ((2000, 3, 1), 11017),
+        ((1900, 2, 28), -25509),
+        ((1900, 3, 1), -25508),
+        ((2100, 2, 28), 47540),
+        ((2100, 3, 1), 47541),
+        ((1600, 2, 29), -135081),
+        ((2038, 1, 19), 24855),
+        ((1, 1, 1), -719162),
+        ((9999, 12, 31), 2932896),
+    ];
+
+    #[test]
+    fn dates_and_days() {
+        for &(date, day) in DATES {
+            assert_eq!(day_from_date(date.0, date.1, date.2), day, "{:?}", date);
+            assert_eq!(date_from_day(day), date, "{}", day);
+        }
+        // Every day of a 400 year cycle, which covers every kind of leap year, survives the round trip
+        for day in day_from_date(1600, 1, 1)..day_from_date(2000, 1, 1) {
+            let (year, month, d) = date_from_day(day);
+            assert_eq!(day_from_date(year, month, d), day);
+        }
+    }
+
+    #[test]
+    fn days_of_times() {
+        assert_eq!(day_of(SystemTime::UNIX_EPOCH), 0);
+        assert_eq!(day_of(start_of_day(10957)), 10957);
+        assert_eq!(
+            day_of(start_of_day(10957) + Duration::from_secs(SECONDS_PER_DAY - 1)),
+            10957
+        );
+        // Times before the epoch belong to the day they fall in, not the one after
+        assert_eq!(day_of(SystemTime::UNIX_EPOCH - Duration::from_secs(1)), -1);
+        assert_eq!(day_of(start_of_day(-1)), -1);
+        assert_eq!(day_of(start_of_day(-25509)), -25509);
+    }
+
+    #[test]
+    fn date_strings() {
+        for &(_, day) in DATES.iter().filter(|&&((year, _, _), _)| year >= 1000) {
+            assert_eq!(parse_date(&format_date(day)), Some(day));
+        }
+        assert_eq!(format_date(0), "1970-01-01");
+        assert_eq!(format_date(11016), "2000-02-29");
+        assert_eq!(parse_date("2000-02-29"), Some(11016));
+        assert_eq!(parse_date("2000-2-29"), Some(11016));
+        assert_eq!(parse_date("1900-02-29"), None);
+        assert_eq!(parse_date("2100-02-29"), None);
+        assert_eq!(parse_date("2021-04-31"), None);
+        assert_eq!(parse_date("2021-13-01"), None);
+        assert_eq!(parse_date("2021-00-01"), None);
+        assert_eq!(parse_date("2021-01-00"), None);
+        assert_eq!(parse_date("2021-01"), None);
+        assert_eq!(parse_date("yesterday"), None);
+        // Day numbers are accepted as well, including -1 to clear a date
+        assert_eq!(parse_date("18000"), Some(18000));
+        assert_eq!(parse_date("-1"), Some(-1));
+    }
+
+    #[test]
+    fn aging_file_round_trip() {
+        let aging = Aging {
+            last_change: Some(19000),
+            min_days: Some(0),
+            max_days: Some(99999),
+            warn_days: Some(7),
+            inactive_days: None,
+            expire_date: Some(-1),
+        };
+        assert_eq!(aging.to_string(), "19000:0:99999:7::-1");
+        assert_eq!(Aging::parse("19000:0:99999:7::-1\n").unwrap(), aging);
+        assert_eq!(Aging::default().to_string(), ":::::");
+        assert_eq!(Aging::parse(":::::").unwrap(), Aging::default());
+        for bad in ["", "1:2:3:4:5", "1:2:3:4:5:6:7", "1:2:x:4:5:6", "1.5:::::"] {
+            assert!(
+                matches!(Aging::parse(bad), Err(Error::Corrupt(_))),
+                "{:?} parsed",
+                bad
+            );
+        }
+    }
+
+    fn aging(last_change: i64, max_days: Option<i64>) -> Aging {
+        Aging {
+            last_change: Some(last_change),
+            max_days,
+            ..Aging::default()
+        }
+    }
+
+    #[test]
+    fn password_expiry() {
+        assert_eq!(aging(100, Some(30)).password_expiry(), Some(130));
+        assert_eq!(aging(100, Some(0)).password_expiry(), Some(100));
+        // No maximum, or a negative one, never expires
+        assert_eq!(aging(100, None).password_expiry(), None);
+        assert_eq!(aging(100, Some(-1)).password_expiry(), None);
+        assert_eq!(Aging::default().password_expiry(), None);
+        // A last change of 0 forces a change, whatever the maximum
+        assert_eq!(aging(0, None).password_expiry(), Some(0));
+        assert_eq!(aging(0, Some(30)).password_expiry(), Some(0));
+    }
+
+    #[test]
+    fn minimum_age() {
+        let aging = Aging {
+            min_days: Some(2),
+            ..aging(100, None)
+        };
+        assert!(!aging.can_change(100));
+        assert!(!aging.can_change(101));
+        assert!(aging.can_change(102));
+        // A forced change is always allowed
+        assert!(Aging {
+            last_change: Some(0),
+            ..aging
+        }
+        .can_change(0));
+        assert!(Aging::default().can_change(0));
+    }
+
+    #[test]
+    fn account_expiry() {
+        let aging = Aging {
+            expire_date: Some(200),
+            ..Aging::default()
+        };
+        assert!(!aging.is_account_expired(199));
+        assert!(aging.is_account_expired(200));
+        assert!(!Aging::default().is_account_expired(i64::MAX));
+    }
+
+    #[test]
+    fn inactivity() {
+        let aging = Aging {
+            inactive_days: Some(5),
+            ..aging(100, Some(30))
+        };
+        // The password expires on day 130, and stays usable for a change until day 135
+        assert!(!aging.is_inactive(130));
+        assert!(!aging.is_inactive(134));
+        assert!(aging.is_inactive(135));
+        let no_grace = Aging {
+            inactive_days: Some(0),
+            ..aging
+        };
+        assert!(no_grace.is_inactive(130));
+        assert!(!no_grace.is_inactive(129));
+        // Without an inactivity period, or an expiry, the password never becomes inactive
+        for aging in [
+            Aging {
+                inactive_days: None,
+                ..aging
+            },
+            Aging {
+                inactive_days: Some(-1),
+                ..aging
+            },
+            Aging {
+                max_days: None,
+                ..aging
+            },
+        ] {
+            assert!(!aging.is_inactive(i64::MAX / 2), "{:?}", aging);
+        }
+        // Nor does a forced change
+        assert!(!Aging {
+            last_change: Some(0),
+            ..aging
+        }
+        .is_inactive(1000));
+    }
+}
@@ -0,0 +1,113 @@
+use std::{
+    ffi::OsString,
+    io::{ErrorKind, Read, Write},
+    mem::forget,
+    path::{Path, PathBuf},
+};
+
+use std::os::unix::prelude::*;
+
+use crate::Error;
+
+/// The hidden file `.<name><suffix>` next to `path`, whose name is `<name>`.
+///
+/// Unlike `<path>-`, this cannot collide with the backups that shadow-utils keeps next to `/etc/passwd` and the like.
+fn sibling(path: &Path, suffix: &str) -> PathBuf {
+    let mut name = OsString::from(".");
+    name.push(path.file_name().unwrap_or_default());
+    name.push(suffix);
+    path.with_file_name(name)
+}
+
+/// Atomically updates the file at `path`.
+///
+/// `f` is given the current contents, or `None` if there is no file, and returns the new contents, or `None` to leave the file as-is.
+/// The update holds the lock file `.<name>.lc-login.lock`, created exclusively next to `path`: if another update is in progress, this fails with [`Error::Busy`].
+/// The new contents are written to `.<name>.lc-login.tmp` (created with `mode`), which is then renamed over `path`, so readers never observe a partial write.
+pub(crate) fn update<F>(path: &Path, mode: u32, f: F) -> crate::Result<()>
+where
+    F: FnOnce(Option<Vec<u8>>) -> crate::Result<Option<Vec<u8>>>,
+{
+    let lock_path = sibling(path, ".lc-login.lock");
+    std::fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .mode(0o600)
+        .open(&lock_path)
+        .map_err(|e| match e.kind() {
+            ErrorKind::AlreadyExists => Error::Busy,
+            _ => Error::Io(e),
+        })?;
+    let _unlock = defer::defer(|| drop(std::fs::remove_file(&lock_path)));
+    let tmp_path = sibling(path, ".lc-login.tmp");
+    // Left behind by an interrupted update, as the lock is held
+    match std::fs::remove_file(&tmp_path) {
+        Ok(()) => {}
+        Err(e) if e.kind() == ErrorKind::NotFound => {}
+        Err(e) => return Err(e.into()),
+    }
+    let mut tmp = std::fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .mode(mode)
+        .open(&tmp_path)?;
+    let defer = defer::defer(|| drop(std::fs::remove_file(&tmp_path)));
+    let current = match std::fs::File::open(path) {
+        Ok(mut file) => {
+            let mut bytes = Vec::new();
+            file.read_to_end(&mut bytes)?;
+            Some(bytes)
+        }
+        Err(e) if e.kind() == ErrorKind::NotFound => None,
+        Err(e) => return Err(e.into()),
+    };
+    if let Some(bytes) = f(current)? {
+        tmp.write_all(&bytes)?;
+        std::fs::rename(&tmp_path, path)?;
+        forget(defer);
+    }
+    Ok(())
+}
+
+/// Atomically points the symlink at `link` to `target`, replacing any existing link.
+///
+/// The new link is created as `<link>-` and renamed over `link`. Like [`update`], this fails with [`Error::Busy`] if `<link>-` already exists.
+pub(crate) fn replace_symlink<P: AsRef<Path>>(target: P, link: &Path) -> crate::Result<()> {
+    let mut tmp = PathBuf::from(link);
+    tmp.as_mut_os_string().push("-");
+    std::os::unix::fs::symlink(target, &tmp).map_err(|e| match e.kind() {
+        ErrorKind::AlreadyExists => Error::Busy,
+        _ => Error::Io(e),
+    })?;
+    std::fs::rename(&tmp, link).map_err(|e| {
+        let _ = std::fs::remove_file(&tmp);
+        Error::Io(e)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn update_is_exclusive() {
+        let root = tempfile::tempdir().unwrap();
+        let path = root.path().join("file");
+        update(&path, 0o644, |current| {
+            assert_eq!(current, None);
+            assert!(matches!(
+                update(&path, 0o644, |_| Ok(None)),
+                Err(Error::Busy)
+            ));
+            Ok(Some(b"contents".to_vec()))
+        })
+        .unwrap();
+        assert_eq!(std::fs::read(&path).unwrap(), b"contents");
+        // The lock is released afterwards
+        update(&path, 0o644, |current| {
+            assert_eq!(current.as_deref(), Some(&b"contents"[..]));
+            Ok(None)
+        })
+        .unwrap();
+    }
+}
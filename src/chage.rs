@@ -0,0 +1,350 @@
+use std::{io::ErrorKind, path::PathBuf};
+
+use lc_login::{aging::Aging, users::UserHandle, Error};
+
+fn print_help(prg_name: &str) {
+    println!("Usage: {} [options] LOGIN", prg_name);
+    println!("Changes the password aging information of a user account");
+    println!("Options:");
+    println!("\t-d, --lastday <date>: Set the date of the last password change. 0 requires a change at the next login. This also clears any expiry set by passwd");
+    println!("\t-E, --expiredate <date>: Set the date the account expires, or -1 to remove the account expiry");
+    println!("\t-h, --help: Print this message and exit");
+    println!("\t-I, --inactive <days>: Set the number of days after the password expires before the account is locked, or -1 to remove it");
+    println!("\t-l, --list: Show the password aging information of the account");
+    println!("\t-m, --mindays <days>: Set the minimum number of days between password changes, or -1 to remove it");
+    println!("\t-M, --maxdays <days>: Set the maximum number of days between password changes, or -1 to remove it");
+    println!("\t-R, --root: Make changes within the given sysroot");
+    println!("\t-W, --warndays <days>: Set the number of days of warning before the password expires, or -1 to remove it");
+    println!("Dates are given as YYYY-MM-DD, or as a number of days since 1970-01-01");
+}
+
+fn parse_days(prg_name: &str, opt: &str, s: &str) -> Option<i64> {
+    match s.parse::<i64>() {
+        Ok(v) if v < 0 => None,
+        Ok(v) => Some(v),
+        Err(e) => {
+            eprintln!("{}: Invalid operand for {}: {}", prg_name, opt, e);
+            std::process::exit(2)
+        }
+    }
+}
+
+fn parse_date(prg_name: &str, opt: &str, s: &str) -> Option<i64> {
+    match lc_login::aging::parse_date(s) {
+        Some(v) if v < 0 => None,
+        Some(v) => Some(v),
+        None => {
+            eprintln!("{}: Invalid date for {}: {}", prg_name, opt, s);
+            std::process::exit(2)
+        }
+    }
+}
+
+fn list_date(day: Option<i64>) -> String {
+    const MONTHS: [&str; 12] = [
+        "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+    ];
+    match day {
+        Some(day) => {
+            let (year, month, day) = lc_login::aging::date_from_day(day);
+            format!("{} {:02}, {}", MONTHS[month as usize - 1], day, year)
+        }
+        None => "never".to_string(),
+    }
+}
+
+fn list(handle: &UserHandle, aging: &Aging) -> lc_login::Result<()> {
+    // The password expires at the earlier of the aging rules and any expiry set by passwd
+    let header_expiry = match handle.password_file() {
+        Ok(file) => file.record().expiry.map(lc_login::aging::day_of),
+        Err(Error::Io(e)) if e.kind() == ErrorKind::NotFound => None,
+        Err(e) => return Err(e),
+    };
+    let expiry = match (aging.password_expiry(), header_expiry) {
+        (Some(a), Some(b)) => Some(a.min(b)),
+        (a, b) => a.or(b),
+    };
+    let must_change = aging.last_change == Some(0);
+
+    let last_change = if must_change {
+        "password must be changed".to_string()
+    } else {
+        list_date(aging.last_change)
+    };
+    let (password_expires, password_inactive) = if must_change {
+        let s = "password must be changed".to_string();
+        (s.clone(), s)
+    } else {
+        (
+            list_date(expiry),
+            list_date(match (expiry, aging.inactive_days) {
+                (Some(expiry), Some(days)) => Some(expiry + days),
+                _ => None,
+            }),
+        )
+    };
+
+    println!("Last password change\t\t\t\t\t: {}", last_change);
+    println!("Password expires\t\t\t\t\t: {}", password_expires);
+    println!("Password inactive\t\t\t\t\t: {}", password_inactive);
+    println!(
+        "Account expires\t\t\t\t\t\t: {}",
+        list_date(aging.expire_date)
+    );
+    println!(
+        "Minimum number of days between password change\t\t: {}",
+        aging.min_days.unwrap_or(-1)
+    );
+    println!(
+        "Maximum number of days between password change\t\t: {}",
+        aging.max_days.unwrap_or(-1)
+    );
+    println!(
+        "Number of days of warning before password expires\t: {}",
+        aging.warn_days.unwrap_or(-1)
+    );
+    Ok(())
+}
+
+fn operand(prg_name: &str, opt: &str, rest: String, args: &mut std::env::Args) -> String {
+    if !rest.is_empty() {
+        return rest;
+    }
+    match args.next() {
+        Some(s) => s,
+        None => {
+            eprintln!("{}: Missing operand for {}", prg_name, opt);
+            std::process::exit(2)
+        }
+    }
+}
+
+pub fn main() {
+    let mut args = std::env::args();
+    let prg_name = args.next().unwrap();
+    let mut login_name = None;
+    let mut list_only = false;
+    let mut chroot = None;
+    let mut last_day = None;
+    let mut expire_date = None;
+    let mut inactive = None;
+    let mut min_days = None;
+    let mut max_days = None;
+    let mut warn_days = None;
+
+    while let Some(s) = args.next() {
+        match &*s {
+            "--help" => {
+                print_help(&prg_name);
+                std::process::exit(0)
+            }
+            "--list" => list_only = true,
+            "--root" => chroot = Some(operand(&prg_name, &s, String::new(), &mut args)),
+            "--lastday" => {
+                let v = operand(&prg_name, &s, String::new(), &mut args);
+                last_day = Some(parse_date(&prg_name, &s, &v));
+            }
+            "--expiredate" => {
+                let v = operand(&prg_name, &s, String::new(), &mut args);
+                expire_date = Some(parse_date(&prg_name, &s, &v));
+            }
+            "--inactive" => {
+                let v = operand(&prg_name, &s, String::new(), &mut args);
+                inactive = Some(parse_days(&prg_name, &s, &v));
+            }
+            "--mindays" => {
+                let v = operand(&prg_name, &s, String::new(), &mut args);
+                min_days = Some(parse_days(&prg_name, &s, &v));
+            }
+            "--maxdays" => {
+                let v = operand(&prg_name, &s, String::new(), &mut args);
+                max_days = Some(parse_days(&prg_name, &s, &v));
+            }
+            "--warndays" => {
+                let v = operand(&prg_name, &s, String::new(), &mut args);
+                warn_days = Some(parse_days(&prg_name, &s, &v));
+            }
+            "--" => {
+                login_name = args.next();
+                break;
+            }
+            x if x.starts_with("--") => {
+                eprintln!("{}: Unrecognized option {}", prg_name, x);
+                std::process::exit(2)
+            }
+            x if x.starts_with('-') && x.len() > 1 => {
+                let mut chars = x[1..].chars();
+                while let Some(c) = chars.next() {
+                    let opt = format!("-{}", c);
+                    match c {
+                        'h' => {
+                            print_help(&prg_name);
+                            std::process::exit(0)
+                        }
+                        'l' => list_only = true,
+                        'R' => {
+                            chroot = Some(operand(&prg_name, &opt, chars.collect(), &mut args));
+                            break;
+                        }
+                        'd' => {
+                            let v = operand(&prg_name, &opt, chars.collect(), &mut args);
+                            last_day = Some(parse_date(&prg_name, &opt, &v));
+                            break;
+                        }
+                        'E' => {
+                            let v = operand(&prg_name, &opt, chars.collect(), &mut args);
+                            expire_date = Some(parse_date(&prg_name, &opt, &v));
+                            break;
+                        }
+                        'I' => {
+                            let v = operand(&prg_name, &opt, chars.collect(), &mut args);
+                            inactive = Some(parse_days(&prg_name, &opt, &v));
+                            break;
+                        }
+                        'm' => {
+                            let v = operand(&prg_name, &opt, chars.collect(), &mut args);
+                            min_days = Some(parse_days(&prg_name, &opt, &v));
+                            break;
+                        }
+                        'M' => {
+                            let v = operand(&prg_name, &opt, chars.collect(), &mut args);
+                            max_days = Some(parse_days(&prg_name, &opt, &v));
+                            break;
+                        }
+                        'W' => {
+                            let v = operand(&prg_name, &opt, chars.collect(), &mut args);
+                            warn_days = Some(parse_days(&prg_name, &opt, &v));
+                            break;
+                        }
+                        v => {
+                            eprintln!("{}: Unrecognized option -{}", prg_name, v);
+                            std::process::exit(2)
+                        }
+                    }
+                }
+            }
+            x => login_name = Some(x.to_string()),
+        }
+    }
+
+    let modify = last_day.is_some()
+        || expire_date.is_some()
+        || inactive.is_some()
+        || min_days.is_some()
+        || max_days.is_some()
+        || warn_days.is_some();
+
+    if list_only && modify {
+        eprintln!(
+            "{}: Cannot list and change aging information simultaneously",
+            prg_name
+        );
+        std::process::exit(2)
+    }
+
+    if !list_only && !modify {
+        print_help(&prg_name);
+        std::process::exit(2)
+    }
+
+    let login_name = match login_name {
+        Some(n) => n,
+        None => {
+            eprintln!("{}: Missing LOGIN", prg_name);
+            std::process::exit(2)
+        }
+    };
+
+    if (modify || chroot.is_some()) && unsafe { libc::getuid() } != 0 {
+        eprintln!("{}: Permission Denied", prg_name);
+        std::process::exit(1)
+    }
+
+    let handle = match chroot {
+        Some(chroot) => UserHandle::from_name_in(&*login_name, PathBuf::from(chroot)),
+        None => UserHandle::from_name(&*login_name),
+    };
+    let handle = match handle {
+        Ok(hdl) => hdl,
+        Err(Error::NoSuchUser) => {
+            eprintln!("{}: No such user {}", prg_name, login_name);
+            std::process::exit(1)
+        }
+        Err(e) => {
+            eprintln!("{}: {}", prg_name, e);
+            std::process::exit(3)
+        }
+    };
+
+    if list_only {
+        // Users may list their own account
+        if unsafe { libc::getuid() } != 0 {
+            match handle.uid() {
+                Ok(uid) if uid == unsafe { libc::getuid() } => {}
+                Ok(_) => {
+                    eprintln!("{}: Permission Denied", prg_name);
+                    std::process::exit(1)
+                }
+                Err(e) => {
+                    eprintln!("{}: {}", prg_name, e);
+                    std::process::exit(3)
+                }
+            }
+        }
+        if let Err(e) = handle.aging().and_then(|aging| list(&handle, &aging)) {
+            eprintln!("{}: {}", prg_name, e);
+            std::process::exit(3)
+        }
+        std::process::exit(0)
+    }
+
+    if let Some(day) = last_day {
+        // chage takes over from any expiry set with passwd -e or -x
+        let header = if day == Some(0) {
+            handle.expire_password(None)
+        } else {
+            handle.unexpire_password()
+        };
+        match header {
+            Ok(()) => {}
+            Err(Error::Io(e)) if e.kind() == ErrorKind::NotFound => {}
+            Err(e) => {
+                eprintln!("{}: Failed to update password expiry, {}", prg_name, e);
+                std::process::exit(3)
+            }
+        }
+    }
+
+    let result = handle.update_aging(|aging| {
+        if let Some(day) = last_day {
+            aging.last_change = day;
+        }
+        if let Some(day) = expire_date {
+            aging.expire_date = day;
+        }
+        if let Some(days) = inactive {
+            aging.inactive_days = days;
+        }
+        if let Some(days) = min_days {
+            aging.min_days = days;
+        }
+        if let Some(days) = max_days {
+            aging.max_days = days;
+        }
+        if let Some(days) = warn_days {
+            aging.warn_days = days;
+        }
+    });
+
+    match result {
+        Ok(()) => {}
+        Err(Error::Busy) => {
+            eprintln!("{}: Aging File busy, please retry", prg_name);
+            std::process::exit(3)
+        }
+        Err(e) => {
+            eprintln!("{}: Failed to change aging information, {}", prg_name, e);
+            std::process::exit(3)
+        }
+    }
+}
@@ -0,0 +1,143 @@
+use std::{io::BufRead, path::PathBuf};
+
+use lc_login::{
+    password::{PasswordFile, PasswordRecord},
+    users::UserHandle,
+    Error,
+};
+use zeroize::Zeroizing;
+
+fn print_help(prg_name: &str) {
+    println!("Usage: {} [options]", prg_name);
+    println!("Reads lines of the form user:password from standard input, and sets the password of each user");
+    println!("Options:");
+    println!("\t-e, --encrypted: The passwords are already encoded, either as a base64 password file, or as a crypt(3) string starting with $");
+    println!("\t-h, --help: Print this message and exit");
+    println!("\t-R, --root: Make changes within the given sysroot");
+}
+
+/// Decodes an encoded password given with `-e`
+fn decode(encoded: &str) -> lc_login::Result<PasswordFile> {
+    if encoded.starts_with('$') {
+        Ok(PasswordFile::Enabled(PasswordRecord::crypt(encoded)?))
+    } else {
+        let bytes = Zeroizing::new(
+            openssl::base64::decode_block(encoded)
+                .map_err(|_| Error::Corrupt("Invalid base64 password file"))?,
+        );
+        PasswordFile::parse(&bytes)
+    }
+}
+
+fn apply(line: &str, encrypted: bool, chroot: Option<&str>) -> Result<(), String> {
+    let (name, passwd) = line
+        .split_once(':')
+        .ok_or_else(|| "missing ':' separator".to_string())?;
+    let handle = match chroot {
+        Some(chroot) => UserHandle::from_name_in(name, PathBuf::from(chroot)),
+        None => UserHandle::from_name(name),
+    }
+    .map_err(|e| match e {
+        Error::NoSuchUser => format!("user '{}' does not exist", name),
+        e => format!("{}: {}", name, e),
+    })?;
+    let result = if encrypted {
+        decode(passwd).and_then(|file| handle.set_password_file(file))
+    } else {
+        handle.set_password(passwd)
+    };
+    result.map_err(|e| format!("failed to set the password of '{}': {}", name, e))
+}
+
+fn operand(prg_name: &str, opt: &str, rest: String, args: &mut std::env::Args) -> String {
+    if !rest.is_empty() {
+        return rest;
+    }
+    match args.next() {
+        Some(s) => s,
+        None => {
+            eprintln!("{}: Missing operand for {}", prg_name, opt);
+            std::process::exit(2)
+        }
+    }
+}
+
+pub fn main() {
+    let mut args = std::env::args();
+    let prg_name = args.next().unwrap();
+    let mut encrypted = false;
+    let mut chroot = None;
+
+    while let Some(s) = args.next() {
+        match &*s {
+            "--help" => {
+                print_help(&prg_name);
+                std::process::exit(0)
+            }
+            "--encrypted" => encrypted = true,
+            "--root" => chroot = Some(operand(&prg_name, &s, String::new(), &mut args)),
+            x if x.starts_with("--") => {
+                eprintln!("{}: Unrecognized option {}", prg_name, x);
+                std::process::exit(2)
+            }
+            x if x.starts_with('-') && x.len() > 1 => {
+                let mut chars = x[1..].chars();
+                while let Some(c) = chars.next() {
+                    match c {
+                        'e' => encrypted = true,
+                        'h' => {
+                            print_help(&prg_name);
+                            std::process::exit(0)
+                        }
+                        'R' => {
+                            chroot = Some(operand(&prg_name, "-R", chars.collect(), &mut args));
+                            break;
+                        }
+                        v => {
+                            eprintln!("{}: Unrecognized option -{}", prg_name, v);
+                            std::process::exit(2)
+                        }
+                    }
+                }
+            }
+            x => {
+                eprintln!("{}: Unexpected argument {}", prg_name, x);
+                std::process::exit(2)
+            }
+        }
+    }
+
+    if unsafe { libc::getuid() } != 0 {
+        eprintln!("{}: Permission Denied", prg_name);
+        std::process::exit(1)
+    }
+
+    let stdin = std::io::stdin();
+    let mut stdin = stdin.lock();
+    let mut failed = false;
+    let mut lineno = 0;
+    loop {
+        let mut line = Zeroizing::new(String::new());
+        match stdin.read_line(&mut line) {
+            Ok(0) => break,
+            Ok(_) => {}
+            Err(e) => {
+                eprintln!("{}: Failed to read input, {}", prg_name, e);
+                std::process::exit(1)
+            }
+        }
+        lineno += 1;
+        let line = line.trim_end_matches(['\n', '\r']);
+        if line.is_empty() {
+            continue;
+        }
+        if let Err(e) = apply(line, encrypted, chroot.as_deref()) {
+            eprintln!("{}: line {}: {}", prg_name, lineno, e);
+            failed = true;
+        }
+    }
+
+    if failed {
+        std::process::exit(1)
+    }
+}
@@ -0,0 +1,1111 @@
+//! Conversion between the traditional passwd(5), shadow(5), group(5) and gshadow(5) files and the user and group directories.
+//!
+//! Each user becomes a directory named by its uid in [`crate::dirs::USERS`], next to a symlink from the user name to that directory.
+//! The directory holds `name`, `uid`, `home`, `shell` and `group` symlinks, a `groups` file listing the supplementary gids, a `gecos` file holding the GECOS field, and the `password` and `aging` files derived from the shadow entry.
+//! Groups become the same kind of directory in [`crate::dirs::GROUPS`], with `name` and `gid` symlinks, a `members` file listing the uids of the users that have the group as a supplementary group, and the `admins` and `password` files derived from the gshadow entry.
+//!
+//! The files can also be generated from the directories, for software that reads them directly, such as getpwnam(3).
+
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    fmt,
+    io::ErrorKind,
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    aging::Aging,
+    password::{algorithms, crypt, PasswordFile, PasswordRecord},
+    users::is_valid_name,
+    Error,
+};
+
+/// An entry of passwd(5): `name:password:uid:gid:gecos:home:shell`
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PasswdEntry {
+    pub name: String,
+    /// The password, or `x` if it is kept in shadow(5)
+    pub password: String,
+    pub uid: u32,
+    pub gid: u32,
+    pub gecos: String,
+    pub home: PathBuf,
+    pub shell: PathBuf,
+}
+
+/// An entry of shadow(5): `name:password:` followed by the [`Aging`] fields and a reserved field
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ShadowEntry {
+    pub name: String,
+    pub password: String,
+    pub aging: Aging,
+}
+
+/// An entry of group(5): `name:password:gid:members`
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct GroupEntry {
+    pub name: String,
+    /// The password, or `x` if it is kept in gshadow(5)
+    pub password: String,
+    pub gid: u32,
+    pub members: Vec<String>,
+}
+
+/// An entry of gshadow(5): `name:password:admins:members`
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct GshadowEntry {
+    pub name: String,
+    pub password: String,
+    pub admins: Vec<String>,
+    pub members: Vec<String>,
+}
+
+fn split_list(field: &str) -> Vec<String> {
+    field
+        .split(',')
+        .filter(|s| !s.is_empty())
+        .map(str::to_string)
+        .collect()
+}
+
+fn parse_id(field: &str, err: &'static str) -> crate::Result<u32> {
+    field.parse().map_err(|_| Error::Corrupt(err))
+}
+
+impl PasswdEntry {
+    pub fn parse(line: &str) -> crate::Result<Self> {
+        const ERR: &str = "Invalid passwd entry";
+        match line.split(':').collect::<Vec<_>>()[..] {
+            [name, password, uid, gid, gecos, home, shell] if !name.is_empty() => Ok(Self {
+                name: name.to_string(),
+                password: password.to_string(),
+                uid: parse_id(uid, ERR)?,
+                gid: parse_id(gid, ERR)?,
+                gecos: gecos.to_string(),
+                home: PathBuf::from(home),
+                shell: PathBuf::from(shell),
+            }),
+            _ => Err(Error::Corrupt(ERR)),
+        }
+    }
+}
+
+impl fmt::Display for PasswdEntry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}:{}:{}:{}:{}:{}:{}",
+            self.name,
+            self.password,
+            self.uid,
+            self.gid,
+            self.gecos,
+            self.home.display(),
+            self.shell.display()
+        )
+    }
+}
+
+impl ShadowEntry {
+    pub fn parse(line: &str) -> crate::Result<Self> {
+        match line.split(':').collect::<Vec<_>>()[..] {
+            [name, password, ref aging @ .., _reserved] if !name.is_empty() && aging.len() == 6 => {
+                Ok(Self {
+                    name: name.to_string(),
+                    password: password.to_string(),
+                    aging: Aging::parse(&aging.join(":"))?,
+                })
+            }
+            _ => Err(Error::Corrupt("Invalid shadow entry")),
+        }
+    }
+}
+
+impl fmt::Display for ShadowEntry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}:{}:", self.name, self.password, self.aging)
+    }
+}
+
+impl GroupEntry {
+    pub fn parse(line: &str) -> crate::Result<Self> {
+        const ERR: &str = "Invalid group entry";
+        match line.split(':').collect::<Vec<_>>()[..] {
+            [name, password, gid, members] if !name.is_empty() => Ok(Self {
+                name: name.to_string(),
+                password: password.to_string(),
+                gid: parse_id(gid, ERR)?,
+                members: split_list(members),
+            }),
+            _ => Err(Error::Corrupt(ERR)),
+        }
+    }
+}
+
+impl fmt::Display for GroupEntry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}:{}:{}:{}",
+            self.name,
+            self.password,
+            self.gid,
+            self.members.join(",")
+        )
+    }
+}
+
+impl GshadowEntry {
+    pub fn parse(line: &str) -> crate::Result<Self> {
+        match line.split(':').collect::<Vec<_>>()[..] {
+            [name, password, admins, members] if !name.is_empty() => Ok(Self {
+                name: name.to_string(),
+                password: password.to_string(),
+                admins: split_list(admins),
+                members: split_list(members),
+            }),
+            _ => Err(Error::Corrupt("Invalid gshadow entry")),
+        }
+    }
+}
+
+impl fmt::Display for GshadowEntry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}:{}:{}:{}",
+            self.name,
+            self.password,
+            self.admins.join(","),
+            self.members.join(",")
+        )
+    }
+}
+
+/// Reads the entries of the file at `path`, skipping blank lines. A missing file has no entries
+fn read_entries<T>(path: &Path, parse: fn(&str) -> crate::Result<T>) -> crate::Result<Vec<T>> {
+    match std::fs::read_to_string(path) {
+        Ok(s) => s
+            .lines()
+            .filter(|line| !line.trim().is_empty())
+            .map(parse)
+            .collect(),
+        Err(e) if e.kind() == ErrorKind::NotFound => Ok(Vec::new()),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Converts a password field of shadow(5) or passwd(5).
+///
+/// An empty field means no password is needed, so there is no password file.
+/// Supported crypt(3) strings are carried over as [`algorithms::CRYPT`] records, and are disabled if they are prefixed with `!`.
+/// Any other value, like `*` or `!!`, can never match, and becomes a disabled record that keeps the field as-is.
+pub fn password_from_field(field: &str) -> Option<PasswordFile> {
+    if field.is_empty() {
+        return None;
+    }
+    let unlocked = field.trim_start_matches('!');
+    match PasswordRecord::crypt(unlocked) {
+        Ok(record) if unlocked.len() == field.len() => Some(PasswordFile::Enabled(record)),
+        Ok(record) if unlocked.len() + 1 == field.len() => Some(PasswordFile::Disabled(record)),
+        _ => Some(PasswordFile::Disabled(PasswordRecord {
+            version: crate::password::CURRENT_VERSION,
+            algorithm: algorithms::CRYPT,
+            salt_and_repetition: 0,
+            params: None,
+            expiry: None,
+            salt: Vec::new(),
+            hash: field.as_bytes().to_vec(),
+        })),
+    }
+}
+
+/// The inverse of [`password_from_field`].
+///
+/// Passwords hashed by lc-login itself cannot be expressed in crypt(3) form, so they are exported as `*`, or `!*` if they are disabled.
+pub fn field_from_password(file: Option<&PasswordFile>) -> String {
+    let (record, disabled) = match file {
+        None => return String::new(),
+        Some(PasswordFile::Enabled(record)) => (record, false),
+        Some(PasswordFile::Disabled(record)) => (record, true),
+    };
+    if record.algorithm != algorithms::CRYPT {
+        return if disabled { "!*" } else { "*" }.to_string();
+    }
+    let hash = String::from_utf8_lossy(&record.hash);
+    if !crypt::is_supported(&hash) {
+        // Kept as-is by password_from_field
+        hash.into_owned()
+    } else if disabled {
+        format!("!{}", hash)
+    } else {
+        hash.into_owned()
+    }
+}
+
+/// Writes the password file at `path` from `field`, unless the current file already exports as `field`.
+///
+/// This keeps passwords hashed by lc-login, which were exported as `*` or `!*`, from being replaced on a re-import.
+fn import_password(path: PathBuf, field: &str) -> Option<Change> {
+    let file = password_from_field(field)?;
+    match PasswordFile::open(&path) {
+        Ok(current) if field_from_password(Some(&current)) == field => None,
+        _ => Some(Change::WriteFile {
+            path,
+            mode: 0o600,
+            contents: file.to_bytes(),
+        }),
+    }
+}
+
+/// A single step of an import or export
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Change {
+    CreateDir(PathBuf),
+    /// Creates or replaces the symlink at `link`
+    Symlink {
+        link: PathBuf,
+        target: PathBuf,
+    },
+    /// Creates or replaces the file at `path`
+    WriteFile {
+        path: PathBuf,
+        mode: u32,
+        contents: Vec<u8>,
+    },
+}
+
+impl fmt::Display for Change {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::CreateDir(path) => write!(f, "create directory {}", path.display()),
+            Self::Symlink { link, target } => {
+                write!(f, "link {} -> {}", link.display(), target.display())
+            }
+            // Password files are not printed, as they contain hashes
+            Self::WriteFile { path, mode, .. } => {
+                write!(f, "write {} (mode {:o})", path.display(), mode)
+            }
+        }
+    }
+}
+
+impl Change {
+    /// Checks whether the change would leave the filesystem as it is
+    fn is_noop(&self) -> crate::Result<bool> {
+        let current = match self {
+            Self::CreateDir(path) => return Ok(path.is_dir()),
+            Self::Symlink { link, target } => std::fs::read_link(link).map(|t| t == *target),
+            Self::WriteFile { path, contents, .. } => std::fs::read(path).map(|c| c == *contents),
+        };
+        match current {
+            Ok(same) => Ok(same),
+            Err(e) if e.kind() == ErrorKind::NotFound || e.kind() == ErrorKind::InvalidInput => {
+                Ok(false)
+            }
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    pub fn apply(&self) -> crate::Result<()> {
+        match self {
+            Self::CreateDir(path) => Ok(std::fs::create_dir_all(path)?),
+            Self::Symlink { link, target } => crate::atomic::replace_symlink(target, link),
+            Self::WriteFile {
+                path,
+                mode,
+                contents,
+            } => crate::atomic::update(path, *mode, |_| Ok(Some(contents.clone()))),
+        }
+    }
+}
+
+/// The result of [`Database::plan_import`] or [`Database::plan_export`]
+#[derive(Clone, Debug, Default)]
+pub struct Plan {
+    /// The changes, in the order they must be applied
+    pub changes: Vec<Change>,
+    /// Entries that were skipped, and why
+    pub warnings: Vec<String>,
+}
+
+impl Plan {
+    pub fn apply(&self) -> crate::Result<()> {
+        self.changes.iter().try_for_each(Change::apply)
+    }
+}
+
+/// The contents of the passwd(5), shadow(5), group(5) and gshadow(5) files
+#[derive(Clone, Debug, Default)]
+pub struct Database {
+    pub passwd: Vec<PasswdEntry>,
+    pub shadow: Vec<ShadowEntry>,
+    pub group: Vec<GroupEntry>,
+    pub gshadow: Vec<GshadowEntry>,
+}
+
+/// Resolves the absolute `path` within `chroot`
+fn in_root(chroot: Option<&Path>, path: &Path) -> PathBuf {
+    match chroot {
+        Some(chroot) => chroot.join(path.strip_prefix("/").unwrap_or(path)),
+        None => path.to_path_buf(),
+    }
+}
+
+impl Database {
+    /// Reads the files at their configured paths. Missing files are treated as empty
+    pub fn read() -> crate::Result<Self> {
+        Self::read_from(None)
+    }
+
+    /// Reads the files at their configured paths within `chroot`
+    pub fn read_in<P: AsRef<Path>>(chroot: P) -> crate::Result<Self> {
+        Self::read_from(Some(chroot.as_ref()))
+    }
+
+    fn read_from(chroot: Option<&Path>) -> crate::Result<Self> {
+        Ok(Self {
+            passwd: read_entries(&in_root(chroot, &crate::dirs::PASSWD), PasswdEntry::parse)?,
+            shadow: read_entries(&in_root(chroot, &crate::dirs::SHADOW), ShadowEntry::parse)?,
+            group: read_entries(&in_root(chroot, &crate::dirs::GROUP), GroupEntry::parse)?,
+            gshadow: read_entries(&in_root(chroot, &crate::dirs::GSHADOW), GshadowEntry::parse)?,
+        })
+    }
+
+    /// Works out the changes needed to bring the user and group directories within `chroot` in line with the files.
+    ///
+    /// Existing directories are updated rather than replaced, and anything already up to date is left out of the plan, so importing the same files twice makes no changes.
+    /// Entries that reuse a name or id already seen, or whose name is not [`is_valid_name`], are skipped with a warning, as are group members that are not users.
+    pub fn plan_import(&self, chroot: Option<&Path>) -> crate::Result<Plan> {
+        let users = in_root(chroot, &crate::dirs::USERS);
+        let groups = in_root(chroot, &crate::dirs::GROUPS);
+        let mut plan = Plan::default();
+        let mut changes = Vec::new();
+
+        let mut gids = HashSet::new();
+        let mut group_names = HashSet::new();
+        let mut group_entries = Vec::new();
+        for entry in &self.group {
+            if !is_valid_name(&entry.name) {
+                plan.warnings.push(format!(
+                    "group {} ({}): invalid name, skipped",
+                    entry.name, entry.gid
+                ));
+                continue;
+            }
+            if !gids.insert(entry.gid) || !group_names.insert(&*entry.name) {
+                plan.warnings.push(format!(
+                    "group {} ({}): duplicate name or gid, skipped",
+                    entry.name, entry.gid
+                ));
+                continue;
+            }
+            group_entries.push(entry);
+            let dir = groups.join(entry.gid.to_string());
+            changes.push(Change::CreateDir(dir.clone()));
+            changes.push(Change::Symlink {
+                link: dir.join("gid"),
+                target: PathBuf::from(format!("../{}", entry.gid)),
+            });
+            changes.push(Change::Symlink {
+                link: dir.join("name"),
+                target: PathBuf::from(format!("../{}", entry.name)),
+            });
+            changes.push(Change::Symlink {
+                link: groups.join(&entry.name),
+                target: PathBuf::from(entry.gid.to_string()),
+            });
+        }
+
+        let shadow = self
+            .shadow
+            .iter()
+            .map(|s| (&*s.name, s))
+            .collect::<HashMap<_, _>>();
+        let passwd = self
+            .passwd
+            .iter()
+            .filter(|p| is_valid_name(&p.name))
+            .map(|p| (&*p.name, p))
+            .collect::<HashMap<_, _>>();
+
+        // Supplementary groups come from the member lists of both group and gshadow
+        let mut memberships = BTreeMap::<&str, Vec<u32>>::new();
+        let gshadow = self
+            .gshadow
+            .iter()
+            .map(|g| (&*g.name, g))
+            .collect::<HashMap<_, _>>();
+        for entry in &group_entries {
+            let extra = gshadow
+                .get(&*entry.name)
+                .map_or(&[][..], |g| &g.members[..]);
+            for member in entry.members.iter().chain(extra) {
+                if passwd.contains_key(&**member) {
+                    memberships.entry(member).or_default().push(entry.gid);
+                } else {
+                    plan.warnings.push(format!(
+                        "group {}: member {} is not a user, skipped",
+                        entry.name, member
+                    ));
+                }
+            }
+        }
+
+        let mut members = BTreeMap::<u32, Vec<u32>>::new();
+        let mut imported_uids = HashMap::new();
+        let mut uids = HashSet::new();
+        let mut user_names = HashSet::new();
+        for entry in &self.passwd {
+            if !is_valid_name(&entry.name) {
+                plan.warnings.push(format!(
+                    "user {} ({}): invalid name, skipped",
+                    entry.name, entry.uid
+                ));
+                continue;
+            }
+            if !uids.insert(entry.uid) || !user_names.insert(&*entry.name) {
+                plan.warnings.push(format!(
+                    "user {} ({}): duplicate name or uid, skipped",
+                    entry.name, entry.uid
+                ));
+                continue;
+            }
+            imported_uids.insert(&*entry.name, entry.uid);
+            let dir = users.join(entry.uid.to_string());
+            changes.push(Change::CreateDir(dir.clone()));
+            changes.push(Change::Symlink {
+                link: dir.join("uid"),
+                target: PathBuf::from(format!("../{}", entry.uid)),
+            });
+            changes.push(Change::Symlink {
+                link: dir.join("name"),
+                target: PathBuf::from(format!("../{}", entry.name)),
+            });
+            changes.push(Change::Symlink {
+                link: users.join(&entry.name),
+                target: PathBuf::from(entry.uid.to_string()),
+            });
+            if !entry.home.as_os_str().is_empty() {
+                changes.push(Change::Symlink {
+                    link: dir.join("home"),
+                    target: entry.home.clone(),
+                });
+            }
+            if !entry.shell.as_os_str().is_empty() {
+                changes.push(Change::Symlink {
+                    link: dir.join("shell"),
+                    target: entry.shell.clone(),
+                });
+            }
+            changes.push(Change::Symlink {
+                link: dir.join("group"),
+                target: crate::dirs::GROUPS.join(entry.gid.to_string()),
+            });
+            // Like the member lists, an empty field only needs writing over an existing file
+            if !entry.gecos.is_empty() || dir.join("gecos").exists() {
+                changes.push(Change::WriteFile {
+                    path: dir.join("gecos"),
+                    mode: 0o644,
+                    contents: format!("{}\n", entry.gecos).into_bytes(),
+                });
+            }
+            let mut secondary = memberships.remove(&*entry.name).unwrap_or_default();
+            for gid in &secondary {
+                members.entry(*gid).or_default().push(entry.uid);
+            }
+            changes.push(Change::WriteFile {
+                path: dir.join("groups"),
+                mode: 0o644,
+                contents: crate::tree::format_ids(&mut secondary),
+            });
+
+            let shadow = shadow.get(&*entry.name);
+            let field = match shadow {
+                Some(shadow) if entry.password == "x" => &shadow.password,
+                _ => &entry.password,
+            };
+            changes.extend(import_password(dir.join("password"), field));
+            if let Some(shadow) = shadow {
+                if shadow.aging != Aging::default() {
+                    changes.push(Change::WriteFile {
+                        path: dir.join("aging"),
+                        mode: 0o600,
+                        contents: format!("{}\n", shadow.aging).into_bytes(),
+                    });
+                }
+            }
+        }
+
+        for entry in group_entries {
+            let dir = groups.join(entry.gid.to_string());
+            let mut uids = members.remove(&entry.gid).unwrap_or_default();
+            // A missing list is an empty one, so only an existing list needs emptying
+            if !uids.is_empty() || dir.join("members").exists() {
+                changes.push(Change::WriteFile {
+                    path: dir.join("members"),
+                    mode: 0o644,
+                    contents: crate::tree::format_ids(&mut uids),
+                });
+            }
+
+            let gshadow = gshadow.get(&*entry.name);
+            let mut admins = Vec::new();
+            for admin in gshadow.map_or(&[][..], |g| &g.admins[..]) {
+                match imported_uids.get(&**admin) {
+                    Some(uid) => admins.push(*uid),
+                    None => plan.warnings.push(format!(
+                        "group {}: administrator {} is not a user, skipped",
+                        entry.name, admin
+                    )),
+                }
+            }
+            if !admins.is_empty() || dir.join("admins").exists() {
+                changes.push(Change::WriteFile {
+                    path: dir.join("admins"),
+                    mode: 0o644,
+                    contents: crate::tree::format_ids(&mut admins),
+                });
+            }
+
+            let field = match gshadow {
+                Some(gshadow) if entry.password == "x" => &gshadow.password,
+                _ => &entry.password,
+            };
+            // A group without a password is only open to its members, which is what `!` means in gshadow(5)
+            if field != "!" {
+                changes.extend(import_password(dir.join("password"), field));
+            }
+        }
+
+        for change in changes {
+            if !change.is_noop()? {
+                plan.changes.push(change);
+            }
+        }
+        Ok(plan)
+    }
+
+    /// Builds the files from the user and group directories within `chroot`.
+    ///
+    /// Entries that cannot be read, or lack a name, are skipped, and returned with the reason.
+    /// Group members and administrators are taken from the `members` and `admins` files of each group. Groups without a password get `!` in gshadow(5).
+    pub fn from_dirs(chroot: Option<&Path>) -> crate::Result<(Self, Vec<String>)> {
+        let mut db = Self::default();
+        let mut warnings = Vec::new();
+        let mut names = BTreeMap::new();
+
+        let users = match chroot {
+            Some(chroot) => crate::users::iter_in(chroot)?.collect::<Vec<_>>(),
+            None => crate::users::iter()?.collect(),
+        };
+        for handle in users {
+            let handle = match handle {
+                Ok(handle) => handle,
+                Err(e) => {
+                    warnings.push(format!("users: {}, skipped", e));
+                    continue;
+                }
+            };
+            let user = (|| {
+                let uid = handle.uid()?;
+                let name = handle
+                    .name()?
+                    .ok_or(Error::Corrupt("Missing name symlink"))?;
+                let gid = handle.primary_group()?;
+                let password = match handle.password_file() {
+                    Ok(file) => Some(file),
+                    Err(Error::Io(e)) if e.kind() == ErrorKind::NotFound => None,
+                    Err(e) => return Err(e),
+                };
+                let passwd = PasswdEntry {
+                    name: name.clone(),
+                    password: "x".to_string(),
+                    uid,
+                    gid,
+                    gecos: handle.gecos()?.unwrap_or_default(),
+                    home: handle.home()?.unwrap_or_else(|| PathBuf::from("/")),
+                    shell: handle.shell()?.unwrap_or_default(),
+                };
+                let shadow = ShadowEntry {
+                    name,
+                    password: field_from_password(password.as_ref()),
+                    aging: handle.aging()?,
+                };
+                Ok((passwd, shadow))
+            })();
+            match user {
+                Ok((passwd, shadow)) => {
+                    names.insert(passwd.uid, passwd.name.clone());
+                    db.passwd.push(passwd);
+                    db.shadow.push(shadow);
+                }
+                Err(e) => warnings.push(format!(
+                    "user {}: {}, skipped",
+                    handle.user_dir().display(),
+                    e
+                )),
+            }
+        }
+
+        let groups = match chroot {
+            Some(chroot) => crate::groups::iter_in(chroot)?.collect::<Vec<_>>(),
+            None => crate::groups::iter()?.collect(),
+        };
+        for handle in groups {
+            let handle = match handle {
+                Ok(handle) => handle,
+                Err(e) => {
+                    warnings.push(format!("groups: {}, skipped", e));
+                    continue;
+                }
+            };
+            let group = handle.gid().and_then(|gid| {
+                let name = handle
+                    .name()?
+                    .ok_or(Error::Corrupt("Missing name symlink"))?;
+                let password = match handle.password_file() {
+                    Ok(file) => Some(file),
+                    Err(Error::Io(e)) if e.kind() == ErrorKind::NotFound => None,
+                    Err(e) => return Err(e),
+                };
+                Ok((gid, name, handle.members()?, handle.admins()?, password))
+            });
+            let (gid, name, uids, admin_uids, password) = match group {
+                Ok(group) => group,
+                Err(e) => {
+                    warnings.push(format!(
+                        "group {}: {}, skipped",
+                        handle.user_dir().display(),
+                        e
+                    ));
+                    continue;
+                }
+            };
+            let mut members = Vec::new();
+            for uid in uids {
+                match names.get(&uid) {
+                    Some(member) => members.push(member.clone()),
+                    None => warnings.push(format!(
+                        "group {}: member {} is not a user, skipped",
+                        name, uid
+                    )),
+                }
+            }
+            db.group.push(GroupEntry {
+                name: name.clone(),
+                password: "x".to_string(),
+                gid,
+                members: members.clone(),
+            });
+            let mut admins = Vec::new();
+            for uid in admin_uids {
+                match names.get(&uid) {
+                    Some(admin) => admins.push(admin.clone()),
+                    None => warnings.push(format!(
+                        "group {}: administrator {} is not a user, skipped",
+                        name, uid
+                    )),
+                }
+            }
+            db.gshadow.push(GshadowEntry {
+                name,
+                password: match password {
+                    Some(file) => field_from_password(Some(&file)),
+                    None => "!".to_string(),
+                },
+                admins,
+                members,
+            });
+        }
+
+        Ok((db, warnings))
+    }
+
+    /// Works out the changes needed to bring the files within `chroot` in line with this database.
+    ///
+    /// Each file is replaced atomically, and only if its contents change, so exporting is idempotent.
+    /// shadow(5) and gshadow(5) are only written if `shadow` is set.
+    pub fn plan_export(&self, chroot: Option<&Path>, shadow: bool) -> crate::Result<Plan> {
+        fn contents<T: fmt::Display>(entries: &[T]) -> Vec<u8> {
+            entries
+                .iter()
+                .map(|e| format!("{}\n", e))
+                .collect::<String>()
+                .into_bytes()
+        }
+
+        let mut changes = vec![
+            Change::WriteFile {
+                path: in_root(chroot, &crate::dirs::PASSWD),
+                mode: 0o644,
+                contents: contents(&self.passwd),
+            },
+            Change::WriteFile {
+                path: in_root(chroot, &crate::dirs::GROUP),
+                mode: 0o644,
+                contents: contents(&self.group),
+            },
+        ];
+        if shadow {
+            changes.push(Change::WriteFile {
+                path: in_root(chroot, &crate::dirs::SHADOW),
+                mode: 0o600,
+                contents: contents(&self.shadow),
+            });
+            changes.push(Change::WriteFile {
+                path: in_root(chroot, &crate::dirs::GSHADOW),
+                mode: 0o600,
+                contents: contents(&self.gshadow),
+            });
+        }
+
+        let mut plan = Plan::default();
+        for change in changes {
+            if !change.is_noop()? {
+                plan.changes.push(change);
+            }
+        }
+        Ok(plan)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::os::unix::fs::PermissionsExt;
+
+    use super::*;
+    use crate::users::UserHandle;
+
+    fn files(root: &Path) -> [PathBuf; 4] {
+        [
+            in_root(Some(root), &crate::dirs::PASSWD),
+            in_root(Some(root), &crate::dirs::SHADOW),
+            in_root(Some(root), &crate::dirs::GROUP),
+            in_root(Some(root), &crate::dirs::GSHADOW),
+        ]
+    }
+
+    fn database() -> Database {
+        Database {
+            passwd: vec![
+                PasswdEntry::parse("alice:x:1000:1000:Alice:/home/alice:/bin/sh").unwrap(),
+            ],
+            shadow: vec![ShadowEntry::parse("alice:!:19000:0:99999:7:::").unwrap()],
+            group: vec![GroupEntry::parse("alice:x:1000:").unwrap()],
+            gshadow: vec![GshadowEntry::parse("alice:!::").unwrap()],
+        }
+    }
+
+    #[test]
+    fn export_leaves_backups_alone() {
+        let root = tempfile::tempdir().unwrap();
+        let files = files(root.path());
+        for path in &files {
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            let mut backup = path.clone();
+            backup.as_mut_os_string().push("-");
+            std::fs::write(backup, "backup:x:0:0::/:\n").unwrap();
+        }
+        let db = database();
+        db.plan_export(Some(root.path()), true)
+            .unwrap()
+            .apply()
+            .unwrap();
+        for path in &files {
+            let mut backup = path.clone();
+            backup.as_mut_os_string().push("-");
+            assert_eq!(std::fs::read(backup).unwrap(), b"backup:x:0:0::/:\n");
+        }
+        assert_eq!(
+            std::fs::read_to_string(&files[0]).unwrap(),
+            "alice:x:1000:1000:Alice:/home/alice:/bin/sh\n"
+        );
+        // No lock or temporary files are left behind
+        let dir = files[0].parent().unwrap();
+        assert_eq!(std::fs::read_dir(dir).unwrap().count(), 8);
+        assert!(db
+            .plan_export(Some(root.path()), true)
+            .unwrap()
+            .changes
+            .is_empty());
+    }
+
+    /// Writes `contents` to the files at their configured paths within `root`
+    fn write_files(root: &Path, contents: [&str; 4]) {
+        for (path, contents) in files(root).iter().zip(contents) {
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(path, contents).unwrap();
+        }
+    }
+
+    #[test]
+    fn import_export_round_trip() {
+        let root = tempfile::tempdir().unwrap();
+        let passwd = "root:x:0:0:Super User,,,:/root:/bin/sh\n\
+                      alice:x:1000:1000:Alice Liddell,Room 1,,:/home/alice:/bin/sh\n\
+                      bob:x:1001:1000::/home/bob:/bin/bash\n";
+        let group = "root:x:0:\nusers:x:1000:bob\n";
+        write_files(
+            root.path(),
+            [
+                passwd,
+                "root:*:19000:0:99999:7:::\nalice:!:19000:0:99999:7:::\nbob::19000::::::\n",
+                group,
+                "root:!::\nusers:!:alice:bob\n",
+            ],
+        );
+        Database::read_in(root.path())
+            .unwrap()
+            .plan_import(Some(root.path()))
+            .unwrap()
+            .apply()
+            .unwrap();
+        assert_eq!(
+            UserHandle::from_name_in("alice", root.path())
+                .unwrap()
+                .gecos()
+                .unwrap()
+                .as_deref(),
+            Some("Alice Liddell,Room 1,,")
+        );
+        // Like shadow(5), aging data is only readable by root
+        let aging = UserHandle::from_name_in("alice", root.path())
+            .unwrap()
+            .user_dir()
+            .join("aging");
+        assert_eq!(
+            std::fs::metadata(aging).unwrap().permissions().mode() & 0o777,
+            0o600
+        );
+
+        let (db, warnings) = Database::from_dirs(Some(root.path())).unwrap();
+        assert!(warnings.is_empty(), "{:?}", warnings);
+        let plan = db.plan_export(Some(root.path()), false).unwrap();
+        assert!(plan.changes.is_empty(), "{:?}", plan.changes);
+        assert_eq!(
+            std::fs::read_to_string(&files(root.path())[0]).unwrap(),
+            passwd
+        );
+    }
+
+    #[test]
+    fn passwd_entries() {
+        let line = "alice:x:1000:100:Alice Liddell,,,:/home/alice:/bin/sh";
+        let entry = PasswdEntry::parse(line).unwrap();
+        assert_eq!(
+            entry,
+            PasswdEntry {
+                name: "alice".to_string(),
+                password: "x".to_string(),
+                uid: 1000,
+                gid: 100,
+                gecos: "Alice Liddell,,,".to_string(),
+                home: PathBuf::from("/home/alice"),
+                shell: PathBuf::from("/bin/sh"),
+            }
+        );
+        assert_eq!(entry.to_string(), line);
+        let entry = PasswdEntry::parse("nobody::65534:65534:::").unwrap();
+        assert_eq!(entry.to_string(), "nobody::65534:65534:::");
+        for bad in [
+            ":x:1000:100::/home/alice:/bin/sh",
+            "alice:x:1000:100::/home/alice",
+            "alice:x:1000:100::/home/alice:/bin/sh:extra",
+            "alice:x:-1:100::/home/alice:/bin/sh",
+            "alice:x:1000:users::/home/alice:/bin/sh",
+            "alice:x:4294967296:100::/home/alice:/bin/sh",
+        ] {
+            assert!(
+                matches!(PasswdEntry::parse(bad), Err(Error::Corrupt(_))),
+                "{:?} parsed",
+                bad
+            );
+        }
+    }
+
+    #[test]
+    fn shadow_entries() {
+        let line = "alice:$1$saltsalt$BsXyQbZiQujHkdhwPwdol.:19000:0:99999:7::20000:";
+        let entry = ShadowEntry::parse(line).unwrap();
+        assert_eq!(entry.name, "alice");
+        assert_eq!(entry.password, "$1$saltsalt$BsXyQbZiQujHkdhwPwdol.");
+        assert_eq!(
+            entry.aging,
+            Aging {
+                last_change: Some(19000),
+                min_days: Some(0),
+                max_days: Some(99999),
+                warn_days: Some(7),
+                inactive_days: None,
+                expire_date: Some(20000),
+            }
+        );
+        assert_eq!(entry.to_string(), line);
+        assert_eq!(
+            ShadowEntry::parse("bob:*:::::::").unwrap().to_string(),
+            "bob:*:::::::"
+        );
+        for bad in [
+            "alice:!:19000:0:99999:7::",
+            "alice:!:19000:0:99999:7:::::",
+            ":!:19000:0:99999:7:::",
+            "alice:!:soon:0:99999:7:::",
+        ] {
+            assert!(
+                matches!(ShadowEntry::parse(bad), Err(Error::Corrupt(_))),
+                "{:?} parsed",
+                bad
+            );
+        }
+    }
+
+    #[test]
+    fn group_entries() {
+        let line = "users:x:100:alice,bob";
+        let entry = GroupEntry::parse(line).unwrap();
+        assert_eq!(
+            entry,
+            GroupEntry {
+                name: "users".to_string(),
+                password: "x".to_string(),
+                gid: 100,
+                members: vec!["alice".to_string(), "bob".to_string()],
+            }
+        );
+        assert_eq!(entry.to_string(), line);
+        // Empty members are dropped
+        assert_eq!(
+            GroupEntry::parse("users:x:100:,alice,,").unwrap().members,
+            ["alice"]
+        );
+        assert!(GroupEntry::parse("users:x:100:")
+            .unwrap()
+            .members
+            .is_empty());
+        for bad in [
+            "users:x:100",
+            "users:x:100:a:b",
+            ":x:100:",
+            "users:x:staff:",
+        ] {
+            assert!(
+                matches!(GroupEntry::parse(bad), Err(Error::Corrupt(_))),
+                "{:?} parsed",
+                bad
+            );
+        }
+    }
+
+    #[test]
+    fn gshadow_entries() {
+        let line = "users:!:alice:alice,bob";
+        let entry = GshadowEntry::parse(line).unwrap();
+        assert_eq!(
+            entry,
+            GshadowEntry {
+                name: "users".to_string(),
+                password: "!".to_string(),
+                admins: vec!["alice".to_string()],
+                members: vec!["alice".to_string(), "bob".to_string()],
+            }
+        );
+        assert_eq!(entry.to_string(), line);
+        for bad in ["users:!:alice", "users:!:alice:bob:carol", ":!::"] {
+            assert!(
+                matches!(GshadowEntry::parse(bad), Err(Error::Corrupt(_))),
+                "{:?} parsed",
+                bad
+            );
+        }
+    }
+
+    #[test]
+    fn password_fields() {
+        let hash = "$1$saltsalt$BsXyQbZiQujHkdhwPwdol.";
+        assert_eq!(password_from_field(""), None);
+        assert!(matches!(
+            password_from_field(hash),
+            Some(PasswordFile::Enabled(_))
+        ));
+        assert!(matches!(
+            password_from_field(&format!("!{}", hash)),
+            Some(PasswordFile::Disabled(_))
+        ));
+        for field in ["", hash, &format!("!{}", hash), "*", "!!", "!"] {
+            assert_eq!(
+                field_from_password(password_from_field(field).as_ref()),
+                field
+            );
+        }
+    }
+
+    #[test]
+    fn second_import_changes_nothing() {
+        let root = tempfile::tempdir().unwrap();
+        write_files(
+            root.path(),
+            [
+                "root:x:0:0::/root:/bin/sh\nalice:x:1000:100:Alice:/home/alice:/bin/sh\n",
+                "root:*:19000::::::\nalice:$1$saltsalt$BsXyQbZiQujHkdhwPwdol.:19000:0:99999:7:::\n",
+                "root:x:0:\nusers:x:100:alice\nwheel:x:10:\n",
+                "root:!::\nusers:!:alice:alice\nwheel:!::alice\n",
+            ],
+        );
+        let db = Database::read_in(root.path()).unwrap();
+        let plan = db.plan_import(Some(root.path())).unwrap();
+        assert!(plan.warnings.is_empty(), "{:?}", plan.warnings);
+        assert!(!plan.changes.is_empty());
+        plan.apply().unwrap();
+        let plan = db.plan_import(Some(root.path())).unwrap();
+        assert!(plan.changes.is_empty(), "{:?}", plan.changes);
+
+        let alice = UserHandle::from_name_in("alice", root.path()).unwrap();
+        assert_eq!(alice.uid().unwrap(), 1000);
+        assert_eq!(alice.primary_group().unwrap(), 100);
+        let mut groups = alice.secondary_groups().unwrap();
+        groups.sort_unstable();
+        assert_eq!(groups, [10, 100]);
+        assert!(alice
+            .password_file()
+            .unwrap()
+            .record()
+            .verify("correct horse battery staple")
+            .unwrap());
+    }
+
+    #[test]
+    fn import_skips_invalid_names() {
+        let root = tempfile::tempdir().unwrap();
+        write_files(
+            root.path(),
+            [
+                "alice:x:1000:100::/home/alice:/bin/sh\n\
+                 -rf:x:1001:100::/:/bin/sh\n\
+                 1002:x:1002:100::/:/bin/sh\n\
+                 bad name:x:1003:100::/:/bin/sh\n\
+                 ..:x:1004:100::/:/bin/sh\n",
+                "",
+                "users:x:100:alice,-rf\nbad,group:x:101:\n",
+                "",
+            ],
+        );
+        let plan = Database::read_in(root.path())
+            .unwrap()
+            .plan_import(Some(root.path()))
+            .unwrap();
+        // Four users, one group and one member
+        assert_eq!(plan.warnings.len(), 6, "{:?}", plan.warnings);
+        plan.apply().unwrap();
+        let users = crate::users::iter_in(root.path())
+            .unwrap()
+            .map(|user| user.unwrap().name().unwrap().unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(users, ["alice"]);
+        let groups = crate::groups::iter_in(root.path())
+            .unwrap()
+            .map(|group| group.unwrap().name().unwrap().unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(groups, ["users"]);
+    }
+}
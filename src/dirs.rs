@@ -15,3 +15,23 @@ lazy_static! {
     pub static ref GROUPS: &'static Path =
         Path::new(std::option_env!("groups").unwrap_or("/etc/groups"));
 }
+
+lazy_static! {
+    pub static ref PASSWD: &'static Path =
+        Path::new(std::option_env!("passwd").unwrap_or("/etc/passwd"));
+}
+
+lazy_static! {
+    pub static ref SHADOW: &'static Path =
+        Path::new(std::option_env!("shadow").unwrap_or("/etc/shadow"));
+}
+
+lazy_static! {
+    pub static ref GROUP: &'static Path =
+        Path::new(std::option_env!("group").unwrap_or("/etc/group"));
+}
+
+lazy_static! {
+    pub static ref GSHADOW: &'static Path =
+        Path::new(std::option_env!("gshadow").unwrap_or("/etc/gshadow"));
+}
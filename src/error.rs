@@ -0,0 +1,88 @@
+use std::fmt;
+
+/// The errors returned by [`crate::users::UserHandle`], [`crate::groups::GroupHandle`] and [`crate::password`]
+#[derive(Debug)]
+pub enum Error {
+    /// The named user does not exist
+    NoSuchUser,
+    /// The named group does not exist
+    NoSuchGroup,
+    /// The supplied password does not match the stored one
+    WrongPassword,
+    /// Authentication is disabled for the account.
+    ///
+    /// Note that [`crate::users::UserHandle::authenticate`] reports this as [`crate::users::AuthOutcome::Locked`] instead
+    Locked,
+    /// The account has expired and can no longer be used
+    Expired,
+    /// A user with the requested name or uid already exists
+    UserExists,
+    /// A group with the requested name or gid already exists
+    GroupExists,
+    /// The group is still the primary group of a user
+    GroupInUse,
+    /// The name is not usable as a user or group name
+    InvalidName,
+    /// Every id in the configured range is taken, see [`crate::ids`]
+    NoFreeId,
+    /// A record (password file, symlink, or group list) could not be parsed
+    Corrupt(&'static str),
+    /// The password file is being updated by another process. The operation may be retried
+    Busy,
+    Io(std::io::Error),
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::NoSuchUser => f.write_str("No such user"),
+            Self::NoSuchGroup => f.write_str("No such group"),
+            Self::WrongPassword => f.write_str("Password is incorrect"),
+            Self::Locked => f.write_str("Account has authentication disabled"),
+            Self::Expired => f.write_str("Account has expired"),
+            Self::UserExists => f.write_str("User already exists"),
+            Self::GroupExists => f.write_str("Group already exists"),
+            Self::GroupInUse => f.write_str("Group is the primary group of a user"),
+            Self::InvalidName => f.write_str("Invalid name"),
+            Self::NoFreeId => f.write_str("No free id left in the configured range"),
+            Self::Corrupt(what) => write!(f, "Corrupt record: {}", what),
+            Self::Busy => f.write_str("Password File busy, please retry"),
+            Self::Io(e) => e.fmt(f),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<std::io::Error> for Error {
+    fn from(e: std::io::Error) -> Self {
+        Self::Io(e)
+    }
+}
+
+impl From<Error> for std::io::Error {
+    fn from(e: Error) -> Self {
+        match e {
+            Error::Io(e) => e,
+            Error::NoSuchUser | Error::NoSuchGroup => {
+                std::io::Error::new(std::io::ErrorKind::NotFound, e)
+            }
+            Error::Corrupt(_) => std::io::Error::new(std::io::ErrorKind::InvalidData, e),
+            Error::UserExists | Error::GroupExists => {
+                std::io::Error::new(std::io::ErrorKind::AlreadyExists, e)
+            }
+            Error::InvalidName => std::io::Error::new(std::io::ErrorKind::InvalidInput, e),
+            Error::Busy => std::io::Error::new(std::io::ErrorKind::AlreadyExists, e),
+            e => std::io::Error::other(e),
+        }
+    }
+}
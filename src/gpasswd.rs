@@ -0,0 +1,297 @@
+use lc_login::{groups::GroupHandle, users::UserHandle, Error};
+use zeroize::Zeroizing;
+
+fn print_help(prg_name: &str) {
+    println!("Usage: {} [option] GROUP", prg_name);
+    println!("Administers a group. Without options, changes the group password");
+    println!("Options:");
+    println!("\t-a, --add <user>: Add the user to the group");
+    println!("\t-A, --administrators <users>: Set the comma-separated administrators of the group. Only root may do this");
+    println!("\t-d, --delete <user>: Remove the user from the group");
+    println!("\t-h, --help: Print this message and exit");
+    println!("\t-M, --members <users>: Set the comma-separated members of the group. Only root may do this");
+    println!("\t-Q, --root <dir>: Make changes within the given sysroot. Only root may do this");
+    println!("\t-r, --remove-password: Remove the group password, so that only members may switch to the group");
+    println!("\t-R, --restrict: Disable the group password, so that only members may switch to the group");
+}
+
+fn operand(prg_name: &str, opt: &str, rest: String, args: &mut std::env::Args) -> String {
+    if !rest.is_empty() {
+        return rest;
+    }
+    match args.next() {
+        Some(s) => s,
+        None => {
+            eprintln!("{}: Missing operand for {}", prg_name, opt);
+            std::process::exit(2)
+        }
+    }
+}
+
+enum Action {
+    ChangePassword,
+    Add(String),
+    Delete(String),
+    RemovePassword,
+    Restrict,
+    Administrators(String),
+    Members(String),
+}
+
+pub fn main() {
+    let mut args = std::env::args();
+    let prg_name = args.next().unwrap();
+    let mut group_name = None;
+    let mut chroot = None;
+    let mut action = Action::ChangePassword;
+    let mut actions = 0;
+
+    while let Some(s) = args.next() {
+        match &*s {
+            "--help" => {
+                print_help(&prg_name);
+                std::process::exit(0)
+            }
+            "--root" => chroot = Some(operand(&prg_name, &s, String::new(), &mut args)),
+            "--add" => {
+                action = Action::Add(operand(&prg_name, &s, String::new(), &mut args));
+                actions += 1;
+            }
+            "--delete" => {
+                action = Action::Delete(operand(&prg_name, &s, String::new(), &mut args));
+                actions += 1;
+            }
+            "--administrators" => {
+                action = Action::Administrators(operand(&prg_name, &s, String::new(), &mut args));
+                actions += 1;
+            }
+            "--members" => {
+                action = Action::Members(operand(&prg_name, &s, String::new(), &mut args));
+                actions += 1;
+            }
+            "--remove-password" => {
+                action = Action::RemovePassword;
+                actions += 1;
+            }
+            "--restrict" => {
+                action = Action::Restrict;
+                actions += 1;
+            }
+            "--" => {
+                group_name = args.next();
+                break;
+            }
+            x if x.starts_with("--") => {
+                eprintln!("{}: Unrecognized option {}", prg_name, x);
+                std::process::exit(2)
+            }
+            x if x.starts_with('-') && x.len() > 1 => {
+                let mut chars = x[1..].chars();
+                while let Some(c) = chars.next() {
+                    let opt = format!("-{}", c);
+                    match c {
+                        'h' => {
+                            print_help(&prg_name);
+                            std::process::exit(0)
+                        }
+                        'r' => {
+                            action = Action::RemovePassword;
+                            actions += 1;
+                            continue;
+                        }
+                        'R' => {
+                            action = Action::Restrict;
+                            actions += 1;
+                            continue;
+                        }
+                        'Q' => {
+                            chroot = Some(operand(&prg_name, &opt, chars.collect(), &mut args));
+                            break;
+                        }
+                        'a' | 'd' | 'A' | 'M' => {}
+                        v => {
+                            eprintln!("{}: Unrecognized option -{}", prg_name, v);
+                            std::process::exit(2)
+                        }
+                    }
+                    let value = operand(&prg_name, &opt, chars.collect(), &mut args);
+                    action = match c {
+                        'a' => Action::Add(value),
+                        'd' => Action::Delete(value),
+                        'A' => Action::Administrators(value),
+                        _ => Action::Members(value),
+                    };
+                    actions += 1;
+                    break;
+                }
+            }
+            x => group_name = Some(x.to_string()),
+        }
+    }
+
+    let group_name = match group_name {
+        Some(n) => n,
+        None => {
+            print_help(&prg_name);
+            std::process::exit(2)
+        }
+    };
+
+    if actions > 1 {
+        eprintln!(
+            "{}: Only one of -a, -A, -d, -M, -r and -R may be given",
+            prg_name
+        );
+        std::process::exit(2)
+    }
+
+    let uid = unsafe { libc::getuid() };
+    if uid != 0 && chroot.is_some() {
+        eprintln!("{}: Permission Denied", prg_name);
+        std::process::exit(1)
+    }
+
+    let handle = match &chroot {
+        Some(chroot) => GroupHandle::from_name_in(&*group_name, chroot),
+        None => GroupHandle::from_name(&*group_name),
+    };
+    let handle = match handle {
+        Ok(handle) => handle,
+        Err(Error::NoSuchGroup) => {
+            eprintln!("{}: Group {} does not exist", prg_name, group_name);
+            std::process::exit(3)
+        }
+        Err(e) => {
+            eprintln!("{}: {}", prg_name, e);
+            std::process::exit(3)
+        }
+    };
+
+    // Administrators may manage the group, except for who administers it and replacing its members wholesale
+    let permitted = match action {
+        _ if uid == 0 => true,
+        Action::Administrators(_) | Action::Members(_) => false,
+        _ => match handle.is_admin(uid) {
+            Ok(admin) => admin,
+            Err(e) => {
+                eprintln!("{}: {}", prg_name, e);
+                std::process::exit(3)
+            }
+        },
+    };
+    if !permitted {
+        eprintln!("{}: Permission Denied", prg_name);
+        std::process::exit(1)
+    }
+
+    let resolve = |user: &str| {
+        let user_handle = match &chroot {
+            Some(chroot) => UserHandle::from_name_in(user, chroot),
+            None => UserHandle::from_name(user),
+        };
+        match user_handle.and_then(|h| h.uid()) {
+            Ok(uid) => uid,
+            Err(Error::NoSuchUser) => {
+                eprintln!("{}: User {} does not exist", prg_name, user);
+                std::process::exit(3)
+            }
+            Err(e) => {
+                eprintln!("{}: {}", prg_name, e);
+                std::process::exit(3)
+            }
+        }
+    };
+    let resolve_list = |users: &str| -> Vec<u32> {
+        users
+            .split(',')
+            .filter(|u| !u.is_empty())
+            .map(resolve)
+            .collect()
+    };
+
+    let result = match action {
+        Action::Add(user) => {
+            let member = resolve(&user);
+            println!("Adding user {} to group {}", user, group_name);
+            handle.add_member(member)
+        }
+        Action::Delete(user) => {
+            let member = resolve(&user);
+            if !handle.members().unwrap_or_default().contains(&member) {
+                eprintln!(
+                    "{}: User {} is not a member of {}",
+                    prg_name, user, group_name
+                );
+                std::process::exit(3)
+            }
+            println!("Removing user {} from group {}", user, group_name);
+            handle.remove_member(member)
+        }
+        Action::Administrators(users) => handle.set_admins(&resolve_list(&users)),
+        Action::Members(users) => handle.set_members(&resolve_list(&users)),
+        Action::RemovePassword => handle.remove_password(),
+        Action::Restrict => handle.disable_password(),
+        Action::ChangePassword => {
+            println!("Changing the password for group {}", group_name);
+            let passwd = match rpassword::read_password_from_tty(Some("New Password: ")) {
+                Ok(s) => Zeroizing::new(s),
+                Err(e) => {
+                    eprintln!("{}: Failed to read password, {}", prg_name, e);
+                    std::process::exit(3)
+                }
+            };
+            let passwd_confirm = match rpassword::read_password_from_tty(Some("Confirm Password: "))
+            {
+                Ok(s) => Zeroizing::new(s),
+                Err(e) => {
+                    eprintln!("{}: Failed to read password, {}", prg_name, e);
+                    std::process::exit(3)
+                }
+            };
+            if passwd.len() != passwd_confirm.len()
+                || !openssl::memcmp::eq(passwd.as_bytes(), passwd_confirm.as_bytes())
+            {
+                eprintln!("{}: Password Mismatch", prg_name);
+                std::process::exit(1)
+            }
+            let rejection = lc_login::password::policy::read_policy()
+                .and_then(|policy| policy.check(&passwd, Some(&group_name), None));
+            match rejection {
+                Ok(None) => {}
+                // root may set any password
+                Ok(Some(rejection)) if uid == 0 => eprintln!(
+                    "{}: Warning: {} ({} policy)",
+                    prg_name,
+                    rejection,
+                    rejection.policy()
+                ),
+                Ok(Some(rejection)) => {
+                    eprintln!(
+                        "{}: Password rejected by the {} policy: {}",
+                        prg_name,
+                        rejection.policy(),
+                        rejection
+                    );
+                    std::process::exit(1)
+                }
+                Err(e) => {
+                    eprintln!("{}: Failed to check the password policy, {}", prg_name, e);
+                    std::process::exit(3)
+                }
+            }
+            handle.set_password(&passwd)
+        }
+    };
+
+    match result {
+        Ok(()) => {}
+        Err(Error::Busy) => {
+            eprintln!("{}: Group file busy, please retry", prg_name);
+            std::process::exit(5)
+        }
+        Err(e) => {
+            eprintln!("{}: Failed to update the group, {}", prg_name, e);
+            std::process::exit(3)
+        }
+    }
+}
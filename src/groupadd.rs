@@ -0,0 +1,166 @@
+use lc_login::{
+    groups::{GroupHandle, NewGroup},
+    Error,
+};
+
+fn print_help(prg_name: &str) {
+    println!("Usage: {} [options] GROUP", prg_name);
+    println!("Creates a new group");
+    println!("Options:");
+    println!("\t-f, --force: Exit successfully if the group already exists. If the gid is taken, allocate another one");
+    println!("\t-g, --gid <gid>: Set the gid of the group, instead of allocating one");
+    println!("\t-h, --help: Print this message and exit");
+    println!("\t-r, --system: Create a system group, with a gid from the system range");
+    println!("\t-R, --root: Make changes within the given sysroot");
+}
+
+fn operand(prg_name: &str, opt: &str, rest: String, args: &mut std::env::Args) -> String {
+    if !rest.is_empty() {
+        return rest;
+    }
+    match args.next() {
+        Some(s) => s,
+        None => {
+            eprintln!("{}: Missing operand for {}", prg_name, opt);
+            std::process::exit(2)
+        }
+    }
+}
+
+pub fn main() {
+    let mut args = std::env::args();
+    let prg_name = args.next().unwrap();
+    let mut group_name = None;
+    let mut chroot = None;
+    let mut gid = None;
+    let mut force = false;
+    let mut system = false;
+
+    while let Some(s) = args.next() {
+        match &*s {
+            "--help" => {
+                print_help(&prg_name);
+                std::process::exit(0)
+            }
+            "--force" => force = true,
+            "--system" => system = true,
+            "--root" => chroot = Some(operand(&prg_name, &s, String::new(), &mut args)),
+            "--gid" => gid = Some(operand(&prg_name, &s, String::new(), &mut args)),
+            "--" => {
+                group_name = args.next();
+                break;
+            }
+            x if x.starts_with("--") => {
+                eprintln!("{}: Unrecognized option {}", prg_name, x);
+                std::process::exit(2)
+            }
+            x if x.starts_with('-') && x.len() > 1 => {
+                let mut chars = x[1..].chars();
+                while let Some(c) = chars.next() {
+                    let opt = format!("-{}", c);
+                    let target = match c {
+                        'h' => {
+                            print_help(&prg_name);
+                            std::process::exit(0)
+                        }
+                        'f' => {
+                            force = true;
+                            continue;
+                        }
+                        'r' => {
+                            system = true;
+                            continue;
+                        }
+                        'R' => &mut chroot,
+                        'g' => &mut gid,
+                        v => {
+                            eprintln!("{}: Unrecognized option -{}", prg_name, v);
+                            std::process::exit(2)
+                        }
+                    };
+                    *target = Some(operand(&prg_name, &opt, chars.collect(), &mut args));
+                    break;
+                }
+            }
+            x => group_name = Some(x.to_string()),
+        }
+    }
+
+    let group_name = match group_name {
+        Some(n) => n,
+        None => {
+            print_help(&prg_name);
+            std::process::exit(2)
+        }
+    };
+
+    if unsafe { libc::getuid() } != 0 {
+        eprintln!("{}: Permission Denied", prg_name);
+        std::process::exit(1)
+    }
+
+    let gid = gid.map(|gid| match gid.parse::<u32>() {
+        Ok(gid) => gid,
+        Err(e) => {
+            eprintln!("{}: Invalid gid {}: {}", prg_name, gid, e);
+            std::process::exit(3)
+        }
+    });
+
+    let exists = match &chroot {
+        Some(chroot) => GroupHandle::from_name_in(&*group_name, chroot),
+        None => GroupHandle::from_name(&*group_name),
+    };
+    match exists {
+        Ok(_) if force => std::process::exit(0),
+        Ok(_) => {
+            eprintln!("{}: Group {} already exists", prg_name, group_name);
+            std::process::exit(9)
+        }
+        Err(Error::NoSuchGroup) => {}
+        Err(e) => {
+            eprintln!("{}: {}", prg_name, e);
+            std::process::exit(10)
+        }
+    }
+
+    let mut group = NewGroup {
+        name: group_name.clone(),
+        gid,
+        system,
+    };
+    let create = |group: &NewGroup| match &chroot {
+        Some(chroot) => GroupHandle::create_in(group, chroot),
+        None => GroupHandle::create(group),
+    };
+    let mut result = create(&group);
+    if let (Err(Error::GroupExists), true, Some(_)) = (&result, force, gid) {
+        // The name was free above, so the gid is taken
+        group.gid = None;
+        result = create(&group);
+    }
+    match result {
+        Ok(_) => {}
+        Err(Error::GroupExists) if gid.is_some() => {
+            eprintln!(
+                "{}: Group {} or gid {} already exists",
+                prg_name,
+                group_name,
+                gid.unwrap()
+            );
+            std::process::exit(4)
+        }
+        Err(Error::GroupExists) => {
+            eprintln!("{}: Group {} already exists", prg_name, group_name);
+            std::process::exit(9)
+        }
+        Err(Error::InvalidName) => {
+            eprintln!("{}: Invalid group name {}", prg_name, group_name);
+            std::process::exit(3)
+        }
+        Err(e) => {
+            eprintln!("{}: Failed to create the group, {}", prg_name, e);
+            std::process::exit(10)
+        }
+    }
+}
@@ -0,0 +1,101 @@
+use lc_login::{groups::GroupHandle, Error};
+
+fn print_help(prg_name: &str) {
+    println!("Usage: {} [options] GROUP", prg_name);
+    println!("Deletes a group");
+    println!("Options:");
+    println!("\t-h, --help: Print this message and exit");
+    println!("\t-R, --root: Make changes within the given sysroot");
+}
+
+fn operand(prg_name: &str, opt: &str, rest: String, args: &mut std::env::Args) -> String {
+    if !rest.is_empty() {
+        return rest;
+    }
+    match args.next() {
+        Some(s) => s,
+        None => {
+            eprintln!("{}: Missing operand for {}", prg_name, opt);
+            std::process::exit(2)
+        }
+    }
+}
+
+pub fn main() {
+    let mut args = std::env::args();
+    let prg_name = args.next().unwrap();
+    let mut group_name = None;
+    let mut chroot = None;
+
+    while let Some(s) = args.next() {
+        match &*s {
+            "--help" => {
+                print_help(&prg_name);
+                std::process::exit(0)
+            }
+            "--root" => chroot = Some(operand(&prg_name, &s, String::new(), &mut args)),
+            "--" => {
+                group_name = args.next();
+                break;
+            }
+            x if x.starts_with("--") => {
+                eprintln!("{}: Unrecognized option {}", prg_name, x);
+                std::process::exit(2)
+            }
+            x if x.starts_with('-') && x.len() > 1 => {
+                let mut chars = x[1..].chars();
+                match chars.next().unwrap() {
+                    'h' => {
+                        print_help(&prg_name);
+                        std::process::exit(0)
+                    }
+                    'R' => {
+                        chroot = Some(operand(&prg_name, "-R", chars.collect(), &mut args));
+                    }
+                    v => {
+                        eprintln!("{}: Unrecognized option -{}", prg_name, v);
+                        std::process::exit(2)
+                    }
+                }
+            }
+            x => group_name = Some(x.to_string()),
+        }
+    }
+
+    let group_name = match group_name {
+        Some(n) => n,
+        None => {
+            print_help(&prg_name);
+            std::process::exit(2)
+        }
+    };
+
+    if unsafe { libc::getuid() } != 0 {
+        eprintln!("{}: Permission Denied", prg_name);
+        std::process::exit(1)
+    }
+
+    let handle = match &chroot {
+        Some(chroot) => GroupHandle::from_name_in(&*group_name, chroot),
+        None => GroupHandle::from_name(&*group_name),
+    };
+    let result = handle.and_then(GroupHandle::delete);
+    match result {
+        Ok(()) => {}
+        Err(Error::NoSuchGroup) => {
+            eprintln!("{}: Group {} does not exist", prg_name, group_name);
+            std::process::exit(6)
+        }
+        Err(Error::GroupInUse) => {
+            eprintln!(
+                "{}: Group {} is the primary group of a user, not removing it",
+                prg_name, group_name
+            );
+            std::process::exit(8)
+        }
+        Err(e) => {
+            eprintln!("{}: Failed to delete the group, {}", prg_name, e);
+            std::process::exit(10)
+        }
+    }
+}
@@ -0,0 +1,142 @@
+use lc_login::{groups::GroupHandle, Error};
+
+fn print_help(prg_name: &str) {
+    println!("Usage: {} [options] GROUP", prg_name);
+    println!("Modifies a group");
+    println!("Options:");
+    println!("\t-g, --gid <gid>: Change the gid of the group. Users with the group are moved to the new gid");
+    println!("\t-h, --help: Print this message and exit");
+    println!("\t-n, --new-name <name>: Rename the group");
+    println!("\t-R, --root: Make changes within the given sysroot");
+}
+
+fn operand(prg_name: &str, opt: &str, rest: String, args: &mut std::env::Args) -> String {
+    if !rest.is_empty() {
+        return rest;
+    }
+    match args.next() {
+        Some(s) => s,
+        None => {
+            eprintln!("{}: Missing operand for {}", prg_name, opt);
+            std::process::exit(2)
+        }
+    }
+}
+
+pub fn main() {
+    let mut args = std::env::args();
+    let prg_name = args.next().unwrap();
+    let mut group_name = None;
+    let mut chroot = None;
+    let mut gid = None;
+    let mut new_name = None;
+
+    while let Some(s) = args.next() {
+        match &*s {
+            "--help" => {
+                print_help(&prg_name);
+                std::process::exit(0)
+            }
+            "--root" => chroot = Some(operand(&prg_name, &s, String::new(), &mut args)),
+            "--gid" => gid = Some(operand(&prg_name, &s, String::new(), &mut args)),
+            "--new-name" => new_name = Some(operand(&prg_name, &s, String::new(), &mut args)),
+            "--" => {
+                group_name = args.next();
+                break;
+            }
+            x if x.starts_with("--") => {
+                eprintln!("{}: Unrecognized option {}", prg_name, x);
+                std::process::exit(2)
+            }
+            x if x.starts_with('-') && x.len() > 1 => {
+                let mut chars = x[1..].chars();
+                let c = chars.next().unwrap();
+                let opt = format!("-{}", c);
+                let target = match c {
+                    'h' => {
+                        print_help(&prg_name);
+                        std::process::exit(0)
+                    }
+                    'R' => &mut chroot,
+                    'g' => &mut gid,
+                    'n' => &mut new_name,
+                    v => {
+                        eprintln!("{}: Unrecognized option -{}", prg_name, v);
+                        std::process::exit(2)
+                    }
+                };
+                *target = Some(operand(&prg_name, &opt, chars.collect(), &mut args));
+            }
+            x => group_name = Some(x.to_string()),
+        }
+    }
+
+    let group_name = match group_name {
+        Some(n) => n,
+        None => {
+            print_help(&prg_name);
+            std::process::exit(2)
+        }
+    };
+
+    if unsafe { libc::getuid() } != 0 {
+        eprintln!("{}: Permission Denied", prg_name);
+        std::process::exit(1)
+    }
+
+    let gid = gid.map(|gid| match gid.parse::<u32>() {
+        Ok(gid) => gid,
+        Err(e) => {
+            eprintln!("{}: Invalid gid {}: {}", prg_name, gid, e);
+            std::process::exit(3)
+        }
+    });
+
+    let handle = match &chroot {
+        Some(chroot) => GroupHandle::from_name_in(&*group_name, chroot),
+        None => GroupHandle::from_name(&*group_name),
+    };
+    let mut handle = match handle {
+        Ok(handle) => handle,
+        Err(Error::NoSuchGroup) => {
+            eprintln!("{}: Group {} does not exist", prg_name, group_name);
+            std::process::exit(6)
+        }
+        Err(e) => {
+            eprintln!("{}: {}", prg_name, e);
+            std::process::exit(10)
+        }
+    };
+
+    if let Some(gid) = gid {
+        match handle.set_gid(gid) {
+            Ok(()) => {}
+            Err(Error::GroupExists) => {
+                eprintln!("{}: Gid {} already exists", prg_name, gid);
+                std::process::exit(4)
+            }
+            Err(e) => {
+                eprintln!("{}: Failed to change the gid, {}", prg_name, e);
+                std::process::exit(10)
+            }
+        }
+    }
+
+    if let Some(new_name) = new_name {
+        match handle.set_name(&new_name) {
+            Ok(()) => {}
+            Err(Error::GroupExists) => {
+                eprintln!("{}: Group {} already exists", prg_name, new_name);
+                std::process::exit(9)
+            }
+            Err(Error::InvalidName) => {
+                eprintln!("{}: Invalid group name {}", prg_name, new_name);
+                std::process::exit(3)
+            }
+            Err(e) => {
+                eprintln!("{}: Failed to rename the group, {}", prg_name, e);
+                std::process::exit(10)
+            }
+        }
+    }
+}
@@ -1,84 +1,910 @@
 use std::{
+    collections::{BTreeMap, HashSet},
     ffi::OsStr,
+    fmt,
     io::ErrorKind,
     path::{Path, PathBuf},
+    time::SystemTime,
 };
 
+use zeroize::Zeroizing;
+
+use crate::{
+    password::{PasswordFile, PasswordRecord},
+    users::{is_valid_name, UserHandle},
+    Error,
+};
+
+/// The group created by [`GroupHandle::create`]
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct NewGroup {
+    pub name: String,
+    /// The gid, or `None` to allocate one from the [`crate::ids`] ranges
+    pub gid: Option<u32>,
+    /// Allocates the gid from the system range, if `gid` is `None`
+    pub system: bool,
+}
+
 pub struct GroupHandle {
     path: PathBuf,
 }
 
 impl GroupHandle {
-    pub fn from_name<S: AsRef<OsStr>>(name: S) -> std::io::Result<Self> {
+    pub fn from_name<S: AsRef<OsStr>>(name: S) -> crate::Result<Self> {
         let mut path = PathBuf::from(*crate::dirs::GROUPS);
         path.push(name.as_ref());
-        path = std::fs::read_link(&path).unwrap_or(path);
+        match std::fs::read_link(&path) {
+            Ok(p) => {
+                path.pop();
+                path.push(p);
+            }
+            Err(e) if e.kind() == ErrorKind::InvalidInput => {}
+            Err(e) if e.kind() == ErrorKind::NotFound => return Err(Error::NoSuchGroup),
+            Err(e) => return Err(e.into()),
+        }
+        Ok(Self { path })
+    }
+
+    pub fn from_name_in<S: AsRef<OsStr>, P: AsRef<Path>>(
+        name: S,
+        chroot: P,
+    ) -> crate::Result<Self> {
+        let mut path = PathBuf::from(chroot.as_ref());
+        path.push(crate::dirs::GROUPS.strip_prefix("/").unwrap());
+        path.push(name.as_ref());
+        match std::fs::read_link(&path) {
+            Ok(p) => {
+                if p.is_absolute() {
+                    path = PathBuf::from(chroot.as_ref());
+                    path.push(p.strip_prefix("/").unwrap())
+                } else {
+                    path.pop();
+                    path.push(p);
+                }
+            }
+            Err(e) if e.kind() == ErrorKind::InvalidInput => {}
+            Err(e) if e.kind() == ErrorKind::NotFound => return Err(Error::NoSuchGroup),
+            Err(e) => return Err(e.into()),
+        }
         Ok(Self { path })
     }
 
+    /// Looks up a group by name, or by gid if `group` is numeric and no group has that name, as newgrp and sg do
+    pub fn from_name_or_gid(group: &str) -> crate::Result<Self> {
+        match Self::from_name(group) {
+            Err(Error::NoSuchGroup) => match group.parse::<u32>() {
+                Ok(gid) if Self::from_uid(gid).path.is_dir() => Ok(Self::from_uid(gid)),
+                _ => Err(Error::NoSuchGroup),
+            },
+            r => r,
+        }
+    }
+
     pub fn from_uid(uid: u32) -> Self {
         let mut path = PathBuf::from(*crate::dirs::GROUPS);
         path.push(uid.to_string());
         Self { path }
     }
 
+    pub fn from_uid_in<P: AsRef<Path>>(uid: u32, chroot: P) -> Self {
+        let mut path = PathBuf::from(chroot.as_ref());
+        path.push(crate::dirs::GROUPS.strip_prefix("/").unwrap());
+        path.push(uid.to_string());
+        Self { path }
+    }
+
+    /// Creates a new group in [`crate::dirs::GROUPS`]
+    pub fn create(group: &NewGroup) -> crate::Result<Self> {
+        Self::create_from(group, None)
+    }
+
+    /// Creates a new group within `chroot`.
+    ///
+    /// The gid is allocated from the [`crate::ids`] ranges configured within `chroot`, unless [`NewGroup::gid`] is set.
+    pub fn create_in<P: AsRef<Path>>(group: &NewGroup, chroot: P) -> crate::Result<Self> {
+        Self::create_from(group, Some(chroot.as_ref()))
+    }
+
+    fn create_from(group: &NewGroup, chroot: Option<&Path>) -> crate::Result<Self> {
+        if !is_valid_name(&group.name) {
+            return Err(Error::InvalidName);
+        }
+        let (mut dir, ranges) = match chroot {
+            Some(chroot) => (PathBuf::from(chroot), crate::ids::read_ranges_in(chroot)?),
+            None => (PathBuf::from("/"), crate::ids::read_ranges()?),
+        };
+        dir.push(crate::dirs::GROUPS.strip_prefix("/").unwrap());
+        std::fs::create_dir_all(&dir)?;
+
+        let (path, _) = crate::tree::create(
+            &dir,
+            &group.name,
+            group.gid,
+            ranges.gid_candidates(group.system),
+            "gid",
+            Error::GroupExists,
+            |_| Ok(()),
+        )?;
+        Ok(Self { path })
+    }
+
+    fn chroot(&self) -> crate::Result<PathBuf> {
+        let dir = self
+            .path
+            .parent()
+            .ok_or(Error::Corrupt("Invalid group directory"))?;
+        crate::tree::chroot_of(dir, &crate::dirs::GROUPS)
+    }
+
+    /// The users in the same chroot as the group
+    fn users(&self) -> crate::Result<impl Iterator<Item = crate::Result<UserHandle>>> {
+        crate::users::iter_in(self.chroot()?)
+    }
+
+    /// Deletes the group, and removes it from the supplementary groups of every user.
+    ///
+    /// Fails with [`Error::GroupInUse`] if the group is still the primary group of a user.
+    pub fn delete(self) -> crate::Result<()> {
+        let gid = self.existing_gid()?;
+        let mut members = Vec::new();
+        // Malformed entries in the users directory are not users
+        for user in self.users()?.filter_map(Result::ok) {
+            if user.primary_group()? == gid {
+                return Err(Error::GroupInUse);
+            }
+            if user.secondary_groups()?.contains(&gid) {
+                members.push(user);
+            }
+        }
+        crate::tree::remove(&self.path, Error::NoSuchGroup)?;
+        for user in members {
+            user.remove_secondary_group(gid)?;
+        }
+        Ok(())
+    }
+
     pub fn user_dir(&self) -> &Path {
         &self.path
     }
 
-    pub fn name(&self) -> std::io::Result<Option<String>> {
+    pub fn name(&self) -> crate::Result<Option<String>> {
         let mut path = self.path.clone();
         path.push("name");
         match std::fs::read_link(path) {
-            Ok(p) => Some(
-                p.file_name()
-                    .ok_or_else(|| std::io::Error::new(ErrorKind::InvalidData, "Invalid name stem"))
-                    .and_then(|v| {
-                        v.to_str()
-                            .ok_or_else(|| std::io::Error::new(ErrorKind::InvalidData, "Non-UTF8"))
-                    })
-                    .map(<str>::to_string),
-            )
-            .transpose(),
+            Ok(p) => p
+                .file_name()
+                .and_then(OsStr::to_str)
+                .map(|v| Some(v.to_string()))
+                .ok_or(Error::Corrupt("Invalid name symlink")),
             Err(e) if e.kind() == ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Renames the group.
+    ///
+    /// Fails with [`Error::GroupExists`] if another group has the name.
+    pub fn set_name<S: AsRef<str>>(&mut self, name: S) -> crate::Result<()> {
+        if !is_valid_name(name.as_ref()) {
+            return Err(Error::InvalidName);
+        }
+        crate::tree::rename(&self.path, name.as_ref(), Error::GroupExists)
+    }
+
+    pub fn gid(&self) -> crate::Result<u32> {
+        let mut path = self.path.clone();
+        path.push("gid");
+        std::fs::read_link(path)?
+            .file_name()
+            .and_then(OsStr::to_str)
+            .ok_or(Error::Corrupt("Invalid path in gid symlink"))?
+            .parse()
+            .map_err(|_| Error::Corrupt("Invalid path in gid symlink"))
+    }
+
+    /// The gid, or [`Error::NoSuchGroup`] if the group does not exist
+    fn existing_gid(&self) -> crate::Result<u32> {
+        match self.gid() {
+            Err(Error::Io(e)) if e.kind() == ErrorKind::NotFound => Err(Error::NoSuchGroup),
+            r => r,
+        }
+    }
+
+    /// Changes the gid of the group, by renaming the group directory, and moves the users whose primary or supplementary group it is along with it.
+    ///
+    /// Fails with [`Error::GroupExists`] if another group has the gid. Files owned by the old gid are not changed.
+    pub fn set_gid(&mut self, gid: u32) -> crate::Result<()> {
+        let old = self.existing_gid()?;
+        if old == gid {
+            return Ok(());
+        }
+        self.path = crate::tree::renumber(&self.path, gid, "gid", Error::GroupExists)?;
+        for user in self.users()?.filter_map(Result::ok) {
+            if user.primary_group()? == old {
+                user.set_primary_group(gid)?;
+            }
+            if user.secondary_groups()?.contains(&old) {
+                user.update_secondary_groups(|groups| {
+                    groups.retain(|g| *g != old);
+                    groups.push(gid);
+                })?;
+            }
+        }
+        Ok(())
+    }
+
+    fn members_path(&self) -> PathBuf {
+        let mut path = self.path.clone();
+        path.push("members");
+        path
+    }
+
+    /// The uids of the users that have the group as a supplementary group.
+    ///
+    /// The `members` file mirrors the `groups` file of each user, see [`check_membership_in`]. A missing or empty file means there are none
+    pub fn members(&self) -> crate::Result<Vec<u32>> {
+        match std::fs::read(self.members_path()) {
+            Ok(bytes) => crate::tree::parse_ids(&bytes, "Invalid members list"),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(Vec::new()),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Atomically modifies the group's `members` file, without updating the supplementary groups of the users
+    pub(crate) fn update_members<F: FnOnce(&mut Vec<u32>)>(&self, f: F) -> crate::Result<()> {
+        if !self.path.is_dir() {
+            return Err(Error::NoSuchGroup);
+        }
+        crate::atomic::update(&self.members_path(), 0o644, |current| {
+            let mut members = match current {
+                Some(bytes) => crate::tree::parse_ids(&bytes, "Invalid members list")?,
+                None => Vec::new(),
+            };
+            f(&mut members);
+            Ok(Some(crate::tree::format_ids(&mut members)))
+        })
+    }
+
+    /// Adds the user `uid` to the group, both in the group's member list and in the user's supplementary groups
+    pub fn add_member(&self, uid: u32) -> crate::Result<()> {
+        let gid = self.existing_gid()?;
+        let user = UserHandle::from_uid_in(uid, self.chroot()?);
+        if !user.user_dir().is_dir() {
+            return Err(Error::NoSuchUser);
+        }
+        self.update_members(|members| members.push(uid))?;
+        user.update_secondary_groups(|groups| groups.push(gid))
+    }
+
+    /// Removes the user `uid` from the group, both in the group's member list and in the user's supplementary groups
+    pub fn remove_member(&self, uid: u32) -> crate::Result<()> {
+        let gid = self.existing_gid()?;
+        self.update_members(|members| members.retain(|v| *v != uid))?;
+        match UserHandle::from_uid_in(uid, self.chroot()?)
+            .update_secondary_groups(|groups| groups.retain(|v| *v != gid))
+        {
+            Ok(()) | Err(Error::NoSuchUser) => Ok(()),
             Err(e) => Err(e),
         }
     }
 
-    pub fn set_name<S: AsRef<OsStr>>(&mut self, st: S) -> std::io::Result<()> {
-        if let Some(s) = self.name()? {
-            let mut path = self.path.clone();
-            path.push(s);
-            let _ = std::fs::remove_file(path);
+    /// Replaces the members of the group, adding and removing users on both sides like [`GroupHandle::add_member`] and [`GroupHandle::remove_member`].
+    ///
+    /// Fails with [`Error::NoSuchUser`], before changing anything, if one of the new members is not a user.
+    pub fn set_members(&self, uids: &[u32]) -> crate::Result<()> {
+        let old = self.members()?;
+        let chroot = self.chroot()?;
+        for uid in uids.iter().filter(|uid| !old.contains(uid)) {
+            if !UserHandle::from_uid_in(*uid, &chroot).user_dir().is_dir() {
+                return Err(Error::NoSuchUser);
+            }
+        }
+        for uid in old.iter().filter(|uid| !uids.contains(uid)) {
+            self.remove_member(*uid)?;
+        }
+        for uid in uids.iter().filter(|uid| !old.contains(uid)) {
+            self.add_member(*uid)?;
         }
+        Ok(())
+    }
+
+    fn admins_path(&self) -> PathBuf {
         let mut path = self.path.clone();
-        path.pop();
-        path.push(st.as_ref());
-        std::os::unix::fs::symlink(&self.path, &path)?;
-        let mut path2 = self.path.clone();
-        path2.push("name");
-        std::os::unix::fs::symlink(path, path2)
+        path.push("admins");
+        path
+    }
+
+    /// The uids of the administrators of the group, who may change its password and members without being root.
+    ///
+    /// A missing or empty `admins` file means there are none.
+    pub fn admins(&self) -> crate::Result<Vec<u32>> {
+        match std::fs::read(self.admins_path()) {
+            Ok(bytes) => crate::tree::parse_ids(&bytes, "Invalid admins list"),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(Vec::new()),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Checks whether the user `uid` is an administrator of the group
+    pub fn is_admin(&self, uid: u32) -> crate::Result<bool> {
+        Ok(self.admins()?.contains(&uid))
+    }
+
+    /// Replaces the administrators of the group.
+    ///
+    /// Fails with [`Error::NoSuchUser`] if one of them is not a user.
+    pub fn set_admins(&self, uids: &[u32]) -> crate::Result<()> {
+        let chroot = self.chroot()?;
+        for uid in uids {
+            if !UserHandle::from_uid_in(*uid, &chroot).user_dir().is_dir() {
+                return Err(Error::NoSuchUser);
+            }
+        }
+        self.update_admins(|admins| *admins = uids.to_vec())
+    }
+
+    /// Atomically modifies the group's `admins` file
+    pub(crate) fn update_admins<F: FnOnce(&mut Vec<u32>)>(&self, f: F) -> crate::Result<()> {
+        if !self.path.is_dir() {
+            return Err(Error::NoSuchGroup);
+        }
+        crate::atomic::update(&self.admins_path(), 0o644, |current| {
+            let mut admins = match current {
+                Some(bytes) => crate::tree::parse_ids(&bytes, "Invalid admins list")?,
+                None => Vec::new(),
+            };
+            f(&mut admins);
+            Ok(Some(crate::tree::format_ids(&mut admins)))
+        })
     }
 
-    pub fn gid(&self) -> std::io::Result<u32> {
+    fn password_path(&self) -> PathBuf {
         let mut path = self.path.clone();
-        path.push("gid");
-        std::fs::read_link(path)
-            .and_then(|e| {
-                e.file_name()
-                    .ok_or_else(|| {
-                        std::io::Error::new(ErrorKind::InvalidData, "Invalid path in symlink")
-                    })
-                    .map(|s| s.to_owned())
-            })
-            .and_then(|s| {
-                s.into_string().map_err(|_| {
-                    std::io::Error::new(ErrorKind::InvalidData, "Non-UTF path in symlink")
-                })
-            })
-            .and_then(|s| {
-                s.parse()
-                    .map_err(|e| std::io::Error::new(ErrorKind::InvalidData, e))
-            })
+        path.push("password");
+        path
+    }
+
+    pub fn has_password(&self) -> crate::Result<bool> {
+        match std::fs::metadata(self.password_path()) {
+            Ok(_) => Ok(true),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(false),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Reads and parses the group's password file, which has the same format as that of a user
+    pub fn password_file(&self) -> crate::Result<PasswordFile> {
+        match PasswordFile::open(self.password_path()) {
+            Err(Error::Io(e)) if e.kind() == ErrorKind::NotFound && !self.path.exists() => {
+                Err(Error::NoSuchGroup)
+            }
+            r => r,
+        }
+    }
+
+    /// Checks `passwd` against the group password, which lets users that are not members switch to the group.
+    ///
+    /// Fails with [`Error::Locked`] if the group has no password, or it is disabled, as only members may switch to such a group, with [`Error::Expired`] if the password has expired, and with [`Error::WrongPassword`] if it is incorrect.
+    pub fn authenticate(&self, passwd: &str) -> crate::Result<()> {
+        let record = match self.password_file() {
+            Ok(PasswordFile::Enabled(record)) => record,
+            Ok(PasswordFile::Disabled(_)) => return Err(Error::Locked),
+            Err(Error::Io(e)) if e.kind() == ErrorKind::NotFound => return Err(Error::Locked),
+            Err(e) => return Err(e),
+        };
+        if !record.verify(passwd)? {
+            return Err(Error::WrongPassword);
+        }
+        if record.is_expired(SystemTime::now()) {
+            return Err(Error::Expired);
+        }
+        Ok(())
+    }
+
+    /// Checks that `user` may switch to the group, as newgrp and sg do.
+    ///
+    /// Root and members of the group, through either their primary or a supplementary group, may always switch.
+    /// Other users must give the group password, which is only asked for with `passwd_prompt` if the group has a usable one.
+    /// Fails with [`Error::Locked`] if the user may not switch without a password, and otherwise as [`GroupHandle::authenticate`] does.
+    pub fn authorize_switch<F>(&self, user: &UserHandle, passwd_prompt: F) -> crate::Result<()>
+    where
+        F: FnOnce() -> crate::Result<Zeroizing<String>>,
+    {
+        let gid = self.gid()?;
+        if user.uid()? == 0
+            || user.primary_group()? == gid
+            || user.secondary_groups()?.contains(&gid)
+        {
+            return Ok(());
+        }
+        match self.password_file() {
+            Ok(PasswordFile::Enabled(_)) => {}
+            Ok(PasswordFile::Disabled(_)) => return Err(Error::Locked),
+            Err(Error::Io(e)) if e.kind() == ErrorKind::NotFound => return Err(Error::Locked),
+            Err(e) => return Err(e),
+        }
+        self.authenticate(&passwd_prompt()?)
+    }
+
+    /// Hashes `passwd` with the current authtemplate, and replaces the group password
+    pub fn set_password(&self, passwd: &str) -> crate::Result<()> {
+        if !self.path.is_dir() {
+            return Err(Error::NoSuchGroup);
+        }
+        let (header, params) = crate::password::read_template()?;
+        let record = PasswordRecord::new(passwd, &header, params)?;
+        crate::password::update(self.password_path(), |_| {
+            Ok(Some(PasswordFile::Enabled(record)))
+        })
+    }
+
+    /// Removes the group password, so that only members may switch to the group
+    pub fn remove_password(&self) -> crate::Result<()> {
+        match std::fs::remove_file(self.password_path()) {
+            Ok(_) => Ok(()),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(()),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Disables the group password, keeping the hash, so that only members may switch to the group
+    pub fn disable_password(&self) -> crate::Result<()> {
+        crate::password::update(self.password_path(), |file| match file {
+            Some(PasswordFile::Enabled(record)) => Ok(Some(PasswordFile::Disabled(record))),
+            _ => Ok(None), // Already disabled, or no password at all
+        })
+    }
+}
+
+/// A disagreement between the supplementary groups of the users and the member and administrator lists of the groups, found by [`check_membership_in`]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Inconsistency {
+    /// The user lists the group, but the group does not list the user
+    MissingMember { uid: u32, gid: u32 },
+    /// The group lists the user, but the user does not list the group
+    ExtraMember { uid: u32, gid: u32 },
+    /// The group lists a uid that is not a user
+    UnknownMember { uid: u32, gid: u32 },
+    /// The user lists a gid that is not a group
+    UnknownGroup { uid: u32, gid: u32 },
+    /// The group lists a uid that is not a user as an administrator
+    UnknownAdmin { uid: u32, gid: u32 },
+    /// The user directory at `dir` could not be read, for example because its `uid` symlink is broken. It cannot be repaired automatically
+    BrokenUser { dir: PathBuf, reason: String },
+    /// The group directory at `dir` could not be read. It cannot be repaired automatically
+    BrokenGroup { dir: PathBuf, reason: String },
+}
+
+impl Inconsistency {
+    /// Checks whether [`repair_membership_in`] repairs this inconsistency
+    pub fn is_repairable(&self) -> bool {
+        !matches!(self, Self::BrokenUser { .. } | Self::BrokenGroup { .. })
+    }
+}
+
+impl fmt::Display for Inconsistency {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::MissingMember { uid, gid } => write!(
+                f,
+                "user {} has group {}, but is not in its member list",
+                uid, gid
+            ),
+            Self::ExtraMember { uid, gid } => write!(
+                f,
+                "group {} lists user {} as a member, but the user does not have the group",
+                gid, uid
+            ),
+            Self::UnknownMember { uid, gid } => {
+                write!(
+                    f,
+                    "group {} lists {} as a member, which is not a user",
+                    gid, uid
+                )
+            }
+            Self::UnknownGroup { uid, gid } => {
+                write!(f, "user {} has group {}, which does not exist", uid, gid)
+            }
+            Self::UnknownAdmin { uid, gid } => write!(
+                f,
+                "group {} lists {} as an administrator, which is not a user",
+                gid, uid
+            ),
+            Self::BrokenUser { dir, reason } => {
+                write!(f, "user directory {} is broken: {}", dir.display(), reason)
+            }
+            Self::BrokenGroup { dir, reason } => {
+                write!(f, "group directory {} is broken: {}", dir.display(), reason)
+            }
+        }
+    }
+}
+
+/// Compares the supplementary groups of every user in [`crate::dirs::USERS`] with the member lists of the groups, see [`check_membership_in`]
+pub fn check_membership() -> crate::Result<Vec<Inconsistency>> {
+    check_from(Path::new("/"))
+}
+
+/// Compares the supplementary groups of every user within `chroot` with the member lists of the groups.
+///
+/// Malformed entries in the users and groups directories are not users or groups, and are ignored.
+/// Users and groups whose directories cannot be read are reported as [`Inconsistency::BrokenUser`] and [`Inconsistency::BrokenGroup`], and left out of the other checks.
+/// Their ids are taken from the names of their directories, so that memberships of them are not reported as unknown.
+pub fn check_membership_in<P: AsRef<Path>>(chroot: P) -> crate::Result<Vec<Inconsistency>> {
+    check_from(chroot.as_ref())
+}
+
+/// The id that the entry directory `dir` is named by, if it is named by one
+fn id_of_dir(dir: &Path) -> Option<u32> {
+    dir.file_name()?.to_str()?.parse().ok()
+}
+
+fn check_from(chroot: &Path) -> crate::Result<Vec<Inconsistency>> {
+    let mut result = Vec::new();
+    let mut users = BTreeMap::new();
+    let mut broken_uids = HashSet::new();
+    for user in crate::users::iter_in(chroot)?.filter_map(Result::ok) {
+        match user
+            .uid()
+            .and_then(|uid| Ok((uid, user.secondary_groups()?)))
+        {
+            Ok((uid, gids)) => {
+                users.insert(uid, gids);
+            }
+            Err(e) => {
+                broken_uids.extend(id_of_dir(user.user_dir()));
+                result.push(Inconsistency::BrokenUser {
+                    dir: user.user_dir().to_path_buf(),
+                    reason: e.to_string(),
+                });
+            }
+        }
+    }
+    let mut groups = BTreeMap::new();
+    let mut broken_gids = HashSet::new();
+    for group in iter_in(chroot)?.filter_map(Result::ok) {
+        match group
+            .gid()
+            .and_then(|gid| Ok((gid, group.members()?, group.admins()?)))
+        {
+            Ok((gid, members, admins)) => {
+                groups.insert(gid, (members, admins));
+            }
+            Err(e) => {
+                broken_gids.extend(id_of_dir(group.user_dir()));
+                result.push(Inconsistency::BrokenGroup {
+                    dir: group.user_dir().to_path_buf(),
+                    reason: e.to_string(),
+                });
+            }
+        }
+    }
+
+    for (&uid, gids) in &users {
+        for &gid in gids {
+            match groups.get(&gid) {
+                Some((members, _)) if members.contains(&uid) => {}
+                Some(_) => result.push(Inconsistency::MissingMember { uid, gid }),
+                None if broken_gids.contains(&gid) => {}
+                None => result.push(Inconsistency::UnknownGroup { uid, gid }),
+            }
+        }
+    }
+    for (&gid, (members, admins)) in &groups {
+        for &uid in members {
+            match users.get(&uid) {
+                Some(gids) if gids.contains(&gid) => {}
+                Some(_) => result.push(Inconsistency::ExtraMember { uid, gid }),
+                None if broken_uids.contains(&uid) => {}
+                None => result.push(Inconsistency::UnknownMember { uid, gid }),
+            }
+        }
+        for &uid in admins {
+            if !users.contains_key(&uid) && !broken_uids.contains(&uid) {
+                result.push(Inconsistency::UnknownAdmin { uid, gid });
+            }
+        }
+    }
+    Ok(result)
+}
+
+/// Repairs the inconsistencies in [`crate::dirs::USERS`] and [`crate::dirs::GROUPS`], see [`repair_membership_in`]
+pub fn repair_membership() -> crate::Result<Vec<Inconsistency>> {
+    repair_from(Path::new("/"))
+}
+
+/// Finds the inconsistencies within `chroot` like [`check_membership_in`], and repairs them. Returns the inconsistencies found, of which those that are not [`Inconsistency::is_repairable`] are left as they are.
+///
+/// The `groups` file of each user is taken to be correct, as it is what a login uses: member lists are brought in line with it, and only gids of groups that do not exist are removed from it.
+/// Operations that change both sides update the group first, so an interrupted change is undone by the repair.
+pub fn repair_membership_in<P: AsRef<Path>>(chroot: P) -> crate::Result<Vec<Inconsistency>> {
+    repair_from(chroot.as_ref())
+}
+
+fn repair_from(chroot: &Path) -> crate::Result<Vec<Inconsistency>> {
+    let inconsistencies = check_from(chroot)?;
+    for inconsistency in &inconsistencies {
+        match *inconsistency {
+            Inconsistency::MissingMember { uid, gid } => {
+                GroupHandle::from_uid_in(gid, chroot).update_members(|members| members.push(uid))?
+            }
+            Inconsistency::ExtraMember { uid, gid } | Inconsistency::UnknownMember { uid, gid } => {
+                GroupHandle::from_uid_in(gid, chroot)
+                    .update_members(|members| members.retain(|v| *v != uid))?
+            }
+            Inconsistency::UnknownGroup { uid, gid } => UserHandle::from_uid_in(uid, chroot)
+                .update_secondary_groups(|groups| groups.retain(|v| *v != gid))?,
+            Inconsistency::UnknownAdmin { uid, gid } => GroupHandle::from_uid_in(gid, chroot)
+                .update_admins(|admins| admins.retain(|v| *v != uid))?,
+            Inconsistency::BrokenUser { .. } | Inconsistency::BrokenGroup { .. } => {}
+        }
+    }
+    Ok(inconsistencies)
+}
+
+/// Lists every group in [`crate::dirs::GROUPS`], see [`iter_in`]
+pub fn iter() -> crate::Result<impl Iterator<Item = crate::Result<GroupHandle>>> {
+    iter_from(PathBuf::from(*crate::dirs::GROUPS), None)
+}
+
+/// Lists every group within `chroot`.
+///
+/// Each group is yielded once, whether it is found through its gid directory or its name symlink, in order of gid.
+/// Malformed entries, like dangling name symlinks, are yielded as errors, so they can be reported or skipped.
+pub fn iter_in<P: AsRef<Path>>(
+    chroot: P,
+) -> crate::Result<impl Iterator<Item = crate::Result<GroupHandle>>> {
+    let mut path = PathBuf::from(chroot.as_ref());
+    path.push(crate::dirs::GROUPS.strip_prefix("/").unwrap());
+    iter_from(path, Some(chroot.as_ref()))
+}
+
+fn iter_from(
+    dir: PathBuf,
+    chroot: Option<&Path>,
+) -> crate::Result<impl Iterator<Item = crate::Result<GroupHandle>>> {
+    Ok(crate::tree::entries(&dir, chroot)?
+        .into_iter()
+        .map(|path| path.map(|path| GroupHandle { path })))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::users::NewUser;
+
+    fn group(root: &Path, name: &str, gid: u32) -> GroupHandle {
+        GroupHandle::create_in(
+            &NewGroup {
+                name: name.to_string(),
+                gid: Some(gid),
+                system: false,
+            },
+            root,
+        )
+        .unwrap()
+    }
+
+    fn user(root: &Path, name: &str, uid: u32, groups: &[u32]) -> UserHandle {
+        UserHandle::create_in(
+            &NewUser {
+                name: name.to_string(),
+                uid: Some(uid),
+                group: 100,
+                groups: groups.to_vec(),
+                ..NewUser::default()
+            },
+            root,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn check_reports_broken_users_and_carries_on() {
+        let root = tempfile::tempdir().unwrap();
+        group(root.path(), "users", 100);
+        let staff = group(root.path(), "staff", 101);
+        user(root.path(), "alice", 1000, &[101]);
+        let bob = user(root.path(), "bob", 1001, &[101]);
+        std::fs::remove_file(bob.user_dir().join("uid")).unwrap();
+        // Alice's membership is out of step, which is still found
+        staff
+            .update_members(|members| members.retain(|v| *v != 1000))
+            .unwrap();
+
+        let found = check_membership_in(root.path()).unwrap();
+        assert_eq!(found.len(), 2, "{:?}", found);
+        assert!(matches!(
+            &found[0],
+            Inconsistency::BrokenUser { dir, .. } if dir.ends_with("1001")
+        ));
+        assert!(!found[0].is_repairable());
+        assert_eq!(
+            found[1],
+            Inconsistency::MissingMember {
+                uid: 1000,
+                gid: 101
+            }
+        );
+
+        // Bob keeps his membership through the repair
+        repair_membership_in(root.path()).unwrap();
+        assert_eq!(staff.members().unwrap(), [1000, 1001]);
+        let found = check_membership_in(root.path()).unwrap();
+        assert_eq!(found.len(), 1, "{:?}", found);
+    }
+
+    #[test]
+    fn check_reports_broken_groups_and_carries_on() {
+        let root = tempfile::tempdir().unwrap();
+        group(root.path(), "users", 100);
+        let staff = group(root.path(), "staff", 101);
+        let alice = user(root.path(), "alice", 1000, &[101]);
+        std::fs::write(staff.user_dir().join("members"), "not a list").unwrap();
+
+        let found = check_membership_in(root.path()).unwrap();
+        assert_eq!(found.len(), 1, "{:?}", found);
+        assert!(matches!(
+            &found[0],
+            Inconsistency::BrokenGroup { dir, .. } if dir.ends_with("101")
+        ));
+        // Alice's group is broken, not missing, so she keeps it
+        repair_membership_in(root.path()).unwrap();
+        assert_eq!(alice.secondary_groups().unwrap(), [101]);
+    }
+
+    #[test]
+    fn create_allocates_from_ids_conf() {
+        let root = tempfile::tempdir().unwrap();
+        crate::ids::write_ranges_in(
+            root.path(),
+            "gid_min = 2000\ngid_max = 2001\nsys_gid_min = 500\nsys_gid_max = 501\n",
+        );
+        let create = |name: &str, system| {
+            GroupHandle::create_in(
+                &NewGroup {
+                    name: name.to_string(),
+                    gid: None,
+                    system,
+                },
+                root.path(),
+            )
+        };
+
+        assert_eq!(create("staff", false).unwrap().gid().unwrap(), 2000);
+        assert_eq!(create("audio", false).unwrap().gid().unwrap(), 2001);
+        assert!(matches!(create("video", false), Err(Error::NoFreeId)));
+        assert_eq!(create("daemon", true).unwrap().gid().unwrap(), 501);
+        assert_eq!(create("adm", true).unwrap().gid().unwrap(), 500);
+        assert_eq!(
+            GroupHandle::from_name_in("adm", root.path())
+                .unwrap()
+                .gid()
+                .unwrap(),
+            500
+        );
+    }
+
+    #[test]
+    fn create_refuses_conflicts() {
+        let root = tempfile::tempdir().unwrap();
+        group(root.path(), "staff", 101);
+        let create = |name: &str, gid| {
+            GroupHandle::create_in(
+                &NewGroup {
+                    name: name.to_string(),
+                    gid: Some(gid),
+                    system: false,
+                },
+                root.path(),
+            )
+        };
+
+        assert!(matches!(create("staff", 102), Err(Error::GroupExists)));
+        assert!(matches!(create("audio", 101), Err(Error::GroupExists)));
+        assert!(matches!(create("a:b", 102), Err(Error::InvalidName)));
+        let gids: Vec<_> = iter_in(root.path())
+            .unwrap()
+            .map(|group| group.unwrap().gid().unwrap())
+            .collect();
+        assert_eq!(gids, [101]);
+    }
+
+    #[test]
+    fn delete() {
+        let root = tempfile::tempdir().unwrap();
+        let users = group(root.path(), "users", 100);
+        let staff = group(root.path(), "staff", 101);
+        let alice = user(root.path(), "alice", 1000, &[101]);
+        let dir = staff.user_dir().to_path_buf();
+
+        assert!(matches!(users.delete(), Err(Error::GroupInUse)));
+        assert!(GroupHandle::from_name_in("users", root.path()).is_ok());
+        staff.delete().unwrap();
+        assert!(!dir.exists());
+        assert!(matches!(
+            GroupHandle::from_name_in("staff", root.path()),
+            Err(Error::NoSuchGroup)
+        ));
+        assert!(alice.secondary_groups().unwrap().is_empty());
+        assert!(matches!(
+            GroupHandle::from_uid_in(101, root.path()).delete(),
+            Err(Error::NoSuchGroup)
+        ));
+        assert!(check_membership_in(root.path()).unwrap().is_empty());
+    }
+
+    #[test]
+    fn rename() {
+        let root = tempfile::tempdir().unwrap();
+        let mut staff = group(root.path(), "staff", 101);
+        group(root.path(), "audio", 102);
+
+        assert!(matches!(staff.set_name("audio"), Err(Error::GroupExists)));
+        assert!(matches!(staff.set_name("123"), Err(Error::InvalidName)));
+        assert_eq!(staff.name().unwrap().as_deref(), Some("staff"));
+        staff.set_name("video").unwrap();
+        assert_eq!(staff.name().unwrap().as_deref(), Some("video"));
+        assert_eq!(
+            GroupHandle::from_name_in("video", root.path())
+                .unwrap()
+                .gid()
+                .unwrap(),
+            101
+        );
+        assert!(matches!(
+            GroupHandle::from_name_in("staff", root.path()),
+            Err(Error::NoSuchGroup)
+        ));
+    }
+
+    #[test]
+    fn change_gid() {
+        let root = tempfile::tempdir().unwrap();
+        let mut users = group(root.path(), "users", 100);
+        let mut staff = group(root.path(), "staff", 101);
+        let alice = user(root.path(), "alice", 1000, &[101]);
+
+        assert!(matches!(staff.set_gid(100), Err(Error::GroupExists)));
+        staff.set_gid(201).unwrap();
+        assert_eq!(staff.gid().unwrap(), 201);
+        assert_eq!(staff.members().unwrap(), [1000]);
+        assert_eq!(alice.secondary_groups().unwrap(), [201]);
+        users.set_gid(200).unwrap();
+        assert_eq!(alice.primary_group().unwrap(), 200);
+        assert_eq!(
+            GroupHandle::from_name_in("users", root.path())
+                .unwrap()
+                .gid()
+                .unwrap(),
+            200
+        );
+        assert!(check_membership_in(root.path()).unwrap().is_empty());
+    }
+
+    #[test]
+    fn members() {
+        let root = tempfile::tempdir().unwrap();
+        group(root.path(), "users", 100);
+        let staff = group(root.path(), "staff", 101);
+        let alice = user(root.path(), "alice", 1000, &[]);
+        let bob = user(root.path(), "bob", 1001, &[]);
+
+        staff.add_member(1000).unwrap();
+        assert!(matches!(staff.add_member(1002), Err(Error::NoSuchUser)));
+        assert_eq!(staff.members().unwrap(), [1000]);
+        assert_eq!(alice.secondary_groups().unwrap(), [101]);
+        staff.set_members(&[1001]).unwrap();
+        assert!(alice.secondary_groups().unwrap().is_empty());
+        assert_eq!(bob.secondary_groups().unwrap(), [101]);
+        assert!(matches!(
+            staff.set_members(&[1000, 1002]),
+            Err(Error::NoSuchUser)
+        ));
+        assert_eq!(staff.members().unwrap(), [1001]);
+        staff.remove_member(1001).unwrap();
+        assert!(staff.members().unwrap().is_empty());
+        assert!(bob.secondary_groups().unwrap().is_empty());
+        assert!(check_membership_in(root.path()).unwrap().is_empty());
     }
 }
@@ -0,0 +1,95 @@
+fn print_help(prg_name: &str) {
+    println!("Usage: {} [options]", prg_name);
+    println!("Checks that the member lists of the groups agree with the supplementary groups of the users, and repairs them");
+    println!("Options:");
+    println!("\t-h, --help: Print this message and exit");
+    println!("\t-r, --read-only: Report inconsistencies without repairing them");
+    println!("\t-R, --root: Check the users and groups within the given sysroot");
+}
+
+fn operand(prg_name: &str, opt: &str, rest: String, args: &mut std::env::Args) -> String {
+    if !rest.is_empty() {
+        return rest;
+    }
+    match args.next() {
+        Some(s) => s,
+        None => {
+            eprintln!("{}: Missing operand for {}", prg_name, opt);
+            std::process::exit(2)
+        }
+    }
+}
+
+pub fn main() {
+    let mut args = std::env::args();
+    let prg_name = args.next().unwrap();
+    let mut chroot = None;
+    let mut read_only = false;
+
+    while let Some(s) = args.next() {
+        match &*s {
+            "--help" => {
+                print_help(&prg_name);
+                std::process::exit(0)
+            }
+            "--read-only" => read_only = true,
+            "--root" => chroot = Some(operand(&prg_name, &s, String::new(), &mut args)),
+            x if x.starts_with('-') && !x.starts_with("--") && x.len() > 1 => {
+                let mut chars = x[1..].chars();
+                while let Some(c) = chars.next() {
+                    match c {
+                        'h' => {
+                            print_help(&prg_name);
+                            std::process::exit(0)
+                        }
+                        'r' => read_only = true,
+                        'R' => {
+                            chroot = Some(operand(&prg_name, "-R", chars.collect(), &mut args));
+                            break;
+                        }
+                        v => {
+                            eprintln!("{}: Unrecognized option -{}", prg_name, v);
+                            std::process::exit(2)
+                        }
+                    }
+                }
+            }
+            x => {
+                eprintln!("{}: Unrecognized option {}", prg_name, x);
+                std::process::exit(2)
+            }
+        }
+    }
+
+    if !read_only && unsafe { libc::getuid() } != 0 {
+        eprintln!("{}: Permission Denied", prg_name);
+        std::process::exit(1)
+    }
+
+    let result = match (&chroot, read_only) {
+        (Some(chroot), true) => lc_login::groups::check_membership_in(chroot),
+        (Some(chroot), false) => lc_login::groups::repair_membership_in(chroot),
+        (None, true) => lc_login::groups::check_membership(),
+        (None, false) => lc_login::groups::repair_membership(),
+    };
+    let inconsistencies = match result {
+        Ok(inconsistencies) => inconsistencies,
+        Err(e) => {
+            eprintln!("{}: {}", prg_name, e);
+            std::process::exit(10)
+        }
+    };
+    for inconsistency in &inconsistencies {
+        if read_only || !inconsistency.is_repairable() {
+            println!("{}", inconsistency);
+        } else {
+            println!("{}, repaired", inconsistency);
+        }
+    }
+    if inconsistencies
+        .iter()
+        .any(|inconsistency| read_only || !inconsistency.is_repairable())
+    {
+        std::process::exit(1)
+    }
+}
@@ -0,0 +1,133 @@
+//! The ranges new uids and gids are allocated from, configured from `ids.conf` in the sysconfdir.
+//!
+//! The file consists of `key = value` lines. Blank lines and lines starting with `#` are ignored. The recognized keys are:
+//! * `uid_min`, `uid_max`: The range of uids for regular users
+//! * `sys_uid_min`, `sys_uid_max`: The range of uids for system users
+//! * `gid_min`, `gid_max`: The range of gids for regular groups
+//! * `sys_gid_min`, `sys_gid_max`: The range of gids for system groups
+//! * `default_gid`: The primary group of new users that are not given one
+//!
+//! Regular ids are allocated from the bottom of their range, and system ids from the top.
+
+use std::{
+    io::ErrorKind,
+    ops::RangeInclusive,
+    path::{Path, PathBuf},
+};
+
+use crate::Error;
+
+/// The configured id ranges. See the [module documentation](self) for the meaning of each field
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct IdRanges {
+    pub uid_min: u32,
+    pub uid_max: u32,
+    pub sys_uid_min: u32,
+    pub sys_uid_max: u32,
+    pub gid_min: u32,
+    pub gid_max: u32,
+    pub sys_gid_min: u32,
+    pub sys_gid_max: u32,
+    pub default_gid: u32,
+}
+
+impl Default for IdRanges {
+    fn default() -> Self {
+        Self {
+            uid_min: 1000,
+            uid_max: 60000,
+            sys_uid_min: 100,
+            sys_uid_max: 999,
+            gid_min: 1000,
+            gid_max: 60000,
+            sys_gid_min: 100,
+            sys_gid_max: 999,
+            default_gid: 100,
+        }
+    }
+}
+
+impl IdRanges {
+    pub fn parse(s: &str) -> crate::Result<Self> {
+        let mut ranges = Self::default();
+        for line in s.lines() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let (key, value) = line
+                .split_once('=')
+                .ok_or(Error::Corrupt("Invalid id ranges"))?;
+            let value = value
+                .trim()
+                .parse()
+                .map_err(|_| Error::Corrupt("Invalid id ranges"))?;
+            match key.trim() {
+                "uid_min" => ranges.uid_min = value,
+                "uid_max" => ranges.uid_max = value,
+                "sys_uid_min" => ranges.sys_uid_min = value,
+                "sys_uid_max" => ranges.sys_uid_max = value,
+                "gid_min" => ranges.gid_min = value,
+                "gid_max" => ranges.gid_max = value,
+                "sys_gid_min" => ranges.sys_gid_min = value,
+                "sys_gid_max" => ranges.sys_gid_max = value,
+                "default_gid" => ranges.default_gid = value,
+                _ => return Err(Error::Corrupt("Unknown key in id ranges")),
+            }
+        }
+        Ok(ranges)
+    }
+
+    /// The uids to try, in order, when allocating a uid for a new user
+    pub fn uid_candidates(&self, system: bool) -> Box<dyn Iterator<Item = u32>> {
+        if system {
+            Box::new(RangeInclusive::new(self.sys_uid_min, self.sys_uid_max).rev())
+        } else {
+            Box::new(RangeInclusive::new(self.uid_min, self.uid_max))
+        }
+    }
+
+    /// The gids to try, in order, when allocating a gid for a new group
+    pub fn gid_candidates(&self, system: bool) -> Box<dyn Iterator<Item = u32>> {
+        if system {
+            Box::new(RangeInclusive::new(self.sys_gid_min, self.sys_gid_max).rev())
+        } else {
+            Box::new(RangeInclusive::new(self.gid_min, self.gid_max))
+        }
+    }
+}
+
+/// Reads the id ranges from `ids.conf` in the sysconfdir.
+///
+/// If there is no such file, the defaults are used.
+pub fn read_ranges() -> crate::Result<IdRanges> {
+    read_ranges_from(&crate::dirs::INSTALL_DIRS.sysconfdir)
+}
+
+/// Reads the id ranges from `ids.conf` in the sysconfdir within `chroot`
+pub fn read_ranges_in<P: AsRef<Path>>(chroot: P) -> crate::Result<IdRanges> {
+    read_ranges_from(&sysconfdir_in(chroot.as_ref()))
+}
+
+fn sysconfdir_in(chroot: &Path) -> PathBuf {
+    let sysconfdir = &crate::dirs::INSTALL_DIRS.sysconfdir;
+    chroot.join(sysconfdir.strip_prefix("/").unwrap_or(sysconfdir))
+}
+
+/// Writes `ids.conf` within `chroot`, for the tests that allocate ids
+#[cfg(test)]
+pub(crate) fn write_ranges_in(chroot: &Path, conf: &str) {
+    let sysconfdir = sysconfdir_in(chroot);
+    std::fs::create_dir_all(&sysconfdir).unwrap();
+    std::fs::write(sysconfdir.join("ids.conf"), conf).unwrap();
+}
+
+fn read_ranges_from(sysconfdir: &Path) -> crate::Result<IdRanges> {
+    let mut path = PathBuf::from(sysconfdir);
+    path.push("ids.conf");
+    match std::fs::read_to_string(path) {
+        Ok(s) => IdRanges::parse(&s),
+        Err(e) if e.kind() == ErrorKind::NotFound => Ok(IdRanges::default()),
+        Err(e) => Err(e.into()),
+    }
+}
@@ -3,10 +3,25 @@
 #[cfg(not(unix))]
 compile_error!("This is a replacement for the unix login programs, and is only available on unix");
 
+pub mod error;
+pub use error::{Error, Result};
+
 pub mod dirs;
 
+mod atomic;
+
+mod tree;
+
+pub mod aging;
+
+pub mod ids;
+
 pub mod password;
 
 pub mod users;
 
 pub mod groups;
+
+pub mod convert;
+
+pub mod session;
@@ -1,83 +1,36 @@
-use std::{
-    collections::HashMap,
-    ffi::CString,
-    io::{ErrorKind, Write},
-    path::Path,
-    process::Command,
-};
+use std::{collections::HashMap, io::Write};
 
-use lc_login::users::UserHandle;
+use lc_login::{
+    session::{self, Environment, Session},
+    users::{AuthOutcome, UserHandle},
+    Error,
+};
 use libc::getuid;
 use zeroize::Zeroizing;
 
-use std::os::unix::prelude::*;
-
 #[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
 pub enum Void {}
 
 pub fn execute_login(
     expired: bool,
+    old_passwd: Option<&str>,
     handle: &UserHandle,
     env: HashMap<String, String>,
     preserve_env: bool,
-) -> std::io::Result<Void> {
-    let uid = handle.uid()?;
+) -> lc_login::Result<Void> {
     if expired {
-        println!("Password Expired");
-        loop {
-            let passwd = Zeroizing::new(rpassword::prompt_password_stdout("New Password: ")?);
-            let passwd_confirm =
-                Zeroizing::new(rpassword::prompt_password_stdout("Confirm Password: ")?);
-            if passwd.len() == passwd_confirm.len() {
-                if openssl::memcmp::eq(passwd.as_bytes(), passwd_confirm.as_bytes()) {
-                    handle.set_password(&passwd)?;
-                    break;
-                }
-            }
-            eprintln!("Password Mismatch");
-        }
-    }
-    let home = handle.home()?;
-    let shell = handle.shell()?;
-    let root = handle.root()?;
-    let group = handle.primary_group()?;
-    let groups = handle.secondary_groups()?;
-
-    if let Some(root) = root {
-        let rdir = CString::new(root.into_os_string().into_vec())
-            .map_err(|e| std::io::Error::new(ErrorKind::InvalidData, e))?;
-        //
-        // SAFETY:
-        // rdir.as_ptr() is from rdir, so it is valid
-        // rdir is a CString, so it has a NUL terminator.
-        if unsafe { libc::chroot(rdir.as_ptr()) } < 0 {
-            return Err(std::io::Error::last_os_error());
-        }
+        session::change_expired_password(handle, old_passwd)?;
     }
-    //
-    // SAFETY:
-    // groups.as_ptr() is from groups, so it is valid
-    // groups.as_ptr() is valid for the length of the vector
-    if unsafe { libc::setgroups(groups.len(), groups.as_ptr()) } < 0 {
-        return Err(std::io::Error::last_os_error());
-    }
-
-    let mut cmd = Command::new(shell.as_deref().unwrap_or(Path::new("/bin/sh")));
-    if !preserve_env {
-        cmd.env_clear();
-    } else {
-        if let Some(dir) = &home {
-            cmd.env("HOME", dir);
-        }
-        cmd.env("SHELL", shell.as_deref().unwrap_or(Path::new("/bin/sh")));
-        cmd.env("PATH", "/usr/local/bin:/usr/bin:/bin");
-    }
-    cmd.envs(env);
-
-    cmd.uid(uid);
-    cmd.gid(group);
-    cmd.current_dir(home.as_deref().unwrap_or(Path::new("/")));
-    Err(cmd.exec())
+    let status = Session::new(handle)
+        .login_shell(true)
+        .environment(if preserve_env {
+            Environment::Update
+        } else {
+            Environment::Reset
+        })
+        .envs(env)
+        .run()?;
+    std::process::exit(session::exit_code(status))
 }
 
 pub fn main() -> ! {
@@ -96,7 +49,7 @@ pub fn main() -> ! {
     let mut no_auth = false;
     let mut uname = None;
     let mut env = HashMap::new();
-    while let Some(v) = args.next() {
+    for v in args {
         match &*v {
             "-p" => preserve = true,
             "-r" | "-h" => {
@@ -126,7 +79,7 @@ pub fn main() -> ! {
                 }
             };
 
-            match execute_login(false, &handle, env, preserve) {
+            match execute_login(false, None, &handle, env, preserve) {
                 Ok(v) => match v {},
                 Err(e) => {
                     eprintln!("{}: {}", prg_name, e);
@@ -151,6 +104,10 @@ pub fn main() -> ! {
 
         let handle = match lc_login::users::UserHandle::from_name(uname) {
             Ok(h) => h,
+            Err(Error::NoSuchUser) => {
+                eprintln!("{}: Login incorrect", prg_name);
+                std::process::exit(1)
+            }
             Err(e) => {
                 eprintln!("{}: {}", prg_name, e);
                 std::process::exit(1)
@@ -159,7 +116,11 @@ pub fn main() -> ! {
 
         match handle.has_password() {
             Ok(false) => {
-                let err = execute_login(false, &handle, env, preserve).unwrap_err();
+                // Still applies the account expiry
+                let err = handle
+                    .authenticate("")
+                    .and_then(|_| execute_login(false, None, &handle, env, preserve))
+                    .unwrap_err();
                 eprintln!("{}: {}", prg_name, err);
                 std::process::exit(1)
             }
@@ -178,10 +139,37 @@ pub fn main() -> ! {
             }
         };
 
-        match handle
-            .authenticate(&*passwd)
-            .and_then(|expired| execute_login(expired, &handle, env, preserve))
-        {
+        let outcome = match handle.authenticate_and_upgrade(&passwd) {
+            Ok(outcome) => outcome,
+            Err(Error::WrongPassword) => {
+                eprintln!("{}: Login incorrect", prg_name);
+                std::process::exit(1)
+            }
+            Err(Error::Expired) => {
+                eprintln!(
+                    "{}: Your account has expired; please contact your system administrator",
+                    prg_name
+                );
+                std::process::exit(1)
+            }
+            Err(e) => {
+                eprintln!("{}: {}", prg_name, e);
+                std::process::exit(1)
+            }
+        };
+
+        match outcome {
+            AuthOutcome::Locked => {
+                eprintln!("{}: Account is locked", prg_name);
+                std::process::exit(1)
+            }
+            AuthOutcome::Warning { days_left } => {
+                println!("Warning: your password will expire in {} days", days_left)
+            }
+            _ => {}
+        }
+
+        match execute_login(outcome.must_change(), Some(&passwd), &handle, env, preserve) {
             Ok(v) => match v {},
             Err(e) => {
                 eprintln!("{}: {}", prg_name, e);
@@ -0,0 +1,161 @@
+use lc_login::{
+    groups::GroupHandle,
+    session::{self, Environment, Session},
+    users::UserHandle,
+    Error,
+};
+use zeroize::Zeroizing;
+
+fn print_help(prg_name: &str) {
+    println!("Usage: {} [-] [GROUP]", prg_name);
+    println!("Starts a new shell with GROUP as the current group, or the primary group if no GROUP is given");
+    println!("Users that are not members of GROUP are prompted for the group password");
+    println!("Options:");
+    println!("\t-, -l: Start a login shell, with a fresh environment, in the home directory");
+    println!("\t-h, --help: Print this message and exit");
+}
+
+/// Checks that the caller may switch to `group`, prompting for the group password if they are not a member, and exits on failure
+fn authorize(prg_name: &str, handle: &UserHandle, group: &GroupHandle) {
+    let prompt = || {
+        Ok(Zeroizing::new(rpassword::prompt_password_stdout(
+            "Password: ",
+        )?))
+    };
+    match group.authorize_switch(handle, prompt) {
+        Ok(()) => {}
+        Err(Error::Locked) => {
+            eprintln!("{}: Permission Denied", prg_name);
+            std::process::exit(1)
+        }
+        Err(Error::WrongPassword) => {
+            eprintln!("{}: Authentication failure", prg_name);
+            std::process::exit(1)
+        }
+        Err(Error::Expired) => {
+            eprintln!(
+                "{}: The group password has expired; please contact your system administrator",
+                prg_name
+            );
+            std::process::exit(1)
+        }
+        Err(e) => {
+            eprintln!("{}: {}", prg_name, e);
+            std::process::exit(1)
+        }
+    }
+}
+
+pub fn main() {
+    let mut args = std::env::args();
+    let prg_name = args.next().unwrap();
+    if unsafe { libc::geteuid() } != 0 {
+        eprintln!("{}: Cannot work without effective root", prg_name);
+        std::process::exit(1);
+    }
+
+    let mut login_shell = false;
+    let mut group_name = None;
+
+    while let Some(s) = args.next() {
+        match &*s {
+            "-" => login_shell = true,
+            "--help" => {
+                print_help(&prg_name);
+                std::process::exit(0)
+            }
+            "--" => {
+                group_name = args.next();
+                break;
+            }
+            x if x.starts_with("--") => {
+                eprintln!("{}: Unrecognized option {}", prg_name, x);
+                std::process::exit(2)
+            }
+            x if x.starts_with('-') => {
+                for c in x[1..].chars() {
+                    match c {
+                        'h' => {
+                            print_help(&prg_name);
+                            std::process::exit(0)
+                        }
+                        'l' => login_shell = true,
+                        v => {
+                            eprintln!("{}: Unrecognized option -{}", prg_name, v);
+                            std::process::exit(2)
+                        }
+                    }
+                }
+            }
+            x => {
+                group_name = Some(x.to_string());
+                break;
+            }
+        }
+    }
+
+    if args.next().is_some() {
+        print_help(&prg_name);
+        std::process::exit(2)
+    }
+
+    let caller = unsafe { libc::getuid() };
+    let handle = UserHandle::from_uid(caller);
+    if !handle.user_dir().is_dir() {
+        eprintln!("{}: Unknown uid {}", prg_name, caller);
+        std::process::exit(1)
+    }
+
+    let gid = match &group_name {
+        Some(group_name) => {
+            let group = match GroupHandle::from_name_or_gid(group_name) {
+                Ok(group) => group,
+                Err(Error::NoSuchGroup) => {
+                    eprintln!("{}: Group {} does not exist", prg_name, group_name);
+                    std::process::exit(1)
+                }
+                Err(e) => {
+                    eprintln!("{}: {}", prg_name, e);
+                    std::process::exit(1)
+                }
+            };
+            let gid = match group.gid() {
+                Ok(gid) => gid,
+                Err(e) => {
+                    eprintln!("{}: {}", prg_name, e);
+                    std::process::exit(1)
+                }
+            };
+            authorize(&prg_name, &handle, &group);
+            gid
+        }
+        None => match handle.primary_group() {
+            Ok(gid) => gid,
+            Err(e) => {
+                eprintln!("{}: {}", prg_name, e);
+                std::process::exit(1)
+            }
+        },
+    };
+
+    let mut session = Session::new(&handle);
+    session.group(gid).login_shell(login_shell);
+    // Like su, a login shell starts from a fresh environment
+    session.environment(if login_shell {
+        Environment::Reset
+    } else {
+        Environment::Preserve
+    });
+
+    match session.run() {
+        Ok(status) => std::process::exit(session::exit_code(status)),
+        Err(Error::Io(e)) if e.kind() == std::io::ErrorKind::NotFound => {
+            eprintln!("{}: Failed to execute the shell, {}", prg_name, e);
+            std::process::exit(127)
+        }
+        Err(e) => {
+            eprintln!("{}: Failed to execute the shell, {}", prg_name, e);
+            std::process::exit(126)
+        }
+    }
+}
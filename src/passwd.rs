@@ -1,12 +1,52 @@
-use std::{
-    io::ErrorKind,
-    path::PathBuf,
-    time::{Duration, SystemTime},
-};
+use std::path::PathBuf;
 
-use lc_login::users::UserHandle;
+use lc_login::{
+    password::PasswordFile,
+    users::{AuthOutcome, UserHandle},
+    Error,
+};
 use zeroize::Zeroizing;
 
+/// Formats the shadow-compatible status line of `handle`: login, L/NP/P, last change, min, max, warn and inactive
+fn status_line(handle: &UserHandle) -> lc_login::Result<String> {
+    let state = match handle.password_file() {
+        Ok(PasswordFile::Enabled(_)) => "P",
+        Ok(PasswordFile::Disabled(_)) => "L",
+        Err(Error::Io(e)) if e.kind() == std::io::ErrorKind::NotFound => "NP",
+        Err(e) => return Err(e),
+    };
+    let name = match handle.name()? {
+        Some(name) => name,
+        None => handle.uid()?.to_string(),
+    };
+    let aging = handle.aging()?;
+    Ok(format!(
+        "{} {} {} {} {} {} {}",
+        name,
+        state,
+        aging
+            .last_change
+            .map_or_else(|| "never".to_string(), lc_login::aging::format_date),
+        aging.min_days.unwrap_or(-1),
+        aging.max_days.unwrap_or(-1),
+        aging.warn_days.unwrap_or(-1),
+        aging.inactive_days.unwrap_or(-1),
+    ))
+}
+
+fn operand(prg_name: &str, opt: &str, rest: String, args: &mut std::env::Args) -> String {
+    if !rest.is_empty() {
+        return rest;
+    }
+    match args.next() {
+        Some(s) => s,
+        None => {
+            eprintln!("{}: Missing operand for {}", prg_name, opt);
+            std::process::exit(2)
+        }
+    }
+}
+
 pub fn main() {
     let mut login_name = None;
     let mut args = std::env::args();
@@ -17,6 +57,9 @@ pub fn main() {
     let mut unlock = false;
     let mut expire_days = None;
     let mut chroot = None;
+    let mut status = false;
+    let mut all = false;
+    let mut stdin = false;
 
     if unsafe { libc::geteuid() } != 0 {
         eprintln!("{}: Cannot possibly work without effective root", prg_name);
@@ -28,24 +71,30 @@ pub fn main() {
                 println!("Usage: {} [options] [LOGIN]", prg_name);
                 println!("Changes the password of the current user account. The superuser can change the password of any account");
                 println!("Options:");
+                println!("\t-a, --all: With -S, show the status of all accounts");
                 println!("\t-d, --delete: Removes the user password");
                 println!("\t-e, --expire: Expire the user's password immediately. During the next login, the user will be required to enter a new password");
                 println!("\t-h, --help: Print this message and exit");
                 println!("\t-l, --lock: Lock the password.");
                 println!("\t-q, --quiet: Accepted for compatibility. Currently has no effect");
                 println!("\t-R, --root: Make changes within the given sysroot");
+                println!("\t-S, --status: Show the status of the account: login, L (locked), NP (no password) or P (usable password), last change, minimum age, maximum age, warning period and inactivity period");
+                println!("\t--stdin: Read the new password from standard input, rather than the terminal");
                 println!(
                     "\t-u, --unlock: Unlock the password and restore it to the previous value"
                 );
-                println!("\t-x, --maxdays <days>: Expire the password in <days> days, or -1 to remove the expiry");
+                println!("\t-x, --maxdays <days>: Set the maximum number of days the password remains valid after it is changed, or -1 to remove the maximum");
 
                 std::process::exit(0);
             }
+            "--all" => all = true,
             "--delete" => delete = true,
             "--expire" => expire = true,
             "--lock" => lock = true,
             "--quiet" => {}
-            "--root" => chroot = args.next(),
+            "--root" => chroot = Some(operand(&prg_name, &s, String::new(), &mut args)),
+            "--status" => status = true,
+            "--stdin" => stdin = true,
             "--unlock" => unlock = true,
             "--maxdays" => {
                 expire_days = match args.next().map(|s| s.parse::<i32>()).transpose() {
@@ -68,37 +117,37 @@ pub fn main() {
                 eprintln!("{}: Unrecognized Option {}", prg_name, x)
             }
             x if x.starts_with("-") => {
-                let mut chars = x.chars();
+                let mut chars = x[1..].chars();
                 while let Some(c) = chars.next() {
                     match c {
+                        'a' => all = true,
                         'd' => delete = true,
                         'e' => expire = true,
                         'h' => {
                             println!("Usage: {} [options] [LOGIN]", prg_name);
                             println!("Changes the password of the current user account. The superuser can change the password of any account");
                             println!("Options:");
+                            println!("\t-a, --all: With -S, show the status of all accounts");
                             println!("\t-d, --delete: Removes the user password");
                             println!("\t-e, --expire: Expire the user's password immediately. During the next login, the user will be required to enter a new password");
                             println!("\t-h, --help: Print this message and exit");
                             println!("\t-l, --lock: Lock the password.");
                             println!("\t-q, --quiet: Accepted for compatibility. Currently has no effect");
                             println!("\t-R, --root: Make changes within the given sysroot");
+                            println!("\t-S, --status: Show the status of the account: login, L (locked), NP (no password) or P (usable password), last change, minimum age, maximum age, warning period and inactivity period");
+                            println!("\t--stdin: Read the new password from standard input, rather than the terminal");
                             println!("\t-u, --unlock: Unlock the password and restore it to the previous value");
-                            println!("\t-x, --maxdays <days>: Expire the password in <days> days, or -1 to remove the expiry");
+                            println!("\t-x, --maxdays <days>: Set the maximum number of days the password remains valid after it is changed, or -1 to remove the maximum");
 
                             std::process::exit(0);
                         }
                         'l' => lock = true,
                         'q' => {}
                         'R' => {
-                            let str = chars.collect::<String>();
-                            if str.is_empty() {
-                                chroot = args.next();
-                            } else {
-                                chroot = Some(str);
-                            }
+                            chroot = Some(operand(&prg_name, "-R", chars.collect(), &mut args));
                             break;
                         }
+                        'S' => status = true,
                         'u' => unlock = true,
                         'x' => {
                             let mut str = chars.collect::<String>();
@@ -131,17 +180,18 @@ pub fn main() {
         }
     }
 
-    if matches!(login_name, Some(_))
+    if (login_name.is_some()
         || expire
         || lock
         || unlock
-        || matches!(expire_days, Some(_))
-        || matches!(chroot, Some(_))
+        || expire_days.is_some()
+        || chroot.is_some()
+        || all
+        || stdin)
+        && unsafe { libc::getuid() } != 0
     {
-        if unsafe { libc::getuid() } != 0 {
-            eprintln!("{}: Permission Denied", prg_name);
-            std::process::exit(1)
-        }
+        eprintln!("{}: Permission Denied", prg_name);
+        std::process::exit(1)
     }
 
     if lock && (unlock || delete) {
@@ -152,26 +202,77 @@ pub fn main() {
         std::process::exit(2)
     }
 
-    if expire && matches!(expire_days, Some(_)) {
+    if all && !status {
+        eprintln!("{}: -a is only valid with -S", prg_name);
+        std::process::exit(2)
+    }
+
+    if status && (delete || expire || lock || unlock || expire_days.is_some()) {
         eprintln!(
-            "{}: Cannot expire an account immediately and set the expiry time simultaneously",
+            "{}: Cannot show the status and change the password simultaneously",
             prg_name
         );
         std::process::exit(2)
     }
+
+    if all {
+        if login_name.is_some() {
+            eprintln!("{}: Cannot use -a with a LOGIN", prg_name);
+            std::process::exit(2)
+        }
+        let users = match &chroot {
+            Some(chroot) => lc_login::users::iter_in(chroot).map(|i| i.collect::<Vec<_>>()),
+            None => lc_login::users::iter().map(|i| i.collect()),
+        };
+        let users = match users {
+            Ok(users) => users,
+            Err(e) => {
+                eprintln!("{}: Failed to list users, {}", prg_name, e);
+                std::process::exit(3)
+            }
+        };
+        let mut code = 0;
+        for handle in users {
+            let handle = match handle {
+                Ok(handle) => handle,
+                Err(e) => {
+                    eprintln!("{}: {}", prg_name, e);
+                    code = 3;
+                    continue;
+                }
+            };
+            match status_line(&handle) {
+                Ok(line) => println!("{}", line),
+                Err(e) => {
+                    eprintln!("{}: {}: {}", prg_name, handle.user_dir().display(), e);
+                    code = 3;
+                }
+            }
+        }
+        std::process::exit(code)
+    }
+
     let handle;
     if let Some(n) = login_name {
         if let Some(chroot) = chroot {
             match UserHandle::from_name_in(&*n, PathBuf::from(chroot)) {
                 Ok(hdl) => handle = hdl,
-                Err(_) => {
+                Err(Error::NoSuchUser) => {
                     eprintln!("{}: No such user {}", prg_name, n);
                     std::process::exit(4);
                 }
+                Err(e) => {
+                    eprintln!("{}: {}", prg_name, e);
+                    std::process::exit(3);
+                }
             }
         } else {
             match UserHandle::from_name(&*n) {
                 Ok(hdl) => handle = hdl,
+                Err(Error::NoSuchUser) => {
+                    eprintln!("{}: No such user {}", prg_name, n);
+                    std::process::exit(4);
+                }
                 Err(e) => {
                     eprintln!("{}: {}", prg_name, e);
                     std::process::exit(3);
@@ -187,6 +288,20 @@ pub fn main() {
         }
     }
 
+    if status {
+        match status_line(&handle) {
+            Ok(line) => {
+                println!("{}", line);
+                std::process::exit(0)
+            }
+            Err(e) => {
+                eprintln!("{}: {}", prg_name, e);
+                std::process::exit(3)
+            }
+        }
+    }
+
+    let mut old_passwd = None;
     if unsafe { libc::getuid() } != 0 {
         let passwd = match rpassword::read_password_from_tty(Some("Current Password:")) {
             Ok(p) => Zeroizing::new(p),
@@ -197,117 +312,183 @@ pub fn main() {
         };
 
         match handle.authenticate(&passwd) {
-            Ok(_) => {}
-            Err(_) => {
+            Ok(AuthOutcome::Locked) => {
+                eprintln!("{}: Account is locked", prg_name);
+                std::process::exit(1)
+            }
+            Ok(outcome) => {
+                // An expired password may always be changed, otherwise the minimum age applies
+                if !outcome.must_change() {
+                    match handle.aging() {
+                        Ok(aging) if !aging.can_change(lc_login::aging::today()) => {
+                            eprintln!("{}: You must wait longer to change your password", prg_name);
+                            std::process::exit(1)
+                        }
+                        Ok(_) => {}
+                        Err(e) => {
+                            eprintln!("{}: {}", prg_name, e);
+                            std::process::exit(3)
+                        }
+                    }
+                }
+            }
+            Err(Error::WrongPassword) => {
                 eprintln!("{}: Incorrect password", prg_name);
                 std::process::exit(1)
             }
+            Err(Error::Expired) => {
+                eprintln!("{}: Your account has expired", prg_name);
+                std::process::exit(1)
+            }
+            Err(e) => {
+                eprintln!("{}: Authentication failed, {}", prg_name, e);
+                std::process::exit(3)
+            }
         }
+        old_passwd = Some(passwd);
     }
 
     if expire {
         match handle.expire_password(None) {
             Ok(()) => {}
-            Err(e) if e.kind() == ErrorKind::AlreadyExists => {
+            Err(Error::Busy) => {
                 eprintln!("{}: Password File busy, please retry", prg_name);
                 std::process::exit(5)
             }
-            Err(_) => {
-                eprintln!("{}: Failed to expire password", prg_name);
+            Err(e) => {
+                eprintln!("{}: Failed to expire password, {}", prg_name, e);
                 std::process::exit(3)
             }
         }
-    } else if let Some(days) = expire_days {
-        if days < 0 {
-            match handle.expire_password(Some(SystemTime::UNIX_EPOCH)) {
-                Ok(()) => {}
-                Err(e) if e.kind() == ErrorKind::AlreadyExists => {
-                    eprintln!("{}: Password File busy, please retry", prg_name);
-                    std::process::exit(5)
-                }
-                Err(_) => {
-                    eprintln!("{}: Failed to unexpire password", prg_name);
-                    std::process::exit(3)
-                }
-            }
+    }
+
+    if let Some(days) = expire_days {
+        // The same aging field as chage -M, so it shows up in -S
+        let days = if days < 0 {
+            None
         } else {
-            let mut time = SystemTime::now();
-            time += Duration::from_secs((days as u64) * 60 * 60 * 24);
-            match handle.expire_password(Some(time)) {
-                Ok(()) => {}
-                Err(e) if e.kind() == ErrorKind::AlreadyExists => {
-                    eprintln!("{}: Password File busy, please retry", prg_name);
-                    std::process::exit(5)
-                }
-                Err(_) => {
-                    eprintln!("{}: Failed to unexpire password", prg_name);
-                    std::process::exit(3)
-                }
+            Some(i64::from(days))
+        };
+        match handle.set_max_days(days) {
+            Ok(()) => {}
+            Err(Error::Busy) => {
+                eprintln!("{}: Aging File busy, please retry", prg_name);
+                std::process::exit(5)
+            }
+            Err(e) => {
+                eprintln!(
+                    "{}: Failed to set the maximum password age, {}",
+                    prg_name, e
+                );
+                std::process::exit(3)
             }
         }
     }
 
     if delete {
-        if let Err(_) = handle.remove_password() {
-            eprintln!("{}: Failed to remove password", prg_name);
+        if let Err(e) = handle.remove_password() {
+            eprintln!("{}: Failed to remove password, {}", prg_name, e);
             std::process::exit(3)
         }
     } else if lock {
         match handle.disable_password() {
             Ok(()) => {}
-            Err(e) if e.kind() == ErrorKind::AlreadyExists => {
+            Err(Error::Busy) => {
                 eprintln!("{}: Password File busy, please retry", prg_name);
                 std::process::exit(5)
             }
-            Err(_) => {
-                eprintln!("{}: Failed to lock password", prg_name);
+            Err(e) => {
+                eprintln!("{}: Failed to lock password, {}", prg_name, e);
                 std::process::exit(3)
             }
         }
     } else if unlock {
         match handle.enable_password() {
             Ok(()) => {}
-            Err(e) if e.kind() == ErrorKind::AlreadyExists => {
+            Err(Error::Busy) => {
                 eprintln!("{}: Password File busy, please retry", prg_name);
                 std::process::exit(5)
             }
-            Err(_) => {
-                eprintln!("{}: Failed to unlock password", prg_name);
+            Err(Error::Locked) => {
+                eprintln!("{}: There is no password to unlock", prg_name);
                 std::process::exit(3)
             }
-        }
-    } else {
-        let passwd = match rpassword::read_password_from_tty(Some("New Password: ")) {
-            Ok(s) => Zeroizing::new(s),
             Err(e) => {
-                eprintln!("{}: Failed to read password, {}", prg_name, e);
+                eprintln!("{}: Failed to unlock password, {}", prg_name, e);
                 std::process::exit(3)
             }
-        };
-        let passwd_confirm = match rpassword::read_password_from_tty(Some("Confirm Password: ")) {
-            Ok(s) => Zeroizing::new(s),
-            Err(e) => {
+        }
+    } else if !expire && expire_days.is_none() {
+        let passwd = if stdin {
+            let mut line = Zeroizing::new(String::new());
+            if let Err(e) = std::io::stdin().read_line(&mut line) {
                 eprintln!("{}: Failed to read password, {}", prg_name, e);
                 std::process::exit(3)
             }
-        };
-        if passwd.len() == passwd_confirm.len()
-            && openssl::memcmp::eq(passwd.as_bytes(), passwd_confirm.as_bytes())
-        {
-            match handle.set_password(&passwd) {
-                Ok(()) => {}
-                Err(e) if e.kind() == ErrorKind::AlreadyExists => {
-                    eprintln!("{}: Password File busy, please retry", prg_name);
-                    std::process::exit(5)
+            let len = line.trim_end_matches(['\n', '\r']).len();
+            line.truncate(len);
+            line
+        } else {
+            let passwd = match rpassword::read_password_from_tty(Some("New Password: ")) {
+                Ok(s) => Zeroizing::new(s),
+                Err(e) => {
+                    eprintln!("{}: Failed to read password, {}", prg_name, e);
+                    std::process::exit(3)
                 }
+            };
+            let passwd_confirm = match rpassword::read_password_from_tty(Some("Confirm Password: "))
+            {
+                Ok(s) => Zeroizing::new(s),
                 Err(e) => {
-                    eprintln!("{}: Failed to set password, {}", prg_name, e);
+                    eprintln!("{}: Failed to read password, {}", prg_name, e);
                     std::process::exit(3)
                 }
+            };
+            if passwd.len() != passwd_confirm.len()
+                || !openssl::memcmp::eq(passwd.as_bytes(), passwd_confirm.as_bytes())
+            {
+                eprintln!("{}: Password Mismatch", prg_name);
+                std::process::exit(1)
+            }
+            passwd
+        };
+
+        match handle.check_new_password(&passwd, old_passwd.as_deref().map(|s| &**s)) {
+            Ok(None) => {}
+            // root may set any password
+            Ok(Some(rejection)) if unsafe { libc::getuid() } == 0 => {
+                eprintln!(
+                    "{}: Warning: {} ({} policy)",
+                    prg_name,
+                    rejection,
+                    rejection.policy()
+                )
+            }
+            Ok(Some(rejection)) => {
+                eprintln!(
+                    "{}: Password rejected by the {} policy: {}",
+                    prg_name,
+                    rejection.policy(),
+                    rejection
+                );
+                std::process::exit(1)
+            }
+            Err(e) => {
+                eprintln!("{}: Failed to check the password policy, {}", prg_name, e);
+                std::process::exit(3)
+            }
+        }
+
+        match handle.set_password(&passwd) {
+            Ok(()) => {}
+            Err(Error::Busy) => {
+                eprintln!("{}: Password File busy, please retry", prg_name);
+                std::process::exit(5)
+            }
+            Err(e) => {
+                eprintln!("{}: Failed to set password, {}", prg_name, e);
+                std::process::exit(3)
             }
-        } else {
-            eprintln!("{}: Password Mismatch", prg_name);
-            std::process::exit(1)
         }
     }
 }
@@ -1 +1,132 @@
-pub fn main() {}
+use std::path::PathBuf;
+
+use lc_login::convert::Database;
+
+fn print_help(prg_name: &str) {
+    println!("Usage: {} [options]", prg_name);
+    println!("Imports the users and groups of the passwd, shadow, group and gshadow files into the user and group directories");
+    println!("Options:");
+    println!("\t-e, --export: Generate the passwd and group files from the user and group directories instead");
+    println!("\t-h, --help: Print this message and exit");
+    println!("\t-n, --dry-run: Print the changes that would be made, without making them");
+    println!("\t-R, --root: Read the files and make changes within the given sysroot");
+    println!("\t-s, --shadow: With --export, also generate the shadow and gshadow files");
+}
+
+fn operand(prg_name: &str, opt: &str, rest: String, args: &mut std::env::Args) -> String {
+    if !rest.is_empty() {
+        return rest;
+    }
+    match args.next() {
+        Some(s) => s,
+        None => {
+            eprintln!("{}: Missing operand for {}", prg_name, opt);
+            std::process::exit(2)
+        }
+    }
+}
+
+pub fn main() {
+    let mut args = std::env::args();
+    let prg_name = args.next().unwrap();
+    let mut dry_run = false;
+    let mut export = false;
+    let mut shadow = false;
+    let mut chroot = None;
+
+    while let Some(s) = args.next() {
+        match &*s {
+            "--help" => {
+                print_help(&prg_name);
+                std::process::exit(0)
+            }
+            "--dry-run" => dry_run = true,
+            "--export" => export = true,
+            "--shadow" => shadow = true,
+            "--root" => chroot = Some(operand(&prg_name, &s, String::new(), &mut args)),
+            x if x.starts_with("--") => {
+                eprintln!("{}: Unrecognized option {}", prg_name, x);
+                std::process::exit(2)
+            }
+            x if x.starts_with('-') && x.len() > 1 => {
+                let mut chars = x[1..].chars();
+                while let Some(c) = chars.next() {
+                    match c {
+                        'h' => {
+                            print_help(&prg_name);
+                            std::process::exit(0)
+                        }
+                        'e' => export = true,
+                        'n' => dry_run = true,
+                        's' => shadow = true,
+                        'R' => {
+                            chroot = Some(operand(&prg_name, "-R", chars.collect(), &mut args));
+                            break;
+                        }
+                        v => {
+                            eprintln!("{}: Unrecognized option -{}", prg_name, v);
+                            std::process::exit(2)
+                        }
+                    }
+                }
+            }
+            x => {
+                eprintln!("{}: Unexpected argument {}", prg_name, x);
+                std::process::exit(2)
+            }
+        }
+    }
+
+    if !dry_run && unsafe { libc::getuid() } != 0 {
+        eprintln!("{}: Permission Denied", prg_name);
+        std::process::exit(1)
+    }
+
+    if shadow && !export {
+        eprintln!("{}: --shadow requires --export", prg_name);
+        std::process::exit(2)
+    }
+
+    let chroot = chroot.map(PathBuf::from);
+    let plan = if export {
+        Database::from_dirs(chroot.as_deref()).and_then(|(db, warnings)| {
+            let mut plan = db.plan_export(chroot.as_deref(), shadow)?;
+            plan.warnings = warnings;
+            Ok(plan)
+        })
+    } else {
+        match &chroot {
+            Some(chroot) => Database::read_in(chroot),
+            None => Database::read(),
+        }
+        .and_then(|db| db.plan_import(chroot.as_deref()))
+    };
+    let plan = match plan {
+        Ok(plan) => plan,
+        Err(e) => {
+            eprintln!("{}: {}", prg_name, e);
+            std::process::exit(3)
+        }
+    };
+
+    for warning in &plan.warnings {
+        eprintln!("{}: Warning: {}", prg_name, warning);
+    }
+
+    if dry_run {
+        for change in &plan.changes {
+            println!("{}", change);
+        }
+        std::process::exit(0)
+    }
+
+    if let Err(e) = plan.apply() {
+        eprintln!(
+            "{}: Failed to {}, {}",
+            prg_name,
+            if export { "export" } else { "import" },
+            e
+        );
+        std::process::exit(3)
+    }
+}
@@ -1,8 +1,18 @@
-use std::io::Write;
+use std::{
+    io::{ErrorKind, Read, Write},
+    path::Path,
+    time::{Duration, SystemTime},
+};
 
-use bytemuck::{Pod, Zeroable};
+use openssl::{hash::hash, hash::MessageDigest, pkey::PKey, sign::Signer};
 use zeroize::Zeroizing;
 
+use crate::Error;
+
+pub mod crypt;
+
+pub mod policy;
+
 pub mod algorithms {
     pub const SHA_224: u8 = 0;
     pub const SHA_256: u8 = 1;
@@ -15,10 +25,24 @@ pub mod algorithms {
     pub const SHA_512_256: u8 = 7;
     #[cfg(feature = "sha3")]
     pub const USE_SHA3: u8 = 8;
+    #[cfg(feature = "sha3")]
+    pub const SHA3_224: u8 = USE_SHA3 | SHA_224;
+    #[cfg(feature = "sha3")]
+    pub const SHA3_256: u8 = USE_SHA3 | SHA_256;
+    #[cfg(feature = "sha3")]
+    pub const SHA3_384: u8 = USE_SHA3 | SHA_384;
+    #[cfg(feature = "sha3")]
+    pub const SHA3_512: u8 = USE_SHA3 | SHA_512;
 
     #[cfg(feature = "blake2")]
     pub const BLAKE2B: u8 = 16;
 
+    pub const ARGON2ID: u8 = 32;
+    pub const SCRYPT: u8 = 33;
+
+    /// A crypt(3) string carried over from a shadow file, see [`super::crypt`]
+    pub const CRYPT: u8 = 64;
+
     pub const DISABLED: u8 = 0xFF;
 }
 
@@ -33,8 +57,10 @@ pub mod salting {
     pub const ROUNDS_MASK: u8 = 0xE0;
 }
 
-#[derive(Pod, Zeroable, Copy, Clone, Debug)]
-#[repr(C)]
+/// The fixed-size header at the start of every password file.
+///
+/// On disk, the fields are stored in declaration order, little-endian, with no padding.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
 pub struct PasswordHeader {
     pub version: u16,
     pub algorithm: u8,
@@ -55,7 +81,121 @@ impl Default for PasswordHeader {
     }
 }
 
-pub const CURRENT_VERSION: u16 = 0;
+impl PasswordHeader {
+    pub const SIZE: usize = 16;
+
+    pub fn from_bytes(bytes: &[u8; Self::SIZE]) -> Self {
+        Self {
+            version: u16::from_le_bytes([bytes[0], bytes[1]]),
+            algorithm: bytes[2],
+            salt_and_repetition: bytes[3],
+            salt_size: u32::from_le_bytes([bytes[4], bytes[5], bytes[6], bytes[7]]),
+            expiry_seconds: u64::from_le_bytes([
+                bytes[8], bytes[9], bytes[10], bytes[11], bytes[12], bytes[13], bytes[14],
+                bytes[15],
+            ]),
+        }
+    }
+
+    pub fn to_bytes(&self) -> [u8; Self::SIZE] {
+        let mut bytes = [0u8; Self::SIZE];
+        bytes[0..2].copy_from_slice(&self.version.to_le_bytes());
+        bytes[2] = self.algorithm;
+        bytes[3] = self.salt_and_repetition;
+        bytes[4..8].copy_from_slice(&self.salt_size.to_le_bytes());
+        bytes[8..16].copy_from_slice(&self.expiry_seconds.to_le_bytes());
+        bytes
+    }
+
+    pub fn read<R: Read>(mut r: R) -> crate::Result<Self> {
+        let mut bytes = [0u8; Self::SIZE];
+        r.read_exact(&mut bytes)?;
+        Ok(Self::from_bytes(&bytes))
+    }
+
+    /// Checks whether this header marks a disabled password
+    pub fn is_disabled(&self) -> bool {
+        self.algorithm == algorithms::DISABLED
+            || self.salt_and_repetition & salting::MASK == salting::DISABLED
+    }
+}
+
+/// Cost parameters for the memory-hard algorithms, stored directly after a [`KDF_VERSION`] header.
+///
+/// For [`algorithms::ARGON2ID`], `memory_cost` is in KiB, `time_cost` is the number of passes and
+/// `parallelism` is the number of lanes.
+/// For [`algorithms::SCRYPT`], `memory_cost` is log2 of N, `time_cost` is the block size r and
+/// `parallelism` is p.
+///
+/// On disk, the fields are stored in declaration order as little-endian `u32`s.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct KdfParameters {
+    pub memory_cost: u32,
+    pub time_cost: u32,
+    pub parallelism: u32,
+    pub output_size: u32,
+}
+
+impl KdfParameters {
+    pub const SIZE: usize = 16;
+
+    pub fn from_bytes(bytes: &[u8; Self::SIZE]) -> Self {
+        let word =
+            |i: usize| u32::from_le_bytes([bytes[i], bytes[i + 1], bytes[i + 2], bytes[i + 3]]);
+        Self {
+            memory_cost: word(0),
+            time_cost: word(4),
+            parallelism: word(8),
+            output_size: word(12),
+        }
+    }
+
+    pub fn to_bytes(&self) -> [u8; Self::SIZE] {
+        let mut bytes = [0u8; Self::SIZE];
+        bytes[0..4].copy_from_slice(&self.memory_cost.to_le_bytes());
+        bytes[4..8].copy_from_slice(&self.time_cost.to_le_bytes());
+        bytes[8..12].copy_from_slice(&self.parallelism.to_le_bytes());
+        bytes[12..16].copy_from_slice(&self.output_size.to_le_bytes());
+        bytes
+    }
+
+    pub fn read<R: Read>(mut r: R) -> crate::Result<Self> {
+        let mut bytes = [0u8; Self::SIZE];
+        r.read_exact(&mut bytes)?;
+        Ok(Self::from_bytes(&bytes))
+    }
+}
+
+/// The largest [`KdfParameters::output_size`] that is accepted, in bytes
+pub const MAX_KDF_OUTPUT_SIZE: u32 = 1024;
+
+/// The most memory that [`algorithms::ARGON2ID`] or [`algorithms::SCRYPT`] may be asked to use, in KiB (2 GiB, the largest cost recommended by RFC 9106)
+pub const MAX_KDF_MEMORY: u32 = 2 * 1024 * 1024;
+
+pub const DEFAULT_ARGON2ID_PARAMETERS: KdfParameters = KdfParameters {
+    memory_cost: 19 * 1024,
+    time_cost: 2,
+    parallelism: 1,
+    output_size: 32,
+};
+
+pub const DEFAULT_SCRYPT_PARAMETERS: KdfParameters = KdfParameters {
+    memory_cost: 15,
+    time_cost: 8,
+    parallelism: 1,
+    output_size: 32,
+};
+
+/// Header version of the password files written before [`CURRENT_VERSION`], which are still verified as they were written.
+///
+/// Their round count was taken from the low three bits of `salt_and_repetition`, which overlap the salting method, rather than from [`salting::ROUNDS_MASK`]. See [`rounds`].
+pub const LEGACY_VERSION: u16 = 0;
+
+/// Header version used by [`algorithms::ARGON2ID`] and [`algorithms::SCRYPT`] files, which carry a [`KdfParameters`]
+pub const KDF_VERSION: u16 = 1;
+
+/// Header version of new password files
+pub const CURRENT_VERSION: u16 = 2;
 
 pub const INVALID_VERSION: u16 = 0xFFFF;
 
@@ -63,65 +203,1050 @@ pub const DEFAULT_ALGORITHM: u8 = algorithms::SHA_512;
 pub const DEFAULT_SALT: u8 = salting::CONCAT;
 pub const DEFAULT_ROUNDS: u8 = 4 << 5;
 
+#[cfg(any(feature = "sha512_t", feature = "blake2"))]
+fn named_digest(name: &str) -> crate::Result<MessageDigest> {
+    MessageDigest::from_name(name).ok_or_else(|| {
+        Error::Io(std::io::Error::new(
+            ErrorKind::Unsupported,
+            format!("{} is not supported by the system OpenSSL", name),
+        ))
+    })
+}
+
+fn message_digest(algorithm: u8) -> crate::Result<MessageDigest> {
+    match algorithm {
+        algorithms::SHA_224 => Ok(MessageDigest::sha224()),
+        algorithms::SHA_256 => Ok(MessageDigest::sha256()),
+        algorithms::SHA_384 => Ok(MessageDigest::sha384()),
+        algorithms::SHA_512 => Ok(MessageDigest::sha512()),
+        #[cfg(feature = "sha512_t")]
+        algorithms::SHA_512_224 => named_digest("SHA512-224"),
+        #[cfg(feature = "sha512_t")]
+        algorithms::SHA_512_256 => named_digest("SHA512-256"),
+        #[cfg(feature = "sha3")]
+        algorithms::SHA3_224 => Ok(MessageDigest::sha3_224()),
+        #[cfg(feature = "sha3")]
+        algorithms::SHA3_256 => Ok(MessageDigest::sha3_256()),
+        #[cfg(feature = "sha3")]
+        algorithms::SHA3_384 => Ok(MessageDigest::sha3_384()),
+        #[cfg(feature = "sha3")]
+        algorithms::SHA3_512 => Ok(MessageDigest::sha3_512()),
+        #[cfg(feature = "blake2")]
+        algorithms::BLAKE2B => named_digest("BLAKE2b512"),
+        _ => Err(Error::Corrupt("Unknown password algorithm")),
+    }
+}
+
+/// The number of rounds that a password file of header version `version` is hashed with.
+///
+/// This is 2^(10 + n), where n is the 3-bit field of `salt_and_repetition` in [`salting::ROUNDS_MASK`].
+/// For [`LEGACY_VERSION`] files, n is instead the low three bits of `salt_and_repetition`, as that is how they were written.
+pub fn rounds(version: u16, salt_and_repetition: u8) -> u32 {
+    let n = if version == LEGACY_VERSION {
+        salt_and_repetition & (salting::ROUNDS_MASK >> salting::ROUNDS_SHIFT)
+    } else {
+        (salt_and_repetition & salting::ROUNDS_MASK) >> salting::ROUNDS_SHIFT
+    };
+    1 << (10 + n)
+}
+
+/// Hashes `passwd` as a [`CURRENT_VERSION`] password file with `algorithm` and `salt_and_repetition`
 pub fn write_password<W: Write>(
     passwd: &str,
     salt: &[u8],
     algorithm: u8,
     salt_and_repetition: u8,
+    w: W,
+) -> crate::Result<()> {
+    hash_rounds(
+        passwd,
+        salt,
+        algorithm,
+        salt_and_repetition,
+        rounds(CURRENT_VERSION, salt_and_repetition),
+        w,
+    )
+}
+
+fn hash_rounds<W: Write>(
+    passwd: &str,
+    salt: &[u8],
+    algorithm: u8,
+    salt_and_repetition: u8,
+    rounds: u32,
     mut w: W,
-) -> std::io::Result<()> {
+) -> crate::Result<()> {
     let salt_method = salt_and_repetition & salting::MASK;
-    let rounds =
-        1u32 << (10 + (salt_and_repetition & salting::ROUNDS_MASK >> salting::ROUNDS_SHIFT));
-    let mut input = passwd.as_bytes();
-    let mut output = Zeroizing::new([0u8; 64]);
+    let digest = message_digest(algorithm)?;
+    if salt.is_empty() && salt_method != salting::CONCAT {
+        return Err(Error::Corrupt("Empty password salt"));
+    }
+    let hmac_key = match salt_method {
+        salting::XOR | salting::CONCAT => None,
+        salting::HMAC => Some(PKey::hmac(salt).map_err(std::io::Error::other)?),
+        _ => return Err(Error::Corrupt("Unsupported salting method")),
+    };
+    let mut input = Zeroizing::new(passwd.as_bytes().to_vec());
     for _ in 0..rounds {
-        let size = input.len() + (32 - (input.len() % 32)) % 32 + salt.len();
-        let mut bytes = Zeroizing::new(Vec::with_capacity(size));
-        bytes.extend_from_slice(input);
-        match salt_method {
-            salting::XOR => {
-                for i in 0..bytes.len() {
-                    bytes[i] ^= salt[i % salt.len()];
+        let mut output = Zeroizing::new(vec![0u8; digest.size()]);
+        if let Some(key) = &hmac_key {
+            // The salt is the HMAC key, so each round is HMAC(salt, previous round)
+            let mut signer = Signer::new(digest, key).map_err(std::io::Error::other)?;
+            signer.update(&input).map_err(std::io::Error::other)?;
+            signer.sign(&mut output).map_err(std::io::Error::other)?;
+        } else {
+            let size = input.len() + (32 - (input.len() % 32)) % 32 + salt.len();
+            let mut bytes = Zeroizing::new(Vec::with_capacity(size));
+            bytes.extend_from_slice(&input);
+            if salt_method == salting::XOR {
+                for (i, b) in bytes.iter_mut().enumerate() {
+                    *b ^= salt[i % salt.len()];
                 }
-            }
-            salting::CONCAT => {
+            } else {
                 bytes.extend_from_slice(salt);
             }
-            salting::HMAC => todo!("hmac is not implemented yet"),
-            _ => panic!("Unsupported algorithm"),
-        }
-        let mut output: &mut [u8] = &mut *output;
-        match algorithm {
-            algorithms::SHA_224 => {
-                output = &mut output[0..28];
-                output.copy_from_slice(&openssl::sha::sha224(&bytes))
+            output.copy_from_slice(&hash(digest, &bytes).map_err(std::io::Error::other)?);
+        }
+        input = output;
+    }
+    w.write_all(&input)?;
+
+    Ok(())
+}
+
+/// Reads the header (and [`KdfParameters`], if any) that new passwords are hashed with, from `authtemplate` in the sysconfdir.
+///
+/// If there is no authtemplate, the defaults are used.
+pub fn read_template() -> crate::Result<(PasswordHeader, Option<KdfParameters>)> {
+    let mut authtemplate = crate::dirs::INSTALL_DIRS.sysconfdir.clone();
+    authtemplate.push("authtemplate");
+    match std::fs::File::open(authtemplate) {
+        Ok(mut f) => {
+            let header = PasswordHeader::read(&mut f)?;
+            if header.salt_size == 0 {
+                return Err(Error::Corrupt("authtemplate has an empty salt"));
+            }
+            let params = if header.version == KDF_VERSION {
+                Some(KdfParameters::read(&mut f)?)
+            } else {
+                None
+            };
+            Ok((header, params))
+        }
+        Err(e) if e.kind() == ErrorKind::NotFound => Ok((
+            PasswordHeader {
+                version: CURRENT_VERSION,
+                algorithm: DEFAULT_ALGORITHM,
+                salt_and_repetition: DEFAULT_SALT | DEFAULT_ROUNDS,
+                salt_size: 31,
+                expiry_seconds: 0,
+            },
+            None,
+        )),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Hashes `passwd` with the memory-hard `algorithm` and its cost parameters `params`.
+///
+/// As the parameters come from the password file, they are bounded by [`MAX_KDF_OUTPUT_SIZE`] and [`MAX_KDF_MEMORY`], and [`Error::Corrupt`] is returned above those limits.
+pub fn write_kdf_password<W: Write>(
+    passwd: &str,
+    salt: &[u8],
+    algorithm: u8,
+    params: &KdfParameters,
+    mut w: W,
+) -> crate::Result<()> {
+    if params.output_size > MAX_KDF_OUTPUT_SIZE {
+        return Err(Error::Corrupt("Password hash is too long"));
+    }
+    let mut output = Zeroizing::new(vec![0u8; params.output_size as usize]);
+    match algorithm {
+        algorithms::ARGON2ID => {
+            if params.memory_cost > MAX_KDF_MEMORY {
+                return Err(Error::Corrupt("Argon2 memory cost is too high"));
+            }
+            let params = argon2::Params::new(
+                params.memory_cost,
+                params.time_cost,
+                params.parallelism,
+                Some(params.output_size as usize),
+            )
+            .map_err(|_| Error::Corrupt("Invalid Argon2 parameters"))?;
+            argon2::Argon2::new(argon2::Algorithm::Argon2id, argon2::Version::V0x13, params)
+                .hash_password_into(passwd.as_bytes(), salt, &mut output)
+                .map_err(|_| Error::Corrupt("Invalid Argon2 parameters"))?;
+        }
+        algorithms::SCRYPT => {
+            let n = 1u64
+                .checked_shl(params.memory_cost)
+                .ok_or(Error::Corrupt("Invalid scrypt parameters"))?;
+            let r = u64::from(params.time_cost);
+            let p = u64::from(params.parallelism);
+            // OpenSSL needs room for both the N-sized V array and the p-sized B array
+            let maxmem = (n + 2 + p)
+                .checked_mul(128 * r)
+                .ok_or(Error::Corrupt("Invalid scrypt parameters"))?;
+            if maxmem > u64::from(MAX_KDF_MEMORY) * 1024 {
+                return Err(Error::Corrupt("scrypt memory cost is too high"));
+            }
+            openssl::pkcs5::scrypt(passwd.as_bytes(), salt, n, r, p, maxmem, &mut output)
+                .map_err(std::io::Error::other)?;
+        }
+        _ => return Err(Error::Corrupt("Unknown password algorithm")),
+    }
+    Ok(w.write_all(&output)?)
+}
+
+/// Hashes `passwd` with the algorithm selected by `header`, using either [`write_password`] or [`write_kdf_password`]
+pub fn derive_password<W: Write>(
+    passwd: &str,
+    salt: &[u8],
+    header: &PasswordHeader,
+    params: Option<&KdfParameters>,
+    w: W,
+) -> crate::Result<()> {
+    match params {
+        Some(params) => write_kdf_password(passwd, salt, header.algorithm, params, w),
+        None => hash_rounds(
+            passwd,
+            salt,
+            header.algorithm,
+            header.salt_and_repetition,
+            rounds(header.version, header.salt_and_repetition),
+            w,
+        ),
+    }
+}
+
+/// Checks `passwd` against the stored hash `expected`, which was produced with `salt` and the algorithm selected by `header`
+pub fn verify_password(
+    passwd: &str,
+    salt: &[u8],
+    header: &PasswordHeader,
+    params: Option<&KdfParameters>,
+    expected: &[u8],
+) -> crate::Result<bool> {
+    if header.algorithm == algorithms::CRYPT {
+        let hash =
+            std::str::from_utf8(expected).map_err(|_| Error::Corrupt("Non-UTF8 crypt(3) hash"))?;
+        return crypt::verify(passwd, hash);
+    }
+    let mut checked = Zeroizing::new(Vec::new());
+    derive_password(passwd, salt, header, params, &mut *checked)?;
+    Ok(checked.len() == expected.len() && openssl::memcmp::eq(expected, &checked))
+}
+
+/// A single hashed password, as stored in a password file
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PasswordRecord {
+    /// The header version, which is [`KDF_VERSION`] exactly when `params` is present
+    pub version: u16,
+    pub algorithm: u8,
+    pub salt_and_repetition: u8,
+    /// The cost parameters, present exactly when the record uses [`KDF_VERSION`]
+    pub params: Option<KdfParameters>,
+    /// The time after which the password must be changed, if any
+    pub expiry: Option<SystemTime>,
+    pub salt: Vec<u8>,
+    pub hash: Vec<u8>,
+}
+
+impl PasswordRecord {
+    /// Hashes `passwd` with a fresh random salt, using the algorithm and salt size from `template`.
+    ///
+    /// The record is always written as [`CURRENT_VERSION`] or [`KDF_VERSION`], whatever the version of `template`.
+    pub fn new(
+        passwd: &str,
+        template: &PasswordHeader,
+        params: Option<KdfParameters>,
+    ) -> crate::Result<Self> {
+        if template.salt_size == 0 {
+            return Err(Error::Corrupt("Empty password salt"));
+        }
+        let mut salt = vec![0u8; template.salt_size as usize];
+        openssl::rand::rand_bytes(&mut salt).map_err(std::io::Error::other)?;
+        let mut record = Self {
+            version: if params.is_some() {
+                KDF_VERSION
+            } else {
+                CURRENT_VERSION
+            },
+            algorithm: template.algorithm,
+            salt_and_repetition: template.salt_and_repetition,
+            params,
+            expiry: None,
+            salt,
+            hash: Vec::new(),
+        };
+        let mut hash = Vec::new();
+        derive_password(
+            passwd,
+            &record.salt,
+            &record.header(),
+            record.params.as_ref(),
+            &mut hash,
+        )?;
+        record.hash = hash;
+        Ok(record)
+    }
+
+    /// Wraps a crypt(3) string, see [`algorithms::CRYPT`]
+    pub fn crypt(hash: &str) -> crate::Result<Self> {
+        if !crypt::is_supported(hash) {
+            return Err(Error::Io(std::io::Error::new(
+                ErrorKind::InvalidInput,
+                "Unsupported crypt(3) hash method",
+            )));
+        }
+        Ok(Self {
+            version: CURRENT_VERSION,
+            algorithm: algorithms::CRYPT,
+            salt_and_repetition: 0,
+            params: None,
+            expiry: None,
+            salt: Vec::new(),
+            hash: hash.as_bytes().to_vec(),
+        })
+    }
+
+    pub fn header(&self) -> PasswordHeader {
+        PasswordHeader {
+            version: self.version,
+            algorithm: self.algorithm,
+            salt_and_repetition: self.salt_and_repetition,
+            salt_size: self.salt.len() as u32,
+            expiry_seconds: self
+                .expiry
+                .and_then(|t| t.duration_since(SystemTime::UNIX_EPOCH).ok())
+                .map_or(0, |d| d.as_secs()),
+        }
+    }
+
+    /// Checks whether any password can match this record.
+    ///
+    /// Records holding a crypt(3) placeholder, like the `!` of [`PasswordFile::locked`] or one imported from a shadow file, cannot.
+    pub fn is_usable(&self) -> bool {
+        self.algorithm != algorithms::CRYPT
+            || std::str::from_utf8(&self.hash).is_ok_and(crypt::is_supported)
+    }
+
+    /// Checks `passwd` against this record
+    pub fn verify(&self, passwd: &str) -> crate::Result<bool> {
+        verify_password(
+            passwd,
+            &self.salt,
+            &self.header(),
+            self.params.as_ref(),
+            &self.hash,
+        )
+    }
+
+    /// Checks whether the password has expired at `now`
+    pub fn is_expired(&self, now: SystemTime) -> bool {
+        matches!(self.expiry, Some(expiry) if expiry <= now)
+    }
+
+    fn parse(header: &PasswordHeader, mut bytes: &[u8]) -> crate::Result<Self> {
+        let params = match header.version {
+            LEGACY_VERSION | CURRENT_VERSION => None,
+            KDF_VERSION => Some(
+                KdfParameters::read(&mut bytes)
+                    .map_err(|_| Error::Corrupt("Truncated password file"))?,
+            ),
+            _ => return Err(Error::Corrupt("Unsupported password file version")),
+        };
+        let salt_size = header.salt_size as usize;
+        if bytes.len() < salt_size {
+            return Err(Error::Corrupt("Truncated password file"));
+        }
+        let (salt, hash) = bytes.split_at(salt_size);
+        Ok(Self {
+            version: header.version,
+            algorithm: header.algorithm,
+            salt_and_repetition: header.salt_and_repetition,
+            params,
+            expiry: match header.expiry_seconds {
+                0 => None,
+                secs => Some(SystemTime::UNIX_EPOCH + Duration::from_secs(secs)),
+            },
+            salt: salt.to_vec(),
+            hash: hash.to_vec(),
+        })
+    }
+
+    fn write<W: Write>(&self, mut w: W) -> crate::Result<()> {
+        w.write_all(&self.header().to_bytes())?;
+        if let Some(params) = &self.params {
+            w.write_all(&params.to_bytes())?;
+        }
+        w.write_all(&self.salt)?;
+        Ok(w.write_all(&self.hash)?)
+    }
+}
+
+/// The state of a [`PasswordFile`] at a given time
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum PasswordState {
+    Enabled,
+    Expired,
+    Disabled,
+}
+
+/// A parsed password file.
+///
+/// An enabled file is a [`PasswordHeader`], the [`KdfParameters`] for [`KDF_VERSION`] headers, the salt, and then the hash, which is the rest of the file.
+/// A disabled file is a header with [`algorithms::DISABLED`], followed by the enabled file it replaced, so that it can be restored.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum PasswordFile {
+    Enabled(PasswordRecord),
+    Disabled(PasswordRecord),
+}
+
+impl PasswordFile {
+    /// A disabled file with no password to restore, which new accounts get until a password is set.
+    ///
+    /// The record is the crypt(3) placeholder `!`, as in shadow(5), so enabling it does not make any password usable.
+    pub fn locked() -> Self {
+        Self::Disabled(PasswordRecord {
+            version: CURRENT_VERSION,
+            algorithm: algorithms::CRYPT,
+            salt_and_repetition: 0,
+            params: None,
+            expiry: None,
+            salt: Vec::new(),
+            hash: b"!".to_vec(),
+        })
+    }
+
+    pub fn parse(mut bytes: &[u8]) -> crate::Result<Self> {
+        let header = PasswordHeader::read(&mut bytes)
+            .map_err(|_| Error::Corrupt("Truncated password file"))?;
+        if header.version == INVALID_VERSION {
+            return Err(Error::Corrupt("Invalid Authentication File"));
+        }
+        if header.is_disabled() {
+            if !matches!(header.version, LEGACY_VERSION | CURRENT_VERSION) || header.salt_size != 0
+            {
+                return Err(Error::Corrupt("Unsupported password file version"));
             }
-            algorithms::SHA_256 => {
-                output = &mut output[0..32];
-                output.copy_from_slice(&openssl::sha::sha256(&bytes))
+            let inner = PasswordHeader::read(&mut bytes)
+                .map_err(|_| Error::Corrupt("Truncated password file"))?;
+            if inner.version == INVALID_VERSION || inner.is_disabled() {
+                return Err(Error::Corrupt("Invalid Authentication File"));
             }
-            algorithms::SHA_384 => {
-                output = &mut output[0..48];
-                output.copy_from_slice(&openssl::sha::sha384(&bytes))
+            Ok(Self::Disabled(PasswordRecord::parse(&inner, bytes)?))
+        } else {
+            Ok(Self::Enabled(PasswordRecord::parse(&header, bytes)?))
+        }
+    }
+
+    pub fn read<R: Read>(mut r: R) -> crate::Result<Self> {
+        let mut bytes = Vec::new();
+        r.read_to_end(&mut bytes)?;
+        Self::parse(&bytes)
+    }
+
+    pub fn open<P: AsRef<Path>>(path: P) -> crate::Result<Self> {
+        Self::read(std::fs::File::open(path)?)
+    }
+
+    pub fn write<W: Write>(&self, mut w: W) -> crate::Result<()> {
+        match self {
+            Self::Enabled(record) => record.write(w),
+            Self::Disabled(record) => {
+                let disabled_header = PasswordHeader {
+                    version: CURRENT_VERSION,
+                    salt_size: 0,
+                    ..PasswordHeader::default()
+                };
+                w.write_all(&disabled_header.to_bytes())?;
+                record.write(w)
             }
-            algorithms::SHA_512 => {
-                output = &mut output[0..64];
-                output.copy_from_slice(&openssl::sha::sha512(&bytes))
+        }
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        self.write(&mut bytes)
+            .expect("Writing to a Vec does not fail");
+        bytes
+    }
+
+    /// The current record, or the one that will be restored when the password is enabled
+    pub fn record(&self) -> &PasswordRecord {
+        match self {
+            Self::Enabled(record) | Self::Disabled(record) => record,
+        }
+    }
+
+    pub fn record_mut(&mut self) -> &mut PasswordRecord {
+        match self {
+            Self::Enabled(record) | Self::Disabled(record) => record,
+        }
+    }
+
+    pub fn state(&self, now: SystemTime) -> PasswordState {
+        match self {
+            Self::Disabled(_) => PasswordState::Disabled,
+            Self::Enabled(record) if record.is_expired(now) => PasswordState::Expired,
+            Self::Enabled(_) => PasswordState::Enabled,
+        }
+    }
+}
+
+/// Atomically updates the password file at `path`.
+///
+/// `f` is given the current contents, or `None` if there is no file, and returns the new contents, or `None` to leave the file as-is.
+/// The new contents are written to a temporary file next to `path`, which is then renamed over `path`, so readers never observe a partial write.
+/// The update holds an exclusive lock file next to `path`: if another update is in progress, this fails with [`Error::Busy`].
+pub fn update<P, F>(path: P, f: F) -> crate::Result<()>
+where
+    P: AsRef<Path>,
+    F: FnOnce(Option<PasswordFile>) -> crate::Result<Option<PasswordFile>>,
+{
+    crate::atomic::update(path.as_ref(), 0o600, |current| {
+        let current = current
+            .map(|bytes| PasswordFile::parse(&bytes[..]))
+            .transpose()?;
+        Ok(f(current)?.map(|file| file.to_bytes()))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PASSWD: &str = "correct horse battery staple";
+    const SALT: &[u8] = b"lc-login salt";
+
+    fn hex(bytes: &[u8]) -> String {
+        bytes.iter().map(|b| format!("{:02x}", b)).collect()
+    }
+
+    fn hash_hex(algorithm: u8, salt_and_repetition: u8) -> String {
+        let mut out = Vec::new();
+        write_password(PASSWD, SALT, algorithm, salt_and_repetition, &mut out).unwrap();
+        hex(&out)
+    }
+
+    /// Checks the XOR, concatenation and HMAC round functions of `algorithm` at the lowest round count, 2^10
+    fn check_kats(algorithm: u8, xor: &str, concat: &str, hmac: &str) {
+        assert_eq!(hash_hex(algorithm, salting::XOR), xor, "XOR");
+        assert_eq!(hash_hex(algorithm, salting::CONCAT), concat, "CONCAT");
+        assert_eq!(hash_hex(algorithm, salting::HMAC), hmac, "HMAC");
+    }
+
+    #[test]
+    fn sha224_kats() {
+        check_kats(
+            algorithms::SHA_224,
+            "3ba2f32626bf6cbcab4b5a6ae4b9d779a0e3acd1f891292a8479c23b",
+            "a8386bd86826f2e731ba0b7fba8c621dbaa061ecb4f78331e3cd4f0a",
+            "80f8a48e1d447702246142bc60107b9f65a6c4966bbaa3b9d8d4cd50",
+        );
+    }
+
+    #[test]
+    fn sha256_kats() {
+        check_kats(
+            algorithms::SHA_256,
+            "3076624403b7086ef897a7fb8fce60db5171b23453147af19d40429e678ff4c1",
+            "f70fd9ea5ab9bd10b13d9a9a246b32104a2f9c944cc1c6b27665b7a117ccab04",
+            "98c96f3b7a481df75130a2e75cd37da9575ac82e38b9669c9617ea8f6400de25",
+        );
+    }
+
+    #[test]
+    fn sha384_kats() {
+        check_kats(
+            algorithms::SHA_384,
+            "cbdfa1cc30459ab2ceac6ef9046374e3318e04226484393230c94b0ea17c821cfe340502ae59d4929c5af4a78c997a41",
+            "727c403a35d1c0535bf6fad4837e4b2ab8d529849f7628da4b275fb884a9e95a24f94281be10d2d83f7f6f40d0cb6686",
+            "8a303e8a9d7b88f6b423ef26feb4fb80d82d2bb49fd6905cd5bbac14bbf7f94d0708ae33733cc4dd4a818b286ead7aa5",
+        );
+    }
+
+    #[test]
+    fn sha512_kats() {
+        check_kats(
+            algorithms::SHA_512,
+            "66a7008cc7d8453983ad0b688b7616702c8e6c90adb17483824291d118780b4554fd6d98acfb3740809c7b69b9752eea384d4ee364559f855a9f510d964669a6",
+            "3d912f0bcd5aabb92447dbc43065cb2f6eee5d579b96705c3c65795c66781c9f6d520d83372b08bcb2a3abc67f47a2924292a5dfff25290371f38922c0342eee",
+            "397aaeff108e374c19b7b03d086887d89f3fd56e030491657393418ec854fb390016999ecdaef39a75ce3b36438595189bf08db804ab4b4ef723788b91966047",
+        );
+    }
+
+    #[cfg(feature = "sha512_t")]
+    #[test]
+    fn sha512_224_kats() {
+        check_kats(
+            algorithms::SHA_512_224,
+            "54af39dd4658c9f4c31a29c80e19cb46202e3f682d90362ade4b8537",
+            "467999cf417c93da322cc1c68c77d0bb8447b6feb6c7ea500c6f0a68",
+            "019fc9a6e44ad6097ceb68b8663ba821a1654797ef9f63401bad9a4c",
+        );
+    }
+
+    #[cfg(feature = "sha512_t")]
+    #[test]
+    fn sha512_256_kats() {
+        check_kats(
+            algorithms::SHA_512_256,
+            "a52a408ed6a996e7aac0864c14f1c0d07cffb6a47fee59fdd1864c63ece24ad5",
+            "258bd5b48839ca7381404a0730db76bf00e91da92f0277cb84bbcbb27df0b529",
+            "20c2f79e3f6e5c57d79817fa3275db2fb7c366d2d39101d5fb72d7457f20b189",
+        );
+    }
+
+    #[cfg(feature = "sha3")]
+    #[test]
+    fn sha3_encoding() {
+        assert_eq!(algorithms::SHA3_224, 8);
+        assert_eq!(algorithms::SHA3_256, 9);
+        assert_eq!(algorithms::SHA3_384, 10);
+        assert_eq!(algorithms::SHA3_512, 11);
+        // The flag only selects SHA3 for the four SHA-2 sizes
+        assert!(matches!(
+            write_password(
+                PASSWD,
+                SALT,
+                algorithms::USE_SHA3 | 4,
+                salting::CONCAT,
+                Vec::new()
+            ),
+            Err(Error::Corrupt(_))
+        ));
+    }
+
+    #[cfg(feature = "sha3")]
+    #[test]
+    fn sha3_224_kats() {
+        check_kats(
+            algorithms::SHA3_224,
+            "b75bd24627b10502e34ef2c10e220780c0ca4604787761686946e259",
+            "859bdd4ca9b3955f151e74ad2579261d8f1781ff30cecbc5c08f5a7f",
+            "e06fedeb81779947322bb721b9228b3604faec1c0d83be2bbd639c2f",
+        );
+    }
+
+    #[cfg(feature = "sha3")]
+    #[test]
+    fn sha3_256_kats() {
+        check_kats(
+            algorithms::SHA3_256,
+            "3ba02fc810e944520b9d4d00a2af270a10e34a9579e041f34c0e2e1b6e9c1718",
+            "cf54faf39fd84f96ce7f13b4412958fd6feee36515f0cc399f94810d597e761d",
+            "9ff16d7e56a27282adc041501b9a098008c27b7e8c3d9dd2b53651280ed89437",
+        );
+    }
+
+    #[cfg(feature = "sha3")]
+    #[test]
+    fn sha3_384_kats() {
+        check_kats(
+            algorithms::SHA3_384,
+            "32fe2b20762a69f33a2fb0ab7f6bd8fd784ee95c58b3bf82b1b10f9d4392eab210fed3333d07c1fcf09a36b447d9a98b",
+            "711ed52fa6c2f0c665ab7bb9d185f92721da54d5d080378d7b3afc0eb888d47f88b2ebec2b52ca31f87dee39df9c808f",
+            "ac3c0e354fa1f8fcdd430009db05c507ca85c60c847cb1cbc6aeb7087c9ea2823b555d869fb75325f389281d0b859533",
+        );
+    }
+
+    #[cfg(feature = "sha3")]
+    #[test]
+    fn sha3_512_kats() {
+        check_kats(
+            algorithms::SHA3_512,
+            "6863230c49837cc4f7b47eedc562d1d6b99bf77fd6ee9611e557882eee0000321417788130c1d483496ac400784c19b766cb1573423faf86856f6e8e287e4bed",
+            "900a7b7b4e6a185e3a04200614b48cb2b18ccb79e79945a4b0a63520f466bfe516e017d02f5f8ab2926fbfe9831568d4740ec89c158bfc9b9cefd151e12830a6",
+            "be6ebb8014522f18cc9c5ae3613e58d530d6bb850810d399f4febeda1b09683d835cba2555f1838aed532e7b0198c458f68228ae358037fea3b58f7482539e6f",
+        );
+    }
+
+    #[cfg(feature = "blake2")]
+    #[test]
+    fn blake2b_kats() {
+        check_kats(
+            algorithms::BLAKE2B,
+            "e9b6610c71b92a64b7999453364e8ca95f495f435b619fbd99664a204a0dda3cb07a8753cbb592be1e3c4e1e95f62eaa038413caf0c4e4d4750bd70cc8cc42b0",
+            "e0dea473375ab8c00fdeb659541e08d66b951bf279a1ac29b5489f4e4dbfa9aae900cbc9f0e9a79022184ca439924f0f6ca974b39e7872c7f0957cf43fd0f834",
+            "d9fb4281bb51d163dbe17fe52a08304e6d545e37143c2788e344a55b76a4fd808bca1c006b9941532d08dd3f85a966b28d362c7bc58e8c216c0ed4ee66536d73",
+        );
+    }
+
+    #[test]
+    fn empty_salt_is_rejected() {
+        for method in [salting::XOR, salting::HMAC] {
+            let result = write_password(PASSWD, &[], algorithms::SHA_256, method, Vec::new());
+            assert!(matches!(result, Err(Error::Corrupt(_))));
+        }
+        let template = PasswordHeader {
+            version: CURRENT_VERSION,
+            algorithm: algorithms::SHA_256,
+            salt_and_repetition: salting::HMAC,
+            salt_size: 0,
+            expiry_seconds: 0,
+        };
+        assert!(matches!(
+            PasswordRecord::new(PASSWD, &template, None),
+            Err(Error::Corrupt(_))
+        ));
+    }
+
+    #[test]
+    fn rounds_exclude_salting_bits() {
+        // 2^12 rounds; the salting method must not leak into the round count
+        assert_eq!(
+            hash_hex(
+                algorithms::SHA_256,
+                salting::CONCAT | (2 << salting::ROUNDS_SHIFT)
+            ),
+            "bb5ac4ee9532c68976259cabbf39e5574c5914c7f1adc3cbc066a06a25dcf8b4"
+        );
+    }
+
+    /// A SHA-512 password file for [`PASSWD`], as written by `UserHandle::set_password` before [`CURRENT_VERSION`], with the default template and [`SALT`]
+    const LEGACY_FILE: &str = "000003810d00000000000000000000006c632d6c6f67696e2073616c74\
+        d5d90e91172b99ba60b48ab9ad9dff2d844f5e42b9d1ad1bcc54452222a0c262\
+        39d0ade4e5117d15ad3aa4651ff670a57b76c97b5d0c3c8e33b6e49d56b3716e";
+
+    fn unhex(s: &str) -> Vec<u8> {
+        (0..s.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&s[i..i + 2], 16).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn legacy_file_verifies() {
+        let bytes = unhex(LEGACY_FILE);
+        let file = PasswordFile::parse(&bytes).unwrap();
+        let record = file.record();
+        assert_eq!(record.version, LEGACY_VERSION);
+        assert!(record.verify(PASSWD).unwrap());
+        assert!(!record.verify("Tr0ub4dor&3").unwrap());
+        // Rewriting the file, as expiring or disabling it does, keeps it a legacy file
+        assert_eq!(file.to_bytes(), bytes);
+        let disabled = PasswordFile::Disabled(record.clone());
+        let reparsed = PasswordFile::parse(&disabled.to_bytes()).unwrap();
+        assert!(reparsed.record().verify(PASSWD).unwrap());
+    }
+
+    #[test]
+    fn legacy_rounds() {
+        let default = DEFAULT_SALT | DEFAULT_ROUNDS;
+        assert_eq!(rounds(LEGACY_VERSION, default), 1 << 11);
+        assert_eq!(rounds(CURRENT_VERSION, default), 1 << 14);
+        assert_eq!(rounds(LEGACY_VERSION, salting::HMAC), 1 << 12);
+        assert_eq!(rounds(CURRENT_VERSION, salting::HMAC), 1 << 10);
+    }
+
+    #[test]
+    fn new_records_use_current_version() {
+        let template = PasswordHeader {
+            version: LEGACY_VERSION,
+            algorithm: algorithms::SHA_256,
+            salt_and_repetition: salting::CONCAT,
+            salt_size: 16,
+            expiry_seconds: 0,
+        };
+        let record = PasswordRecord::new(PASSWD, &template, None).unwrap();
+        assert_eq!(record.version, CURRENT_VERSION);
+        let mut expected = Vec::new();
+        write_password(
+            PASSWD,
+            &record.salt,
+            algorithms::SHA_256,
+            salting::CONCAT,
+            &mut expected,
+        )
+        .unwrap();
+        assert_eq!(record.hash, expected);
+    }
+
+    fn sample_record() -> PasswordRecord {
+        PasswordRecord {
+            version: CURRENT_VERSION,
+            algorithm: algorithms::SHA_256,
+            salt_and_repetition: salting::HMAC | (1 << salting::ROUNDS_SHIFT),
+            params: None,
+            expiry: Some(SystemTime::UNIX_EPOCH + Duration::from_secs(0x0102_0304)),
+            salt: b"salt".to_vec(),
+            hash: b"hash".to_vec(),
+        }
+    }
+
+    #[test]
+    fn header_layout_is_little_endian() {
+        let header = PasswordHeader {
+            version: 0x0102,
+            algorithm: 0x03,
+            salt_and_repetition: 0x81,
+            salt_size: 0x0403_0201,
+            expiry_seconds: 0x0807_0605_0403_0201,
+        };
+        let bytes = [
+            0x02, 0x01, 0x03, 0x81, 0x01, 0x02, 0x03, 0x04, 0x01, 0x02, 0x03, 0x04, 0x05, 0x06,
+            0x07, 0x08,
+        ];
+        assert_eq!(header.to_bytes(), bytes);
+        assert_eq!(PasswordHeader::from_bytes(&bytes), header);
+
+        let params = KdfParameters {
+            memory_cost: 0x0403_0201,
+            time_cost: 2,
+            parallelism: 3,
+            output_size: 0x20,
+        };
+        let bytes = [
+            0x01, 0x02, 0x03, 0x04, 0x02, 0, 0, 0, 0x03, 0, 0, 0, 0x20, 0, 0, 0,
+        ];
+        assert_eq!(params.to_bytes(), bytes);
+        assert_eq!(KdfParameters::from_bytes(&bytes), params);
+    }
+
+    #[test]
+    fn file_layout() {
+        let file = PasswordFile::Enabled(sample_record());
+        let bytes = [
+            0x02, 0x00, 0x01, 0x22, 0x04, 0x00, 0x00, 0x00, 0x04, 0x03, 0x02, 0x01, 0x00, 0x00,
+            0x00, 0x00, b's', b'a', b'l', b't', b'h', b'a', b's', b'h',
+        ];
+        assert_eq!(file.to_bytes(), bytes);
+        assert_eq!(PasswordFile::parse(&bytes).unwrap(), file);
+    }
+
+    #[test]
+    fn round_trips() {
+        let mut kdf = sample_record();
+        kdf.version = KDF_VERSION;
+        kdf.algorithm = algorithms::ARGON2ID;
+        kdf.params = Some(DEFAULT_ARGON2ID_PARAMETERS);
+        kdf.expiry = None;
+        for file in [
+            PasswordFile::Enabled(sample_record()),
+            PasswordFile::Disabled(sample_record()),
+            PasswordFile::Enabled(kdf.clone()),
+            PasswordFile::Disabled(kdf),
+            PasswordFile::locked(),
+        ] {
+            assert_eq!(PasswordFile::parse(&file.to_bytes()).unwrap(), file);
+            assert_eq!(PasswordFile::read(&file.to_bytes()[..]).unwrap(), file);
+        }
+    }
+
+    #[test]
+    fn disabled_layout() {
+        let file = PasswordFile::Disabled(sample_record());
+        let bytes = file.to_bytes();
+        let outer = PasswordHeader::read(&bytes[..]).unwrap();
+        assert!(outer.is_disabled());
+        assert_eq!(outer.version, CURRENT_VERSION);
+        assert_eq!(outer.salt_size, 0);
+        // The disabled file wraps the enabled one, so it can be restored as-is
+        assert_eq!(
+            bytes[16..],
+            PasswordFile::Enabled(sample_record()).to_bytes()[..]
+        );
+        assert_eq!(file.state(SystemTime::UNIX_EPOCH), PasswordState::Disabled);
+    }
+
+    #[test]
+    fn unknown_versions_are_rejected() {
+        for version in [3, 0x100, INVALID_VERSION] {
+            let mut bytes = PasswordFile::Enabled(sample_record()).to_bytes();
+            bytes[0..2].copy_from_slice(&u16::to_le_bytes(version));
+            assert!(
+                matches!(PasswordFile::parse(&bytes), Err(Error::Corrupt(_))),
+                "version {:#x} accepted",
+                version
+            );
+            // The same goes for the header of a disabled file, and the one it wraps
+            let mut bytes = PasswordFile::Disabled(sample_record()).to_bytes();
+            bytes[0..2].copy_from_slice(&u16::to_le_bytes(version));
+            assert!(matches!(
+                PasswordFile::parse(&bytes),
+                Err(Error::Corrupt(_))
+            ));
+            let mut bytes = PasswordFile::Disabled(sample_record()).to_bytes();
+            bytes[16..18].copy_from_slice(&u16::to_le_bytes(version));
+            assert!(matches!(
+                PasswordFile::parse(&bytes),
+                Err(Error::Corrupt(_))
+            ));
+        }
+        // A disabled file cannot wrap another disabled file
+        let mut bytes = PasswordFile::Disabled(sample_record()).to_bytes();
+        bytes.splice(16..16, bytes[..16].to_vec());
+        assert!(matches!(
+            PasswordFile::parse(&bytes),
+            Err(Error::Corrupt(_))
+        ));
+    }
+
+    #[test]
+    fn truncated_files_are_rejected() {
+        let mut kdf = sample_record();
+        kdf.version = KDF_VERSION;
+        kdf.params = Some(DEFAULT_SCRYPT_PARAMETERS);
+        for file in [
+            PasswordFile::Enabled(sample_record()),
+            PasswordFile::Disabled(sample_record()),
+            PasswordFile::Enabled(kdf),
+        ] {
+            let bytes = file.to_bytes();
+            // Cutting into the hash only shortens it, anything before that is missing data
+            let hash_start = bytes.len() - 4;
+            for len in 0..hash_start {
+                assert!(
+                    matches!(PasswordFile::parse(&bytes[..len]), Err(Error::Corrupt(_))),
+                    "{:?} parsed at {} bytes",
+                    file,
+                    len
+                );
             }
-            #[cfg(feature = "sha512_t")]
-            algorithms::SHA_512_224 => todo!("lc-login does not yet support SHA-512/224"),
-            #[cfg(feature = "sha512_t")]
-            algorithms::SHA_512_256 => todo!("lc-login does not yet support SHA-512/256"),
-            #[cfg(feature = "sha3")]
-            x if (x & algorithms::USE_SHA3) != 0 => todo!("lc-login does not yet support SHA3"),
-            #[cfg(feature = "blake2")]
-            algorithms::BLAKE2B => todo!("lc-login does not yet support Blake2"),
-            _ => panic!("unknown alogrithm"),
         }
-        input = output;
     }
-    w.write_all(input)?;
 
-    Ok(())
+    fn kdf_hex(algorithm: u8, params: KdfParameters) -> String {
+        let mut out = Vec::new();
+        write_kdf_password(PASSWD, SALT, algorithm, &params, &mut out).unwrap();
+        hex(&out)
+    }
+
+    #[test]
+    fn argon2id_kats() {
+        let params = KdfParameters {
+            memory_cost: 64,
+            time_cost: 2,
+            parallelism: 1,
+            output_size: 32,
+        };
+        assert_eq!(
+            kdf_hex(algorithms::ARGON2ID, params),
+            "43613c7e8f938c10ac15ee00c6d5873f742279691fd6d7c0a36679083717c2dc"
+        );
+        let params = KdfParameters {
+            memory_cost: 256,
+            time_cost: 3,
+            parallelism: 2,
+            output_size: 24,
+        };
+        assert_eq!(
+            kdf_hex(algorithms::ARGON2ID, params),
+            "db638a61b40871aeabecc4591367e5dd92c8aa433d45c1ac"
+        );
+    }
+
+    #[test]
+    fn scrypt_kats() {
+        // From RFC 7914
+        let mut out = Vec::new();
+        let params = KdfParameters {
+            memory_cost: 10,
+            time_cost: 8,
+            parallelism: 16,
+            output_size: 64,
+        };
+        write_kdf_password("password", b"NaCl", algorithms::SCRYPT, &params, &mut out).unwrap();
+        assert_eq!(
+            hex(&out),
+            "fdbabe1c9d3472007856e7190d01e9fe7c6ad7cbc8237830e77376634b3731622eaf30d92e22a3886ff109279d9830dac727afb94a83ee6d8360cbdfa2cc0640"
+        );
+        let params = KdfParameters {
+            memory_cost: 10,
+            time_cost: 8,
+            parallelism: 1,
+            output_size: 32,
+        };
+        assert_eq!(
+            kdf_hex(algorithms::SCRYPT, params),
+            "4346f866deb8f883fd7ef239c485e8e2e415bed886275f61fa1c541a09a83694"
+        );
+    }
+
+    #[test]
+    fn kdf_records_round_trip() {
+        for (algorithm, memory_cost) in [(algorithms::ARGON2ID, 64), (algorithms::SCRYPT, 10)] {
+            let template = PasswordHeader {
+                version: KDF_VERSION,
+                algorithm,
+                salt_and_repetition: 0,
+                salt_size: 16,
+                expiry_seconds: 0,
+            };
+            let params = KdfParameters {
+                memory_cost,
+                time_cost: 1,
+                parallelism: 1,
+                output_size: 32,
+            };
+            let record = PasswordRecord::new(PASSWD, &template, Some(params)).unwrap();
+            assert_eq!(record.version, KDF_VERSION);
+            assert_eq!(record.hash.len(), 32);
+            let file = PasswordFile::parse(&PasswordFile::Enabled(record).to_bytes()).unwrap();
+            assert!(file.record().verify(PASSWD).unwrap());
+            assert!(!file.record().verify("Tr0ub4dor&3").unwrap());
+        }
+    }
+
+    #[test]
+    fn kdf_costs_are_bounded() {
+        let check = |algorithm, params| {
+            let result = write_kdf_password(PASSWD, SALT, algorithm, &params, Vec::new());
+            assert!(
+                matches!(result, Err(Error::Corrupt(_))),
+                "{:?} accepted",
+                params
+            );
+        };
+        let argon2 = KdfParameters {
+            memory_cost: 64,
+            time_cost: 1,
+            parallelism: 1,
+            output_size: 32,
+        };
+        check(
+            algorithms::ARGON2ID,
+            KdfParameters {
+                output_size: u32::MAX,
+                ..argon2
+            },
+        );
+        check(
+            algorithms::ARGON2ID,
+            KdfParameters {
+                memory_cost: MAX_KDF_MEMORY + 1,
+                ..argon2
+            },
+        );
+        let scrypt = KdfParameters {
+            memory_cost: 10,
+            time_cost: 8,
+            parallelism: 1,
+            output_size: 32,
+        };
+        check(
+            algorithms::SCRYPT,
+            KdfParameters {
+                output_size: MAX_KDF_OUTPUT_SIZE + 1,
+                ..scrypt
+            },
+        );
+        // 128 * 2^21 * 8 bytes is 2 GiB, which leaves no room for p
+        check(
+            algorithms::SCRYPT,
+            KdfParameters {
+                memory_cost: 21,
+                ..scrypt
+            },
+        );
+        check(
+            algorithms::SCRYPT,
+            KdfParameters {
+                memory_cost: 64,
+                ..scrypt
+            },
+        );
+    }
 }
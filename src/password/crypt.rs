@@ -0,0 +1,107 @@
+//! Verification of crypt(3) style hashes, as found in shadow files on hosts migrated to lc-login.
+//!
+//! These are only ever verified, never produced. Accounts using them are rehashed on the next password change.
+
+use std::io::ErrorKind;
+
+use crate::Error;
+
+mod yescrypt;
+
+/// The crypt(3) methods that can be verified, identified by the prefix of the hash
+pub const SUPPORTED_PREFIXES: &[&str] = &["$1$", "$5$", "$6$", "$2a$", "$2b$", "$2y$", "$y$"];
+
+/// Checks whether `hash` uses one of the [`SUPPORTED_PREFIXES`]
+pub fn is_supported(hash: &str) -> bool {
+    SUPPORTED_PREFIXES.iter().any(|p| hash.starts_with(p))
+}
+
+/// Verifies `passwd` against the crypt(3) string `hash`.
+///
+/// Fails with [`Error::Corrupt`] if `hash` is malformed, rather than reporting a mismatch.
+// pwhash deprecates the older methods for hashing new passwords, which this never does
+#[allow(deprecated)]
+pub fn verify(passwd: &str, hash: &str) -> crate::Result<bool> {
+    let computed = if hash.starts_with("$1$") {
+        pwhash::md5_crypt::hash_with(hash, passwd)
+    } else if hash.starts_with("$5$") {
+        pwhash::sha256_crypt::hash_with(hash, passwd)
+    } else if hash.starts_with("$6$") {
+        pwhash::sha512_crypt::hash_with(hash, passwd)
+    } else if hash.starts_with("$2a$") || hash.starts_with("$2b$") || hash.starts_with("$2y$") {
+        pwhash::bcrypt::hash_with(hash, passwd)
+    } else if hash.starts_with("$y$") {
+        return yescrypt::crypt(passwd.as_bytes(), hash)
+            .map_err(|_| Error::Corrupt("Invalid yescrypt hash"))
+            .map(|computed| {
+                computed.len() == hash.len()
+                    && openssl::memcmp::eq(computed.as_bytes(), hash.as_bytes())
+            });
+    } else {
+        return Err(Error::Io(std::io::Error::new(
+            ErrorKind::Unsupported,
+            "Unsupported crypt(3) hash method",
+        )));
+    };
+    let computed = computed.map_err(|_| Error::Corrupt("Invalid crypt(3) hash"))?;
+    Ok(computed.len() == hash.len() && openssl::memcmp::eq(computed.as_bytes(), hash.as_bytes()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PASSWD: &str = "correct horse battery staple";
+
+    fn check(hash: &str) {
+        assert!(verify(PASSWD, hash).unwrap(), "{} rejected", hash);
+        assert!(
+            !verify("incorrect horse", hash).unwrap(),
+            "{} accepted",
+            hash
+        );
+    }
+
+    #[test]
+    fn md5_crypt() {
+        check("$1$saltsalt$BsXyQbZiQujHkdhwPwdol.");
+    }
+
+    #[test]
+    fn sha256_crypt() {
+        check("$5$saltsalt$3hGFMknrJ4ZpFPe7XZe397oIMEp7sbvqrcsX/ONJ3i.");
+    }
+
+    #[test]
+    fn sha512_crypt() {
+        check("$6$saltsalt$CPgxBHZBXfhC6lX1yxpdEsbQfXmg3WXVj8AoVwyNFLfb5AtbfM8k6A8yehv1z6sgzoH/DUIs7YK9hVnGhTjhW/");
+    }
+
+    #[test]
+    fn bcrypt() {
+        check("$2b$05$abcdefghijklmnopqrstuuFiPhXf1sVd3pCCRO.uVh34H/qI/ZsuS");
+    }
+
+    #[test]
+    fn yescrypt() {
+        check("$y$j9T$saltsaltsalt$Sq1kqbJbFN7YfV2MqrFeATLs2I0LdD7jIoz3m0GQ2lB");
+    }
+
+    #[test]
+    fn malformed() {
+        for hash in &[
+            "$5$rounds=many$saltsalt$3hGFMknrJ4ZpFPe7XZe397oIMEp7sbvqrcsX/ONJ3i.",
+            "$2b$99$abcdefghijklmnopqrstuuFiPhXf1sVd3pCCRO.uVh34H/qI/ZsuS",
+            "$2b$05$short",
+            "$y$j9T",
+            "$y$!!!$saltsaltsalt$Sq1kqbJbFN7YfV2MqrFeATLs2I0LdD7jIoz3m0GQ2lB",
+        ] {
+            assert!(
+                matches!(verify(PASSWD, hash), Err(Error::Corrupt(_))),
+                "{} was not rejected",
+                hash
+            );
+        }
+        assert!(verify(PASSWD, "$9$unknown").is_err());
+    }
+}
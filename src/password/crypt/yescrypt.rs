@@ -0,0 +1,647 @@
+//! A port of the yescrypt reference implementation, sufficient to verify `$y$` hashes as produced by libxcrypt.
+//!
+//! Only the classic scrypt flavour and the YESCRYPT_RW flavour with the default pwxform settings are supported,
+//! which covers every hash libxcrypt generates. ROM support and hash upgrades are not implemented.
+
+use std::{convert::TryFrom, io::ErrorKind};
+
+use openssl::{hash::MessageDigest, pkey::PKey, sign::Signer};
+use zeroize::Zeroizing;
+
+const ITOA64: &[u8; 64] = b"./0123456789ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz";
+
+const YESCRYPT_RW: u32 = 0x002;
+const YESCRYPT_RW_FLAVOR_MASK: u32 = 0x3fc;
+const YESCRYPT_RW_DEFAULTS: u32 = 0x0b6;
+const YESCRYPT_PREHASH: u32 = 0x1000_0000;
+
+const PWX_SIMPLE: usize = 2;
+const PWX_GATHER: usize = 4;
+const PWX_ROUNDS: usize = 6;
+const PWX_WORDS: usize = PWX_GATHER * PWX_SIMPLE * 2;
+const SWIDTH: usize = 8;
+const S_WORDS: usize = 3 * (1 << SWIDTH) * PWX_SIMPLE * 2;
+const S_BOX_WORDS: usize = (1 << SWIDTH) * PWX_SIMPLE * 2;
+const SMASK: u32 = (((1 << SWIDTH) - 1) * PWX_SIMPLE * 8) as u32;
+
+fn invalid(msg: &'static str) -> std::io::Error {
+    std::io::Error::new(ErrorKind::InvalidData, msg)
+}
+
+fn atoi64(c: u8) -> Option<u32> {
+    ITOA64.iter().position(|&v| v == c).map(|v| v as u32)
+}
+
+fn decode64_uint32(src: &mut &[u8], min: u32) -> std::io::Result<u32> {
+    let (mut start, mut end, mut chars, mut bits) = (0u32, 47u32, 1u32, 0u32);
+    let (&first, rest) = src
+        .split_first()
+        .ok_or_else(|| invalid("Truncated yescrypt parameters"))?;
+    *src = rest;
+    let c = atoi64(first).ok_or_else(|| invalid("Invalid yescrypt parameters"))?;
+    let mut dst = u64::from(min);
+    while c > end {
+        dst += u64::from(end + 1 - start) << bits;
+        start = end + 1;
+        end = start + (62 - end) / 2;
+        chars += 1;
+        bits += 6;
+    }
+    dst += u64::from(c - start) << bits;
+    while chars > 1 {
+        chars -= 1;
+        let (&next, rest) = src
+            .split_first()
+            .ok_or_else(|| invalid("Truncated yescrypt parameters"))?;
+        *src = rest;
+        let c = atoi64(next).ok_or_else(|| invalid("Invalid yescrypt parameters"))?;
+        dst += u64::from(c) << bits;
+        bits += 6;
+    }
+    u32::try_from(dst).map_err(|_| invalid("Invalid yescrypt parameters"))
+}
+
+fn decode64(src: &[u8]) -> std::io::Result<Vec<u8>> {
+    let mut out = Vec::new();
+    for chunk in src.chunks(4) {
+        let mut value = 0u32;
+        let mut bits = 0u32;
+        for &c in chunk {
+            value |= atoi64(c).ok_or_else(|| invalid("Invalid yescrypt salt"))? << bits;
+            bits += 6;
+        }
+        if bits < 12 {
+            return Err(invalid("Invalid yescrypt salt"));
+        }
+        while bits >= 8 {
+            out.push(value as u8);
+            value >>= 8;
+            bits -= 8;
+        }
+        if value != 0 {
+            return Err(invalid("Invalid yescrypt salt"));
+        }
+    }
+    Ok(out)
+}
+
+fn encode64(src: &[u8]) -> String {
+    let mut out = String::new();
+    for chunk in src.chunks(3) {
+        let mut value = 0u32;
+        for (i, &b) in chunk.iter().enumerate() {
+            value |= u32::from(b) << (8 * i);
+        }
+        let mut bits = 0;
+        while bits < 8 * chunk.len() {
+            out.push(ITOA64[(value & 0x3f) as usize] as char);
+            value >>= 6;
+            bits += 6;
+        }
+    }
+    out
+}
+
+fn hmac_sha256(key: &[u8], msg: &[u8]) -> std::io::Result<[u8; 32]> {
+    let key = PKey::hmac(key).map_err(std::io::Error::other)?;
+    let mut signer = Signer::new(MessageDigest::sha256(), &key).map_err(std::io::Error::other)?;
+    signer.update(msg).map_err(std::io::Error::other)?;
+    let mut out = [0u8; 32];
+    signer.sign(&mut out).map_err(std::io::Error::other)?;
+    Ok(out)
+}
+
+fn pbkdf2_sha256(passwd: &[u8], salt: &[u8], out: &mut [u8]) -> std::io::Result<()> {
+    openssl::pkcs5::pbkdf2_hmac(passwd, salt, 1, MessageDigest::sha256(), out)
+        .map_err(std::io::Error::other)
+}
+
+fn salsa20(b: &mut [u32], rounds: usize) {
+    let mut x = [0u32; 16];
+    // The blocks are kept in the SIMD-friendly shuffled order used by the reference implementation
+    for i in 0..16 {
+        x[i * 5 % 16] = b[i];
+    }
+    let input = x;
+    for _ in (0..rounds).step_by(2) {
+        macro_rules! r {
+            ($a:expr, $b:expr, $c:expr, $n:expr) => {
+                x[$a] ^= x[$b].wrapping_add(x[$c]).rotate_left($n)
+            };
+        }
+        r!(4, 0, 12, 7);
+        r!(8, 4, 0, 9);
+        r!(12, 8, 4, 13);
+        r!(0, 12, 8, 18);
+        r!(9, 5, 1, 7);
+        r!(13, 9, 5, 9);
+        r!(1, 13, 9, 13);
+        r!(5, 1, 13, 18);
+        r!(14, 10, 6, 7);
+        r!(2, 14, 10, 9);
+        r!(6, 2, 14, 13);
+        r!(10, 6, 2, 18);
+        r!(3, 15, 11, 7);
+        r!(7, 3, 15, 9);
+        r!(11, 7, 3, 13);
+        r!(15, 11, 7, 18);
+
+        r!(1, 0, 3, 7);
+        r!(2, 1, 0, 9);
+        r!(3, 2, 1, 13);
+        r!(0, 3, 2, 18);
+        r!(6, 5, 4, 7);
+        r!(7, 6, 5, 9);
+        r!(4, 7, 6, 13);
+        r!(5, 4, 7, 18);
+        r!(11, 10, 9, 7);
+        r!(8, 11, 10, 9);
+        r!(9, 8, 11, 13);
+        r!(10, 9, 8, 18);
+        r!(12, 15, 14, 7);
+        r!(13, 12, 15, 9);
+        r!(14, 13, 12, 13);
+        r!(15, 14, 13, 18);
+    }
+    for i in 0..16 {
+        b[i] = x[i * 5 % 16].wrapping_add(input[i * 5 % 16]);
+    }
+}
+
+fn blkxor(dst: &mut [u32], src: &[u32]) {
+    for (d, s) in dst.iter_mut().zip(src) {
+        *d ^= s;
+    }
+}
+
+fn blockmix_salsa8(b: &mut [u32], y: &mut [u32], r: usize) {
+    let mut x = [0u32; 16];
+    x.copy_from_slice(&b[(2 * r - 1) * 16..2 * r * 16]);
+    for i in 0..2 * r {
+        blkxor(&mut x, &b[i * 16..(i + 1) * 16]);
+        salsa20(&mut x, 8);
+        y[i * 16..(i + 1) * 16].copy_from_slice(&x);
+    }
+    for i in 0..r {
+        b[i * 16..(i + 1) * 16].copy_from_slice(&y[(2 * i) * 16..(2 * i + 1) * 16]);
+        b[(i + r) * 16..(i + r + 1) * 16].copy_from_slice(&y[(2 * i + 1) * 16..(2 * i + 2) * 16]);
+    }
+}
+
+/// The pwxform S-boxes, stored as offsets into a single S array of 32-bit words
+struct PwxformCtx<'a> {
+    s: &'a mut [u32],
+    s0: usize,
+    s1: usize,
+    s2: usize,
+    w: usize,
+}
+
+impl PwxformCtx<'_> {
+    fn pwxform(&mut self, b: &mut [u32]) {
+        let mut w = self.w;
+        for i in 0..PWX_ROUNDS {
+            for j in 0..PWX_GATHER {
+                let base = j * PWX_SIMPLE * 2;
+                let p0 = self.s0 + ((b[base] & SMASK) / 4) as usize;
+                let p1 = self.s1 + ((b[base + 1] & SMASK) / 4) as usize;
+                for k in 0..PWX_SIMPLE {
+                    let s0 =
+                        (u64::from(self.s[p0 + 2 * k + 1]) << 32) + u64::from(self.s[p0 + 2 * k]);
+                    let s1 =
+                        (u64::from(self.s[p1 + 2 * k + 1]) << 32) + u64::from(self.s[p1 + 2 * k]);
+                    let xl = b[base + 2 * k];
+                    let xh = b[base + 2 * k + 1];
+                    let x = ((u64::from(xh) * u64::from(xl)).wrapping_add(s0)) ^ s1;
+                    b[base + 2 * k] = x as u32;
+                    b[base + 2 * k + 1] = (x >> 32) as u32;
+                }
+                if i != 0 && i != PWX_ROUNDS - 1 {
+                    for k in 0..PWX_SIMPLE {
+                        self.s[self.s2 + 2 * w] = b[base + 2 * k];
+                        self.s[self.s2 + 2 * w + 1] = b[base + 2 * k + 1];
+                        w += 1;
+                    }
+                }
+            }
+        }
+        let (s0, s1, s2) = (self.s0, self.s1, self.s2);
+        self.s0 = s2;
+        self.s1 = s0;
+        self.s2 = s1;
+        self.w = w & ((1 << SWIDTH) * PWX_SIMPLE - 1);
+    }
+
+    fn blockmix(&mut self, b: &mut [u32], r: usize) {
+        let r1 = 128 * r / (PWX_WORDS * 4);
+        let mut x = [0u32; PWX_WORDS];
+        x.copy_from_slice(&b[(r1 - 1) * PWX_WORDS..r1 * PWX_WORDS]);
+        for i in 0..r1 {
+            if r1 > 1 {
+                blkxor(&mut x, &b[i * PWX_WORDS..(i + 1) * PWX_WORDS]);
+            }
+            self.pwxform(&mut x);
+            b[i * PWX_WORDS..(i + 1) * PWX_WORDS].copy_from_slice(&x);
+        }
+        let mut i = (r1 - 1) * PWX_WORDS / 16;
+        salsa20(&mut b[i * 16..(i + 1) * 16], 2);
+        i += 1;
+        while i < 2 * r {
+            let (prev, cur) = b.split_at_mut(i * 16);
+            blkxor(&mut cur[..16], &prev[(i - 1) * 16..]);
+            salsa20(&mut cur[..16], 2);
+            i += 1;
+        }
+    }
+}
+
+fn integerify(x: &[u32], r: usize) -> u64 {
+    let x = &x[(2 * r - 1) * 16..];
+    (u64::from(x[13]) << 32) + u64::from(x[0])
+}
+
+fn p2floor(mut x: u64) -> u64 {
+    while x & (x - 1) != 0 {
+        x &= x - 1;
+    }
+    x
+}
+
+fn wrap(x: u64, i: u64) -> u64 {
+    let n = p2floor(i);
+    (x & (n - 1)) + (i - n)
+}
+
+fn shuffle_in(b: &[u32], x: &mut [u32]) {
+    for (xk, bk) in x.chunks_mut(16).zip(b.chunks(16)) {
+        for i in 0..16 {
+            xk[i] = bk[i * 5 % 16];
+        }
+    }
+}
+
+fn shuffle_out(x: &[u32], b: &mut [u32]) {
+    for (xk, bk) in x.chunks(16).zip(b.chunks_mut(16)) {
+        for i in 0..16 {
+            bk[i * 5 % 16] = xk[i];
+        }
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+fn smix1(
+    b: &mut [u32],
+    r: usize,
+    n: u64,
+    flags: u32,
+    v: &mut [u32],
+    xy: &mut [u32],
+    mut ctx: Option<&mut PwxformCtx>,
+) {
+    let s = 32 * r;
+    let (x, y) = xy.split_at_mut(s);
+    shuffle_in(&b[..s], x);
+    for i in 0..n {
+        let i = i as usize;
+        v[i * s..(i + 1) * s].copy_from_slice(x);
+        if flags & YESCRYPT_RW != 0 && i > 1 {
+            let j = wrap(integerify(x, r), i as u64) as usize;
+            blkxor(x, &v[j * s..(j + 1) * s]);
+        }
+        match &mut ctx {
+            Some(ctx) => ctx.blockmix(x, r),
+            None => blockmix_salsa8(x, y, r),
+        }
+    }
+    shuffle_out(x, &mut b[..s]);
+}
+
+#[allow(clippy::too_many_arguments)]
+fn smix2(
+    b: &mut [u32],
+    r: usize,
+    n: u64,
+    nloop: u64,
+    flags: u32,
+    v: &mut [u32],
+    xy: &mut [u32],
+    mut ctx: Option<&mut PwxformCtx>,
+) {
+    if nloop == 0 {
+        return;
+    }
+    let s = 32 * r;
+    let (x, y) = xy.split_at_mut(s);
+    shuffle_in(&b[..s], x);
+    for _ in 0..nloop {
+        let j = (integerify(x, r) & (n - 1)) as usize;
+        blkxor(x, &v[j * s..(j + 1) * s]);
+        if flags & YESCRYPT_RW != 0 {
+            v[j * s..(j + 1) * s].copy_from_slice(x);
+        }
+        match &mut ctx {
+            Some(ctx) => ctx.blockmix(x, r),
+            None => blockmix_salsa8(x, y, r),
+        }
+    }
+    shuffle_out(x, &mut b[..s]);
+}
+
+#[allow(clippy::too_many_arguments)]
+fn smix(
+    b: &mut [u32],
+    r: usize,
+    n: u64,
+    p: u32,
+    t: u32,
+    flags: u32,
+    v: &mut [u32],
+    xy: &mut [u32],
+    s_boxes: &mut [u32],
+    passwd: &mut [u8],
+) -> std::io::Result<()> {
+    let s = 32 * r;
+    let nchunk = n / u64::from(p);
+    let mut nloop_all = nchunk;
+    if flags & YESCRYPT_RW != 0 {
+        if t <= 1 {
+            if t != 0 {
+                nloop_all *= 2;
+            }
+            nloop_all = nloop_all.div_ceil(3);
+        } else {
+            nloop_all *= u64::from(t - 1);
+        }
+    } else if t != 0 {
+        if t == 1 {
+            nloop_all += nloop_all.div_ceil(2);
+        }
+        nloop_all *= u64::from(t);
+    }
+    let mut nloop_rw = 0;
+    if flags & YESCRYPT_RW != 0 {
+        nloop_rw = nloop_all / u64::from(p);
+    }
+    let nchunk = nchunk & !1;
+    let nloop_all = (nloop_all + 1) & !1;
+    let nloop_rw = (nloop_rw + 1) & !1;
+
+    let mut ctxs = Vec::with_capacity(p as usize);
+    let mut rest = &mut *s_boxes;
+    let mut vchunk = 0u64;
+    for i in 0..p as usize {
+        let np = if i < p as usize - 1 {
+            nchunk
+        } else {
+            n - vchunk
+        };
+        let bp = &mut b[i * s..(i + 1) * s];
+        let vp = &mut v[vchunk as usize * s..];
+        let mut ctx = if flags & YESCRYPT_RW != 0 {
+            let (si, tail) = std::mem::take(&mut rest).split_at_mut(S_WORDS);
+            rest = tail;
+            smix1(bp, 1, (S_WORDS * 4 / 128) as u64, 0, si, xy, None);
+            if i == 0 {
+                let mut key = [0u8; 64];
+                for (k, word) in key.chunks_mut(4).zip(&bp[s - 16..]) {
+                    k.copy_from_slice(&word.to_le_bytes());
+                }
+                let updated = hmac_sha256(&key, passwd)?;
+                passwd.copy_from_slice(&updated);
+            }
+            Some(PwxformCtx {
+                s: si,
+                s2: 0,
+                s1: S_BOX_WORDS,
+                s0: 2 * S_BOX_WORDS,
+                w: 0,
+            })
+        } else {
+            None
+        };
+        smix1(bp, r, np, flags, vp, xy, ctx.as_mut());
+        smix2(bp, r, p2floor(np), nloop_rw, flags, vp, xy, ctx.as_mut());
+        ctxs.push(ctx);
+        vchunk += nchunk;
+    }
+    for (i, ctx) in ctxs.iter_mut().enumerate() {
+        smix2(
+            &mut b[i * s..(i + 1) * s],
+            r,
+            n,
+            nloop_all - nloop_rw,
+            flags & !YESCRYPT_RW,
+            v,
+            xy,
+            ctx.as_mut(),
+        );
+    }
+    Ok(())
+}
+
+struct Params {
+    flags: u32,
+    n: u64,
+    r: u32,
+    p: u32,
+    t: u32,
+}
+
+fn kdf_body(
+    passwd: &[u8],
+    salt: &[u8],
+    params: &Params,
+    flags: u32,
+    n: u64,
+    t: u32,
+    out: &mut [u8; 32],
+) -> std::io::Result<()> {
+    let r = params.r as usize;
+    let p = params.p;
+    if flags & YESCRYPT_RW != 0
+        && (flags & YESCRYPT_RW_FLAVOR_MASK) != (YESCRYPT_RW_DEFAULTS & YESCRYPT_RW_FLAVOR_MASK)
+    {
+        return Err(std::io::Error::new(
+            ErrorKind::Unsupported,
+            "Unsupported yescrypt flavour",
+        ));
+    }
+    if r == 0 || p == 0 || n < 2 || n & (n - 1) != 0 || n / u64::from(p) <= 1 {
+        return Err(invalid("Invalid yescrypt parameters"));
+    }
+    let s = 32 * r;
+    let v_len = (s as u64)
+        .checked_mul(n)
+        .and_then(|v| usize::try_from(v).ok())
+        .filter(|v| *v <= (1 << 30))
+        .ok_or_else(|| invalid("yescrypt cost too large"))?;
+    let mut v = Zeroizing::new(vec![0u32; v_len]);
+    let mut b = Zeroizing::new(vec![0u32; s * p as usize]);
+    let mut xy = Zeroizing::new(vec![0u32; 2 * s]);
+    let mut s_boxes = Zeroizing::new(vec![
+        0u32;
+        if flags & YESCRYPT_RW != 0 {
+            S_WORDS * p as usize
+        } else {
+            0
+        }
+    ]);
+
+    let mut sha256 = Zeroizing::new([0u8; 32]);
+    let passwd: &[u8] = if flags != 0 {
+        let key: &[u8] = if flags & YESCRYPT_PREHASH != 0 {
+            b"yescrypt-prehash"
+        } else {
+            b"yescrypt"
+        };
+        *sha256 = hmac_sha256(key, passwd)?;
+        &sha256[..]
+    } else {
+        passwd
+    };
+
+    let mut bytes = Zeroizing::new(vec![0u8; b.len() * 4]);
+    pbkdf2_sha256(passwd, salt, &mut bytes)?;
+    for (word, chunk) in b.iter_mut().zip(bytes.chunks(4)) {
+        *word = u32::from_le_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]);
+    }
+
+    // Classic scrypt keeps using the real password for the final PBKDF2, yescrypt uses the (updated) prehash
+    let mut passwd = Zeroizing::new(if flags != 0 {
+        bytes[..32].to_vec()
+    } else {
+        passwd.to_vec()
+    });
+
+    if flags & YESCRYPT_RW != 0 {
+        smix(
+            &mut b,
+            r,
+            n,
+            p,
+            t,
+            flags,
+            &mut v,
+            &mut xy,
+            &mut s_boxes,
+            &mut passwd,
+        )?;
+    } else {
+        for i in 0..p as usize {
+            smix(
+                &mut b[i * s..(i + 1) * s],
+                r,
+                n,
+                1,
+                t,
+                flags,
+                &mut v,
+                &mut xy,
+                &mut s_boxes,
+                &mut passwd,
+            )?;
+        }
+    }
+
+    for (chunk, word) in bytes.chunks_mut(4).zip(b.iter()) {
+        chunk.copy_from_slice(&word.to_le_bytes());
+    }
+    pbkdf2_sha256(&passwd[..], &bytes, out)?;
+
+    if flags != 0 && flags & YESCRYPT_PREHASH == 0 {
+        let client_key = hmac_sha256(out, b"Client Key")?;
+        *out = openssl::sha::sha256(&client_key);
+    }
+    Ok(())
+}
+
+fn kdf(passwd: &[u8], salt: &[u8], params: &Params) -> std::io::Result<[u8; 32]> {
+    let mut dk = Zeroizing::new([0u8; 32]);
+    let mut passwd = passwd;
+    if params.flags & YESCRYPT_RW != 0
+        && params.p >= 1
+        && params.n / u64::from(params.p) >= 0x100
+        && params.n / u64::from(params.p) * u64::from(params.r) >= 0x20000
+    {
+        kdf_body(
+            passwd,
+            salt,
+            params,
+            params.flags | YESCRYPT_PREHASH,
+            params.n >> 6,
+            0,
+            &mut dk,
+        )?;
+        passwd = &dk[..];
+    }
+    let mut out = [0u8; 32];
+    kdf_body(
+        passwd,
+        salt,
+        params,
+        params.flags,
+        params.n,
+        params.t,
+        &mut out,
+    )?;
+    Ok(out)
+}
+
+/// Computes the yescrypt hash of `passwd` using `setting`, returning the full `$y$` string
+pub fn crypt(passwd: &[u8], setting: &str) -> std::io::Result<String> {
+    let mut src = setting
+        .as_bytes()
+        .strip_prefix(b"$y$")
+        .ok_or_else(|| invalid("Not a yescrypt hash"))?;
+    let flavor = decode64_uint32(&mut src, 0)?;
+    let flags = if flavor < YESCRYPT_RW {
+        flavor
+    } else if flavor <= YESCRYPT_RW + (YESCRYPT_RW_FLAVOR_MASK >> 2) {
+        YESCRYPT_RW + ((flavor - YESCRYPT_RW) << 2)
+    } else {
+        return Err(invalid("Invalid yescrypt flavour"));
+    };
+    let n_log2 = decode64_uint32(&mut src, 1)?;
+    if n_log2 > 63 {
+        return Err(invalid("Invalid yescrypt parameters"));
+    }
+    let mut params = Params {
+        flags,
+        n: 1 << n_log2,
+        r: decode64_uint32(&mut src, 1)?,
+        p: 1,
+        t: 0,
+    };
+    if src.first() != Some(&b'$') {
+        let have = decode64_uint32(&mut src, 1)?;
+        if have & 1 != 0 {
+            params.p = decode64_uint32(&mut src, 2)?;
+        }
+        if have & 2 != 0 {
+            params.t = decode64_uint32(&mut src, 1)?;
+        }
+        if have & 4 != 0 || have & 8 != 0 {
+            return Err(std::io::Error::new(
+                ErrorKind::Unsupported,
+                "yescrypt hash upgrades and ROMs are not supported",
+            ));
+        }
+    }
+    src = src
+        .strip_prefix(b"$")
+        .ok_or_else(|| invalid("Invalid yescrypt parameters"))?;
+    let prefix_len = setting.len() - src.len();
+    let salt_str = match src.iter().rposition(|&c| c == b'$') {
+        Some(pos) => &src[..pos],
+        None => src,
+    };
+    let salt = decode64(salt_str)?;
+
+    let hash = kdf(passwd, &salt, &params)?;
+    Ok(format!(
+        "{}{}${}",
+        &setting[..prefix_len],
+        std::str::from_utf8(salt_str).map_err(|_| invalid("Invalid yescrypt salt"))?,
+        encode64(&hash)
+    ))
+}
@@ -0,0 +1,339 @@
+//! Password quality rules, configured from `password-policy.conf` in the sysconfdir.
+//!
+//! The file consists of `key = value` lines. Blank lines and lines starting with `#` are ignored. The recognized keys are:
+//! * `minlen`: The minimum number of characters in a password
+//! * `minclass`: The minimum number of character classes (lowercase, uppercase, digits and other characters) in a password
+//! * `dictpath`: A file of words, one per line, that may not be used as passwords. Only whole passwords are matched, ignoring case, so a word with a suffix is accepted
+//! * `usercheck`: If nonzero, the password may not contain the user name, forwards or backwards
+//! * `difok`: The minimum number of characters in a new password that do not appear in the old one, ignoring case
+//! * `remember`: The number of previous passwords kept in the user's `password-history`, which may not be reused
+
+use std::{
+    fmt,
+    io::ErrorKind,
+    path::{Path, PathBuf},
+};
+
+use crate::Error;
+
+/// The configured password quality rules. See the [module documentation](self) for the meaning of each field
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Policy {
+    pub min_length: usize,
+    pub min_classes: usize,
+    pub dictionary: Option<PathBuf>,
+    pub user_check: bool,
+    pub min_different: usize,
+    pub remember: usize,
+}
+
+impl Default for Policy {
+    fn default() -> Self {
+        Self {
+            min_length: 8,
+            min_classes: 1,
+            dictionary: None,
+            user_check: true,
+            min_different: 1,
+            remember: 5,
+        }
+    }
+}
+
+/// The rule that a password failed
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Rejection {
+    TooShort { min_length: usize },
+    TooFewClasses { min_classes: usize },
+    InDictionary,
+    SimilarToUsername,
+    SimilarToOldPassword,
+    Reused,
+}
+
+impl fmt::Display for Rejection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::TooShort { min_length } => {
+                write!(f, "The password is shorter than {} characters", min_length)
+            }
+            Self::TooFewClasses { min_classes } => write!(
+                f,
+                "The password contains fewer than {} character classes",
+                min_classes
+            ),
+            Self::InDictionary => f.write_str("The password is a dictionary word"),
+            Self::SimilarToUsername => f.write_str("The password contains the user name"),
+            Self::SimilarToOldPassword => f.write_str("The password is too similar to the old one"),
+            Self::Reused => f.write_str("The password has been used recently"),
+        }
+    }
+}
+
+impl Rejection {
+    /// The key in `password-policy.conf` that configures the failed rule
+    pub fn policy(&self) -> &'static str {
+        match self {
+            Self::TooShort { .. } => "minlen",
+            Self::TooFewClasses { .. } => "minclass",
+            Self::InDictionary => "dictpath",
+            Self::SimilarToUsername => "usercheck",
+            Self::SimilarToOldPassword => "difok",
+            Self::Reused => "remember",
+        }
+    }
+}
+
+impl std::error::Error for Rejection {}
+
+fn parse_value<T: std::str::FromStr>(value: &str) -> crate::Result<T> {
+    value
+        .parse()
+        .map_err(|_| Error::Corrupt("Invalid password policy"))
+}
+
+impl Policy {
+    pub fn parse(s: &str) -> crate::Result<Self> {
+        let mut policy = Self::default();
+        for line in s.lines() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let (key, value) = line
+                .split_once('=')
+                .ok_or(Error::Corrupt("Invalid password policy"))?;
+            let value = value.trim();
+            match key.trim() {
+                "minlen" => policy.min_length = parse_value(value)?,
+                "minclass" => policy.min_classes = parse_value(value)?,
+                "dictpath" => {
+                    policy.dictionary = Some(value).filter(|v| !v.is_empty()).map(PathBuf::from)
+                }
+                "usercheck" => policy.user_check = parse_value::<u32>(value)? != 0,
+                "difok" => policy.min_different = parse_value(value)?,
+                "remember" => policy.remember = parse_value(value)?,
+                _ => return Err(Error::Corrupt("Unknown key in password policy")),
+            }
+        }
+        Ok(policy)
+    }
+
+    /// Checks `passwd` against every rule, returning the first one it fails, if any.
+    ///
+    /// The `username` and `old` password are only checked if they are known.
+    /// Failing to read the dictionary is reported as an error rather than a [`Rejection`].
+    /// This does not check for [`Rejection::Reused`], which needs the user's history, see [`crate::users::UserHandle::check_new_password`].
+    pub fn check(
+        &self,
+        passwd: &str,
+        username: Option<&str>,
+        old: Option<&str>,
+    ) -> crate::Result<Option<Rejection>> {
+        if passwd.chars().count() < self.min_length {
+            return Ok(Some(Rejection::TooShort {
+                min_length: self.min_length,
+            }));
+        }
+
+        let classes = [
+            passwd.chars().any(|c| c.is_lowercase()),
+            passwd.chars().any(|c| c.is_uppercase()),
+            passwd.chars().any(|c| c.is_ascii_digit()),
+            passwd
+                .chars()
+                .any(|c| !c.is_lowercase() && !c.is_uppercase() && !c.is_ascii_digit()),
+        ];
+        if classes.iter().filter(|&&c| c).count() < self.min_classes {
+            return Ok(Some(Rejection::TooFewClasses {
+                min_classes: self.min_classes,
+            }));
+        }
+
+        let lower = passwd.to_lowercase();
+
+        if self.user_check {
+            if let Some(username) = username.filter(|u| !u.is_empty()) {
+                let username = username.to_lowercase();
+                let reversed = username.chars().rev().collect::<String>();
+                if lower.contains(&username) || lower.contains(&reversed) {
+                    return Ok(Some(Rejection::SimilarToUsername));
+                }
+            }
+        }
+
+        if let Some(old) = old {
+            let old_lower = old.to_lowercase();
+            let different = lower.chars().filter(|&c| !old_lower.contains(c)).count();
+            if lower == old_lower || different < self.min_different {
+                return Ok(Some(Rejection::SimilarToOldPassword));
+            }
+        }
+
+        if let Some(dictionary) = &self.dictionary {
+            let words = std::fs::read_to_string(dictionary)?;
+            if words
+                .lines()
+                .map(str::trim)
+                .any(|word| !word.is_empty() && word.to_lowercase() == lower)
+            {
+                return Ok(Some(Rejection::InDictionary));
+            }
+        }
+
+        Ok(None)
+    }
+}
+
+/// Reads the password quality rules from `password-policy.conf` in the sysconfdir.
+///
+/// If there is no such file, the defaults are used.
+pub fn read_policy() -> crate::Result<Policy> {
+    let mut path = crate::dirs::INSTALL_DIRS.sysconfdir.clone();
+    path.push("password-policy.conf");
+    read_policy_from(&path)
+}
+
+fn read_policy_from(path: &Path) -> crate::Result<Policy> {
+    match std::fs::read_to_string(path) {
+        Ok(s) => Policy::parse(&s),
+        Err(e) if e.kind() == ErrorKind::NotFound => Ok(Policy::default()),
+        Err(e) => Err(e.into()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn difok_ignores_case() {
+        let policy = Policy {
+            min_different: 3,
+            ..Policy::default()
+        };
+        // Only the case changed, so no character is new
+        assert_eq!(
+            policy
+                .check("Tr0ub4dor&3x", None, Some("tR0UB4DOR&3X"))
+                .unwrap(),
+            Some(Rejection::SimilarToOldPassword)
+        );
+        assert_eq!(
+            policy
+                .check("Tr0ub4dor&3xyz", None, Some("tR0UB4DOR&3"))
+                .unwrap(),
+            None
+        );
+    }
+
+    #[test]
+    fn dictionary_matches_whole_words_ignoring_case() {
+        let path = std::env::temp_dir().join(format!("lc-login-dict-{}", std::process::id()));
+        std::fs::write(&path, "aardvark\r\nPassword1\n").unwrap();
+        let policy = Policy {
+            dictionary: Some(path.clone()),
+            ..Policy::default()
+        };
+        let check = |passwd| policy.check(passwd, None, None).unwrap();
+        assert_eq!(check("AARDVARK"), Some(Rejection::InDictionary));
+        assert_eq!(check("password1"), Some(Rejection::InDictionary));
+        assert_eq!(check("aardvarks"), None);
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn defaults_without_config() {
+        let dir = tempfile::tempdir().unwrap();
+        let policy = read_policy_from(&dir.path().join("password-policy.conf")).unwrap();
+        assert_eq!(policy, Policy::default());
+        assert_eq!(policy.min_length, 8);
+        assert_eq!(policy.min_classes, 1);
+        assert_eq!(policy.dictionary, None);
+        assert!(policy.user_check);
+        assert_eq!(policy.min_different, 1);
+        assert_eq!(policy.remember, 5);
+        assert_eq!(Policy::parse("# Nothing set\n\n").unwrap(), policy);
+    }
+
+    #[test]
+    fn config_keys() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("password-policy.conf");
+        std::fs::write(
+            &path,
+            "minlen = 12\nminclass=3\n  dictpath = /usr/share/dict/words\nusercheck = 0\ndifok = 4\nremember = 0\n",
+        )
+        .unwrap();
+        assert_eq!(
+            read_policy_from(&path).unwrap(),
+            Policy {
+                min_length: 12,
+                min_classes: 3,
+                dictionary: Some(PathBuf::from("/usr/share/dict/words")),
+                user_check: false,
+                min_different: 4,
+                remember: 0,
+            }
+        );
+        for bad in ["minlen", "minlen = eight", "maxlen = 8", "usercheck = yes"] {
+            assert!(
+                matches!(Policy::parse(bad), Err(Error::Corrupt(_))),
+                "{:?} parsed",
+                bad
+            );
+        }
+    }
+
+    #[test]
+    fn minimum_length() {
+        let policy = Policy::default();
+        assert_eq!(
+            policy.check("Sh0rt!", None, None).unwrap(),
+            Some(Rejection::TooShort { min_length: 8 })
+        );
+        assert_eq!(policy.check("longenough", None, None).unwrap(), None);
+        // Characters are counted, not bytes
+        assert_eq!(
+            policy.check("ümlaut", None, None).unwrap(),
+            Some(Rejection::TooShort { min_length: 8 })
+        );
+        assert_eq!(policy.check("ümlautüü", None, None).unwrap(), None);
+    }
+
+    #[test]
+    fn minimum_classes() {
+        let policy = Policy {
+            min_classes: 3,
+            ..Policy::default()
+        };
+        let rejected = Some(Rejection::TooFewClasses { min_classes: 3 });
+        assert_eq!(policy.check("lowercaseonly", None, None).unwrap(), rejected);
+        assert_eq!(policy.check("lowerUPPER", None, None).unwrap(), rejected);
+        assert_eq!(policy.check("lowerUPPER1", None, None).unwrap(), None);
+        assert_eq!(policy.check("lower123!!", None, None).unwrap(), None);
+        assert_eq!(policy.check("UPPER 1234", None, None).unwrap(), None);
+    }
+
+    #[test]
+    fn user_check() {
+        let policy = Policy::default();
+        let check = |passwd| policy.check(passwd, Some("Alice"), None).unwrap();
+        assert_eq!(check("xxaliCExx"), Some(Rejection::SimilarToUsername));
+        assert_eq!(check("xxecilaxx"), Some(Rejection::SimilarToUsername));
+        assert_eq!(check("xxalicxx"), None);
+        let policy = Policy {
+            user_check: false,
+            ..Policy::default()
+        };
+        assert_eq!(
+            policy.check("xxalicexx", Some("alice"), None).unwrap(),
+            None
+        );
+        // Without a known user name there is nothing to compare
+        assert_eq!(
+            Policy::default().check("xxalicexx", None, None).unwrap(),
+            None
+        );
+    }
+}
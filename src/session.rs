@@ -0,0 +1,231 @@
+//! Starting a shell or command as a user, shared by login, su, newgrp and sg
+
+use std::{
+    collections::HashMap,
+    ffi::{OsStr, OsString},
+    path::{Path, PathBuf},
+};
+
+use unshare::{Command, ExitStatus};
+use zeroize::Zeroizing;
+
+use crate::users::UserHandle;
+
+/// The shell used for users without a `shell` symlink
+pub const DEFAULT_SHELL: &str = "/bin/sh";
+
+/// The `PATH` of a reset environment
+pub const DEFAULT_PATH: &str = "/usr/local/bin:/usr/bin:/bin";
+
+/// The `PATH` of a reset environment for root
+pub const ROOT_PATH: &str = "/usr/local/sbin:/usr/local/bin:/usr/sbin:/usr/bin:/sbin:/bin";
+
+/// How the environment of a [`Session`] is derived from the caller's
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Environment {
+    /// Start from an empty environment, keeping only `TERM`, and set `HOME`, `SHELL`, `USER`, `LOGNAME` and `PATH` for the user
+    Reset,
+    /// Keep the caller's environment, but set `HOME`, `SHELL`, `USER`, `LOGNAME` and `PATH` for the user
+    Update,
+    /// Keep the caller's environment as-is
+    Preserve,
+}
+
+/// A shell, or a command run by a shell, to be started as a user.
+///
+/// The process is set up with [`UserHandle::login`], so it runs with the user's ids and groups, inside the user's `root` if they have one.
+/// [`Session::group`] replaces the group it runs with, as newgrp does.
+pub struct Session<'a> {
+    handle: &'a UserHandle,
+    shell: Option<PathBuf>,
+    command: Option<OsString>,
+    args: Vec<OsString>,
+    login_shell: bool,
+    environment: Environment,
+    env: HashMap<OsString, OsString>,
+    gid: Option<u32>,
+}
+
+impl<'a> Session<'a> {
+    /// Creates a session running the user's interactive shell, with a [`Environment::Reset`] environment
+    pub fn new(handle: &'a UserHandle) -> Self {
+        Self {
+            handle,
+            shell: None,
+            command: None,
+            args: Vec::new(),
+            login_shell: false,
+            environment: Environment::Reset,
+            env: HashMap::new(),
+            gid: None,
+        }
+    }
+
+    /// Runs `shell` instead of the user's shell
+    pub fn shell<P: AsRef<Path>>(&mut self, shell: P) -> &mut Self {
+        self.shell = Some(shell.as_ref().to_path_buf());
+        self
+    }
+
+    /// Runs `command` with the shell's `-c` option, instead of an interactive shell
+    pub fn command<S: AsRef<OsStr>>(&mut self, command: S) -> &mut Self {
+        self.command = Some(command.as_ref().to_os_string());
+        self
+    }
+
+    /// Passes an additional argument to the shell
+    pub fn arg<S: AsRef<OsStr>>(&mut self, arg: S) -> &mut Self {
+        self.args.push(arg.as_ref().to_os_string());
+        self
+    }
+
+    /// Starts a login shell: the shell's argument zero is prefixed with `-`, and it starts in the user's home directory
+    pub fn login_shell(&mut self, login_shell: bool) -> &mut Self {
+        self.login_shell = login_shell;
+        self
+    }
+
+    /// Runs with `gid` as the real and effective group, instead of the user's primary group.
+    ///
+    /// The primary group is kept as a supplementary group, so switching groups does not lose access to it.
+    pub fn group(&mut self, gid: u32) -> &mut Self {
+        self.gid = Some(gid);
+        self
+    }
+
+    pub fn environment(&mut self, environment: Environment) -> &mut Self {
+        self.environment = environment;
+        self
+    }
+
+    /// Sets an environment variable, after the [`Environment`] is applied
+    pub fn env<K: AsRef<OsStr>, V: AsRef<OsStr>>(&mut self, key: K, val: V) -> &mut Self {
+        self.env
+            .insert(key.as_ref().to_os_string(), val.as_ref().to_os_string());
+        self
+    }
+
+    pub fn envs<I, K, V>(&mut self, vars: I) -> &mut Self
+    where
+        I: IntoIterator<Item = (K, V)>,
+        K: AsRef<OsStr>,
+        V: AsRef<OsStr>,
+    {
+        for (key, val) in vars {
+            self.env(key, val);
+        }
+        self
+    }
+
+    /// Builds the command that starts the session
+    pub fn to_command(&self) -> crate::Result<Command> {
+        let home = self.handle.home()?;
+        let shell = match &self.shell {
+            Some(shell) => shell.clone(),
+            None => self
+                .handle
+                .shell()?
+                .unwrap_or_else(|| PathBuf::from(DEFAULT_SHELL)),
+        };
+        let name = self.handle.name()?.unwrap_or_default();
+        let uid = self.handle.uid()?;
+
+        let mut cmd = Command::new(&shell);
+        if self.login_shell {
+            let mut arg0 = OsString::from("-");
+            arg0.push(shell.file_name().unwrap_or(shell.as_os_str()));
+            cmd.arg0(arg0);
+        }
+        if let Some(command) = &self.command {
+            cmd.arg("-c").arg(command);
+        }
+        for arg in &self.args {
+            cmd.arg(arg);
+        }
+
+        if self.environment == Environment::Reset {
+            let term = std::env::var_os("TERM");
+            cmd.env_clear();
+            if let Some(term) = term {
+                cmd.env("TERM", term);
+            }
+        }
+        if self.environment != Environment::Preserve {
+            // The caller's PATH is not trusted, for example in a root shell started by su
+            cmd.env("PATH", if uid == 0 { ROOT_PATH } else { DEFAULT_PATH });
+            cmd.env("HOME", home.as_deref().unwrap_or(Path::new("/")));
+            cmd.env("SHELL", &shell);
+            cmd.env("USER", &name);
+            cmd.env("LOGNAME", &name);
+        }
+        cmd.envs(&self.env);
+
+        // The caller's working directory does not exist inside the user's root
+        if self.login_shell || self.handle.root()?.is_some() {
+            cmd.current_dir(home.as_deref().unwrap_or(Path::new("/")));
+        }
+
+        self.handle.login(&mut cmd)?;
+        if let Some(gid) = self.gid {
+            let mut groups = self.handle.secondary_groups()?;
+            groups.push(self.handle.primary_group()?);
+            groups.push(gid);
+            groups.sort_unstable();
+            groups.dedup();
+            cmd.groups(groups).gid(gid);
+        }
+        Ok(cmd)
+    }
+
+    /// Starts the session and waits for it to finish
+    pub fn run(&self) -> crate::Result<ExitStatus> {
+        let mut child = self.to_command()?.spawn().map_err(|e| {
+            let kind = match e.raw_os_error() {
+                Some(code) => std::io::Error::from_raw_os_error(code).kind(),
+                None => std::io::ErrorKind::Other,
+            };
+            std::io::Error::new(kind, e.to_string())
+        })?;
+        Ok(child.wait()?)
+    }
+}
+
+/// The exit code a shell would report for `status`
+pub fn exit_code(status: ExitStatus) -> i32 {
+    match status {
+        ExitStatus::Exited(code) => i32::from(code as u8),
+        ExitStatus::Signaled(signal, _) => 128 + signal as i32,
+    }
+}
+
+/// Prompts for a new password until one is accepted, and sets it.
+///
+/// Used when [`crate::users::AuthOutcome::MustChange`] requires the password to be changed before the session starts.
+/// The password policy is only advisory for root.
+pub fn change_expired_password(handle: &UserHandle, old_passwd: Option<&str>) -> crate::Result<()> {
+    let uid = handle.uid()?;
+    println!("Password Expired");
+    loop {
+        let passwd = Zeroizing::new(rpassword::prompt_password_stdout("New Password: ")?);
+        let passwd_confirm =
+            Zeroizing::new(rpassword::prompt_password_stdout("Confirm Password: ")?);
+        if passwd.len() != passwd_confirm.len()
+            || !openssl::memcmp::eq(passwd.as_bytes(), passwd_confirm.as_bytes())
+        {
+            eprintln!("Password Mismatch");
+            continue;
+        }
+        if let Some(rejection) = handle.check_new_password(&passwd, old_passwd)? {
+            if uid != 0 {
+                eprintln!(
+                    "Password rejected by the {} policy: {}",
+                    rejection.policy(),
+                    rejection
+                );
+                continue;
+            }
+            eprintln!("Warning: {} ({} policy)", rejection, rejection.policy());
+        }
+        return handle.set_password(&passwd);
+    }
+}
@@ -0,0 +1,182 @@
+use lc_login::{
+    groups::GroupHandle,
+    session::{self, Environment, Session},
+    users::UserHandle,
+    Error,
+};
+use zeroize::Zeroizing;
+
+fn print_help(prg_name: &str) {
+    println!("Usage: {} [-] GROUP [[-c] COMMAND]", prg_name);
+    println!("Runs COMMAND with the shell, with GROUP as the current group, or starts an interactive shell if no COMMAND is given");
+    println!("Users that are not members of GROUP are prompted for the group password");
+    println!("Options:");
+    println!("\t-, -l: Start a login shell, with a fresh environment, in the home directory");
+    println!("\t-c, --command <cmd>: Run cmd with the shell");
+    println!("\t-h, --help: Print this message and exit");
+}
+
+fn operand(prg_name: &str, opt: &str, rest: String, args: &mut std::env::Args) -> String {
+    if !rest.is_empty() {
+        return rest;
+    }
+    match args.next() {
+        Some(s) => s,
+        None => {
+            eprintln!("{}: Missing operand for {}", prg_name, opt);
+            std::process::exit(2)
+        }
+    }
+}
+
+/// Checks that the caller may switch to `group`, prompting for the group password if they are not a member, and exits on failure
+fn authorize(prg_name: &str, handle: &UserHandle, group: &GroupHandle) {
+    let prompt = || {
+        Ok(Zeroizing::new(rpassword::prompt_password_stdout(
+            "Password: ",
+        )?))
+    };
+    match group.authorize_switch(handle, prompt) {
+        Ok(()) => {}
+        Err(Error::Locked) => {
+            eprintln!("{}: Permission Denied", prg_name);
+            std::process::exit(1)
+        }
+        Err(Error::WrongPassword) => {
+            eprintln!("{}: Authentication failure", prg_name);
+            std::process::exit(1)
+        }
+        Err(Error::Expired) => {
+            eprintln!(
+                "{}: The group password has expired; please contact your system administrator",
+                prg_name
+            );
+            std::process::exit(1)
+        }
+        Err(e) => {
+            eprintln!("{}: {}", prg_name, e);
+            std::process::exit(1)
+        }
+    }
+}
+
+pub fn main() {
+    let mut args = std::env::args();
+    let prg_name = args.next().unwrap();
+    if unsafe { libc::geteuid() } != 0 {
+        eprintln!("{}: Cannot work without effective root", prg_name);
+        std::process::exit(1);
+    }
+
+    let mut login_shell = false;
+    let mut group_name = None;
+    let mut command = None;
+
+    while let Some(s) = args.next() {
+        match &*s {
+            "-" => login_shell = true,
+            "--help" => {
+                print_help(&prg_name);
+                std::process::exit(0)
+            }
+            "--command" => command = Some(operand(&prg_name, &s, String::new(), &mut args)),
+            "--" => {
+                if group_name.is_none() {
+                    group_name = args.next();
+                }
+                command = command.or_else(|| args.next());
+                break;
+            }
+            x if x.starts_with("--") => {
+                eprintln!("{}: Unrecognized option {}", prg_name, x);
+                std::process::exit(2)
+            }
+            x if x.starts_with('-') => {
+                let mut chars = x[1..].chars();
+                while let Some(c) = chars.next() {
+                    let opt = format!("-{}", c);
+                    match c {
+                        'h' => {
+                            print_help(&prg_name);
+                            std::process::exit(0)
+                        }
+                        'l' => login_shell = true,
+                        'c' => {
+                            command = Some(operand(&prg_name, &opt, chars.collect(), &mut args));
+                            break;
+                        }
+                        v => {
+                            eprintln!("{}: Unrecognized option -{}", prg_name, v);
+                            std::process::exit(2)
+                        }
+                    }
+                }
+            }
+            x if group_name.is_none() => group_name = Some(x.to_string()),
+            x if command.is_none() => command = Some(x.to_string()),
+            _ => {
+                print_help(&prg_name);
+                std::process::exit(2)
+            }
+        }
+    }
+
+    let group_name = match group_name {
+        Some(n) => n,
+        None => {
+            print_help(&prg_name);
+            std::process::exit(2)
+        }
+    };
+
+    let caller = unsafe { libc::getuid() };
+    let handle = UserHandle::from_uid(caller);
+    if !handle.user_dir().is_dir() {
+        eprintln!("{}: Unknown uid {}", prg_name, caller);
+        std::process::exit(1)
+    }
+
+    let group = match GroupHandle::from_name_or_gid(&group_name) {
+        Ok(group) => group,
+        Err(Error::NoSuchGroup) => {
+            eprintln!("{}: Group {} does not exist", prg_name, group_name);
+            std::process::exit(1)
+        }
+        Err(e) => {
+            eprintln!("{}: {}", prg_name, e);
+            std::process::exit(1)
+        }
+    };
+    let gid = match group.gid() {
+        Ok(gid) => gid,
+        Err(e) => {
+            eprintln!("{}: {}", prg_name, e);
+            std::process::exit(1)
+        }
+    };
+    authorize(&prg_name, &handle, &group);
+
+    let mut session = Session::new(&handle);
+    session.group(gid).login_shell(login_shell);
+    // Like su, a login shell starts from a fresh environment
+    session.environment(if login_shell {
+        Environment::Reset
+    } else {
+        Environment::Preserve
+    });
+    if let Some(command) = command {
+        session.command(command);
+    }
+
+    match session.run() {
+        Ok(status) => std::process::exit(session::exit_code(status)),
+        Err(Error::Io(e)) if e.kind() == std::io::ErrorKind::NotFound => {
+            eprintln!("{}: Failed to execute the shell, {}", prg_name, e);
+            std::process::exit(127)
+        }
+        Err(e) => {
+            eprintln!("{}: Failed to execute the shell, {}", prg_name, e);
+            std::process::exit(126)
+        }
+    }
+}
@@ -1,8 +1,236 @@
+use std::path::{Path, PathBuf};
+
+use lc_login::{
+    session::{self, Environment, Session},
+    users::{AuthOutcome, UserHandle},
+    Error,
+};
+use zeroize::Zeroizing;
+
+fn print_help(prg_name: &str) {
+    println!("Usage: {} [options] [-] [USER [ARGS...]]", prg_name);
+    println!("Runs a shell as USER, or root if no USER is given. Any ARGS are passed to the shell");
+    println!("Options:");
+    println!("\t-, -l, --login: Start a login shell, with a fresh environment, in the home directory of USER");
+    println!(
+        "\t-c, --command <cmd>: Run cmd with the shell instead of starting an interactive shell"
+    );
+    println!("\t-h, --help: Print this message and exit");
+    println!("\t-m, -p, --preserve-environment: Do not change the environment");
+    println!("\t-s, --shell <shell>: Run shell instead of the shell of USER");
+}
+
+fn operand(prg_name: &str, opt: &str, rest: String, args: &mut std::env::Args) -> String {
+    if !rest.is_empty() {
+        return rest;
+    }
+    match args.next() {
+        Some(s) => s,
+        None => {
+            eprintln!("{}: Missing operand for {}", prg_name, opt);
+            std::process::exit(2)
+        }
+    }
+}
+
+/// Checks whether `shell` is listed in the `shells` file in the sysconfdir
+fn is_listed_shell(shell: &Path) -> bool {
+    let mut path = lc_login::dirs::INSTALL_DIRS.sysconfdir.clone();
+    path.push("shells");
+    match std::fs::read_to_string(path) {
+        Ok(shells) => shells
+            .lines()
+            .map(str::trim)
+            .any(|line| !line.starts_with('#') && Path::new(line) == shell),
+        Err(_) => false,
+    }
+}
+
+/// Authenticates the caller as the target user, exiting on failure
+fn authenticate(prg_name: &str, handle: &UserHandle) {
+    let passwd = match handle.has_password() {
+        // Still applies the account expiry
+        Ok(false) => Zeroizing::new(String::new()),
+        Ok(true) => match rpassword::prompt_password_stdout("Password: ") {
+            Ok(s) => Zeroizing::new(s),
+            Err(e) => {
+                eprintln!("{}: {}", prg_name, e);
+                std::process::exit(1)
+            }
+        },
+        Err(e) => {
+            eprintln!("{}: {}", prg_name, e);
+            std::process::exit(1)
+        }
+    };
+
+    let outcome = match handle.authenticate_and_upgrade(&passwd) {
+        Ok(outcome) => outcome,
+        Err(Error::WrongPassword) => {
+            eprintln!("{}: Authentication failure", prg_name);
+            std::process::exit(1)
+        }
+        Err(Error::Expired) => {
+            eprintln!(
+                "{}: The account has expired; please contact your system administrator",
+                prg_name
+            );
+            std::process::exit(1)
+        }
+        Err(e) => {
+            eprintln!("{}: {}", prg_name, e);
+            std::process::exit(1)
+        }
+    };
+
+    match outcome {
+        AuthOutcome::Locked => {
+            eprintln!("{}: Account is locked", prg_name);
+            std::process::exit(1)
+        }
+        AuthOutcome::Warning { days_left } => {
+            println!("Warning: your password will expire in {} days", days_left)
+        }
+        AuthOutcome::MustChange => {
+            if let Err(e) = session::change_expired_password(handle, Some(&passwd)) {
+                eprintln!("{}: {}", prg_name, e);
+                std::process::exit(1)
+            }
+        }
+        AuthOutcome::Success | AuthOutcome::NoPassword => {}
+    }
+}
+
 pub fn main() {
     let mut args = std::env::args();
-    let name = args.next().unwrap();
+    let prg_name = args.next().unwrap();
     if unsafe { libc::geteuid() } != 0 {
-        eprintln!("{}: Cannot work without effective root", name);
+        eprintln!("{}: Cannot work without effective root", prg_name);
         std::process::exit(1);
     }
+
+    let mut login_shell = false;
+    let mut preserve = false;
+    let mut command = None;
+    let mut shell = None;
+    let mut uname = None;
+
+    while let Some(s) = args.next() {
+        match &*s {
+            "-" | "--login" => login_shell = true,
+            "--help" => {
+                print_help(&prg_name);
+                std::process::exit(0)
+            }
+            "--preserve-environment" => preserve = true,
+            "--command" => command = Some(operand(&prg_name, &s, String::new(), &mut args)),
+            "--shell" => shell = Some(operand(&prg_name, &s, String::new(), &mut args)),
+            "--" => {
+                uname = args.next();
+                break;
+            }
+            x if x.starts_with("--") => {
+                eprintln!("{}: Unrecognized option {}", prg_name, x);
+                std::process::exit(2)
+            }
+            x if x.starts_with('-') => {
+                let mut chars = x[1..].chars();
+                while let Some(c) = chars.next() {
+                    let opt = format!("-{}", c);
+                    match c {
+                        'h' => {
+                            print_help(&prg_name);
+                            std::process::exit(0)
+                        }
+                        'l' => login_shell = true,
+                        'm' | 'p' => preserve = true,
+                        'c' => {
+                            command = Some(operand(&prg_name, &opt, chars.collect(), &mut args));
+                            break;
+                        }
+                        's' => {
+                            shell = Some(operand(&prg_name, &opt, chars.collect(), &mut args));
+                            break;
+                        }
+                        v => {
+                            eprintln!("{}: Unrecognized option -{}", prg_name, v);
+                            std::process::exit(2)
+                        }
+                    }
+                }
+            }
+            x => {
+                uname = Some(x.to_string());
+                break;
+            }
+        }
+    }
+
+    let uname = uname.unwrap_or_else(|| "root".to_string());
+    let handle = match UserHandle::from_name(&*uname) {
+        Ok(handle) => handle,
+        Err(Error::NoSuchUser) => {
+            eprintln!("{}: User {} does not exist", prg_name, uname);
+            std::process::exit(1)
+        }
+        Err(e) => {
+            eprintln!("{}: {}", prg_name, e);
+            std::process::exit(1)
+        }
+    };
+
+    let caller = unsafe { libc::getuid() };
+    if caller != 0 {
+        authenticate(&prg_name, &handle);
+    }
+
+    let mut session = Session::new(&handle);
+    session.login_shell(login_shell);
+    // A login shell always starts from a fresh environment
+    session.environment(if login_shell {
+        Environment::Reset
+    } else if preserve {
+        Environment::Preserve
+    } else {
+        Environment::Update
+    });
+    if let Some(shell) = shell {
+        let shell = PathBuf::from(shell);
+        // Users with a restricted shell may not escape it
+        let restricted = match handle.shell() {
+            Ok(Some(current)) => !is_listed_shell(&current),
+            Ok(None) => false,
+            Err(e) => {
+                eprintln!("{}: {}", prg_name, e);
+                std::process::exit(1)
+            }
+        };
+        if caller != 0 && restricted {
+            eprintln!(
+                "{}: Using restricted shell instead of {}",
+                prg_name,
+                shell.display()
+            );
+        } else {
+            session.shell(shell);
+        }
+    }
+    if let Some(command) = command {
+        session.command(command);
+    }
+    for arg in args {
+        session.arg(arg);
+    }
+
+    match session.run() {
+        Ok(status) => std::process::exit(session::exit_code(status)),
+        Err(Error::Io(e)) if e.kind() == std::io::ErrorKind::NotFound => {
+            eprintln!("{}: Failed to execute the shell, {}", prg_name, e);
+            std::process::exit(127)
+        }
+        Err(e) => {
+            eprintln!("{}: Failed to execute the shell, {}", prg_name, e);
+            std::process::exit(126)
+        }
+    }
 }
@@ -0,0 +1,369 @@
+use std::{
+    collections::HashSet,
+    io::ErrorKind,
+    path::{Path, PathBuf},
+};
+
+use std::os::unix::ffi::OsStrExt;
+
+use itertools::Itertools;
+
+use crate::Error;
+
+/// Lists the entry directories in `dir`, which is [`crate::dirs::USERS`] or [`crate::dirs::GROUPS`] within `chroot`.
+///
+/// Each directory is listed once, even if it is also reachable through name symlinks. The numbered directories come first, in ascending order, followed by any directories only reachable by name.
+/// Symlinks that do not lead to a directory, and anything that is neither a directory nor a symlink, are reported as [`Error::Corrupt`].
+/// Names ending in `-` are skipped, as they are the in-progress directories of [`create`] and [`remove`], or the temporary symlinks of [`crate::atomic::replace_symlink`].
+pub(crate) fn entries(
+    dir: &Path,
+    chroot: Option<&Path>,
+) -> crate::Result<Vec<crate::Result<PathBuf>>> {
+    let read_dir = match std::fs::read_dir(dir) {
+        Ok(read_dir) => read_dir,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e.into()),
+    };
+    let mut names = read_dir
+        .map(|entry| Ok(entry?.file_name()))
+        .filter(|name| !matches!(name, Ok(name) if name.as_bytes().ends_with(b"-")))
+        .collect::<std::io::Result<Vec<_>>>()?;
+    names.sort_by_key(|name| {
+        let id = name.to_str().and_then(|s| s.parse::<u32>().ok());
+        (id.is_none(), id, name.clone())
+    });
+
+    let mut seen = HashSet::new();
+    let mut result = Vec::new();
+    for name in names {
+        let path = dir.join(&name);
+        let file_type = match std::fs::symlink_metadata(&path) {
+            Ok(meta) => meta.file_type(),
+            // Removed while listing
+            Err(e) if e.kind() == ErrorKind::NotFound => continue,
+            Err(e) => {
+                result.push(Err(e.into()));
+                continue;
+            }
+        };
+        let target = if file_type.is_dir() {
+            path
+        } else if file_type.is_symlink() {
+            // Resolved the same way as UserHandle::from_name_in
+            let link = match std::fs::read_link(&path) {
+                Ok(link) => link,
+                Err(e) => {
+                    result.push(Err(e.into()));
+                    continue;
+                }
+            };
+            match chroot {
+                Some(chroot) if link.is_absolute() => chroot.join(link.strip_prefix("/").unwrap()),
+                _ => dir.join(link),
+            }
+        } else {
+            result.push(Err(Error::Corrupt(
+                "Unexpected file in users or groups directory",
+            )));
+            continue;
+        };
+        match std::fs::canonicalize(&target) {
+            Ok(canonical) if canonical.is_dir() => {
+                if seen.insert(canonical) {
+                    result.push(Ok(target));
+                }
+            }
+            Ok(_) => result.push(Err(Error::Corrupt(
+                "Name symlink does not lead to a directory",
+            ))),
+            Err(e) if e.kind() == ErrorKind::NotFound => {
+                result.push(Err(Error::Corrupt("Dangling name symlink")))
+            }
+            Err(e) => result.push(Err(e.into())),
+        }
+    }
+    Ok(result)
+}
+
+/// Creates an entry directory named `name` in `dir`, which is [`crate::dirs::USERS`] or [`crate::dirs::GROUPS`] within a chroot.
+///
+/// The directory is numbered `id`, or if it is `None`, the first of `candidates` that is free. It gets an `id_link` symlink and a `name` symlink, and is then filled in by `populate`.
+/// All of this happens under the name `<id>-`, which also reserves the id, before it is renamed into place and the name symlink is added, so the entry never appears half-built.
+/// `exists` is returned if the name or the requested id is taken.
+pub(crate) fn create<I, F>(
+    dir: &Path,
+    name: &str,
+    id: Option<u32>,
+    candidates: I,
+    id_link: &str,
+    exists: Error,
+    mut populate: F,
+) -> crate::Result<(PathBuf, u32)>
+where
+    I: Iterator<Item = u32>,
+    F: FnMut(&Path) -> crate::Result<()>,
+{
+    let name_path = dir.join(name);
+    if std::fs::symlink_metadata(&name_path).is_ok() {
+        return Err(exists);
+    }
+    let explicit = id.is_some();
+    let candidates: Box<dyn Iterator<Item = u32>> = match id {
+        Some(id) => Box::new(std::iter::once(id)),
+        None => Box::new(candidates),
+    };
+    for id in candidates {
+        let path = dir.join(id.to_string());
+        let mut tmp_path = path.clone();
+        tmp_path.as_mut_os_string().push("-");
+        if std::fs::symlink_metadata(&path).is_ok() {
+            if explicit {
+                return Err(exists);
+            }
+            continue;
+        }
+        match std::fs::create_dir(&tmp_path) {
+            Ok(()) => {}
+            // Another entry is being created with this id
+            Err(e) if e.kind() == ErrorKind::AlreadyExists && !explicit => continue,
+            Err(e) if e.kind() == ErrorKind::AlreadyExists => return Err(Error::Busy),
+            Err(e) => return Err(e.into()),
+        }
+        let defer = defer::defer(|| drop(std::fs::remove_dir_all(&tmp_path)));
+        std::os::unix::fs::symlink(format!("../{}", id), tmp_path.join(id_link))?;
+        std::os::unix::fs::symlink(format!("../{}", name), tmp_path.join("name"))?;
+        populate(&tmp_path)?;
+        if std::fs::symlink_metadata(&path).is_ok() {
+            return Err(exists);
+        }
+        std::fs::rename(&tmp_path, &path)?;
+        std::mem::forget(defer);
+        if let Err(e) = std::os::unix::fs::symlink(id.to_string(), &name_path) {
+            let _ = std::fs::remove_dir_all(&path);
+            return Err(match e.kind() {
+                ErrorKind::AlreadyExists => exists,
+                _ => e.into(),
+            });
+        }
+        return Ok((path, id));
+    }
+    Err(Error::NoFreeId)
+}
+
+/// Renames the entry at `path` to `name`, by adding the new name symlink in its parent directory, pointing the entry's `name` symlink at it, and then removing the old name symlink.
+///
+/// `exists` is returned if `name` is taken by another entry. Renaming an entry to its current name does nothing.
+pub(crate) fn rename(path: &Path, name: &str, exists: Error) -> crate::Result<()> {
+    let path = std::fs::canonicalize(path)?;
+    let dir = path
+        .parent()
+        .ok_or(Error::Corrupt("Invalid entry directory"))?;
+    let id = path
+        .file_name()
+        .ok_or(Error::Corrupt("Invalid entry directory"))?;
+    let old = match std::fs::read_link(path.join("name")) {
+        Ok(link) => link.file_name().map(|name| name.to_os_string()),
+        Err(e) if e.kind() == ErrorKind::NotFound => None,
+        Err(e) => return Err(e.into()),
+    };
+    if old.as_deref() == Some(name.as_ref()) {
+        return Ok(());
+    }
+    std::os::unix::fs::symlink(id, dir.join(name)).map_err(|e| match e.kind() {
+        ErrorKind::AlreadyExists => exists,
+        _ => e.into(),
+    })?;
+    if let Err(e) = crate::atomic::replace_symlink(format!("../{}", name), &path.join("name")) {
+        let _ = std::fs::remove_file(dir.join(name));
+        return Err(e);
+    }
+    if let Some(old) = old {
+        let old = dir.join(old);
+        // Leaves the old name alone if it has since been given to another entry
+        if std::fs::canonicalize(&old).ok().as_ref() == Some(&path) {
+            std::fs::remove_file(old)?;
+        }
+    }
+    Ok(())
+}
+
+/// Moves the entry at `path` to the id `id`, by renaming its directory and repointing its `id_link` symlink and its name symlink.
+///
+/// Returns the new path of the entry, or `exists` if the id is taken by another entry.
+pub(crate) fn renumber(
+    path: &Path,
+    id: u32,
+    id_link: &str,
+    exists: Error,
+) -> crate::Result<PathBuf> {
+    let path = std::fs::canonicalize(path)?;
+    let dir = path
+        .parent()
+        .ok_or(Error::Corrupt("Invalid entry directory"))?;
+    let new_path = dir.join(id.to_string());
+    if new_path == path {
+        return Ok(new_path);
+    }
+    let mut tmp_path = new_path.clone();
+    tmp_path.as_mut_os_string().push("-");
+    // An entry being created with this id holds <id>-
+    if std::fs::symlink_metadata(&new_path).is_ok() || std::fs::symlink_metadata(&tmp_path).is_ok()
+    {
+        return Err(exists);
+    }
+    std::fs::rename(&path, &new_path)?;
+    crate::atomic::replace_symlink(format!("../{}", id), &new_path.join(id_link))?;
+    match std::fs::read_link(new_path.join("name")) {
+        Ok(name) => {
+            if let Some(name) = name.file_name() {
+                crate::atomic::replace_symlink(id.to_string(), &dir.join(name))?;
+            }
+        }
+        Err(e) if e.kind() == ErrorKind::NotFound => {}
+        Err(e) => return Err(e.into()),
+    }
+    Ok(new_path)
+}
+
+/// Removes the entry at `path`, after removing its name symlink from its parent directory.
+///
+/// The directory is renamed to `<id>-` before its contents are removed, so the entry disappears at once, even if the removal fails part way.
+/// `missing` is returned if there is no such entry.
+pub(crate) fn remove(path: &Path, missing: Error) -> crate::Result<()> {
+    let path = match std::fs::canonicalize(path) {
+        Ok(path) if path.is_dir() => path,
+        Ok(_) => return Err(missing),
+        Err(e) if e.kind() == ErrorKind::NotFound => return Err(missing),
+        Err(e) => return Err(e.into()),
+    };
+    let dir = path
+        .parent()
+        .ok_or(Error::Corrupt("Invalid entry directory"))?;
+    match std::fs::read_link(path.join("name")) {
+        Ok(name) => {
+            if let Some(name) = name.file_name() {
+                let link = dir.join(name);
+                // Leaves the name alone if it has since been given to another entry
+                if std::fs::canonicalize(&link).ok().as_ref() == Some(&path) {
+                    std::fs::remove_file(&link)?;
+                }
+            }
+        }
+        Err(e) if e.kind() == ErrorKind::NotFound => {}
+        Err(e) => return Err(e.into()),
+    }
+    let mut tmp_path = path.clone();
+    tmp_path.as_mut_os_string().push("-");
+    if std::fs::symlink_metadata(&tmp_path).is_ok() {
+        return Err(Error::Busy);
+    }
+    std::fs::rename(&path, &tmp_path)?;
+    Ok(std::fs::remove_dir_all(&tmp_path)?)
+}
+
+/// Finds the chroot that the entry directory `dir` was found in, given that it is `base` ([`crate::dirs::USERS`] or [`crate::dirs::GROUPS`]) within the chroot
+pub(crate) fn chroot_of(dir: &Path, base: &Path) -> crate::Result<PathBuf> {
+    dir.ancestors()
+        .nth(base.components().count() - 1)
+        .map(PathBuf::from)
+        .ok_or(Error::Corrupt("Invalid entry directory"))
+}
+
+/// Parses a comma-separated list of ids, like the `groups` file of a user. `what` names the list in the [`Error::Corrupt`] returned if it is malformed
+pub(crate) fn parse_ids(bytes: &[u8], what: &'static str) -> crate::Result<Vec<u32>> {
+    std::str::from_utf8(bytes)
+        .map_err(|_| Error::Corrupt(what))?
+        .trim_end()
+        .split(',')
+        .filter(|v| !v.is_empty())
+        .map(|v| v.parse())
+        .collect::<Result<_, _>>()
+        .map_err(|_| Error::Corrupt(what))
+}
+
+/// Formats a list of ids for [`parse_ids`], sorted and without duplicates
+pub(crate) fn format_ids(ids: &mut Vec<u32>) -> Vec<u8> {
+    ids.sort_unstable();
+    ids.dedup();
+    ids.iter().join(",").into_bytes()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn names(dir: &Path) -> Vec<String> {
+        entries(dir, None)
+            .unwrap()
+            .into_iter()
+            .map(|path| {
+                std::fs::read_link(path.unwrap().join("name"))
+                    .unwrap()
+                    .file_name()
+                    .unwrap()
+                    .to_string_lossy()
+                    .into_owned()
+            })
+            .collect()
+    }
+
+    fn add(dir: &Path, name: &str, id: u32) {
+        create(
+            dir,
+            name,
+            Some(id),
+            std::iter::empty(),
+            "uid",
+            Error::UserExists,
+            |_| Ok(()),
+        )
+        .unwrap();
+    }
+
+    #[test]
+    fn entries_are_listed_once_in_id_order() {
+        let dir = tempfile::tempdir().unwrap();
+        add(dir.path(), "carol", 1002);
+        add(dir.path(), "alice", 1000);
+        add(dir.path(), "bob", 999);
+        // An extra name for alice
+        std::os::unix::fs::symlink("1000", dir.path().join("alias")).unwrap();
+        assert_eq!(names(dir.path()), ["bob", "alice", "carol"]);
+        assert!(entries(&dir.path().join("missing"), None)
+            .unwrap()
+            .is_empty());
+    }
+
+    #[test]
+    fn entries_skip_temporary_names() {
+        let dir = tempfile::tempdir().unwrap();
+        add(dir.path(), "alice", 1000);
+        // A user being created, one being deleted, and a symlink being replaced
+        std::fs::create_dir(dir.path().join("1001-")).unwrap();
+        std::fs::create_dir(dir.path().join("1002-")).unwrap();
+        std::os::unix::fs::symlink("1001", dir.path().join("alice-")).unwrap();
+        assert_eq!(names(dir.path()), ["alice"]);
+    }
+
+    #[test]
+    fn malformed_entries_are_errors() {
+        let dir = tempfile::tempdir().unwrap();
+        add(dir.path(), "alice", 1000);
+        std::os::unix::fs::symlink("1005", dir.path().join("dangling")).unwrap();
+        std::fs::write(dir.path().join("stray"), "").unwrap();
+        std::fs::write(dir.path().join("file"), "").unwrap();
+        std::os::unix::fs::symlink("file", dir.path().join("not-a-dir")).unwrap();
+        let entries = entries(dir.path(), None).unwrap();
+        assert_eq!(entries.len(), 5);
+        assert_eq!(
+            entries.iter().filter(|entry| entry.is_ok()).count(),
+            1,
+            "{:?}",
+            entries
+        );
+        assert!(entries
+            .iter()
+            .filter_map(|entry| entry.as_ref().err())
+            .all(|e| matches!(e, Error::Corrupt(_))));
+    }
+}
@@ -0,0 +1,336 @@
+use std::{
+    io::ErrorKind,
+    os::unix::fs::DirBuilderExt,
+    path::{Path, PathBuf},
+};
+
+use lc_login::{
+    groups::GroupHandle,
+    users::{NewUser, UserHandle},
+    Error,
+};
+
+fn print_help(prg_name: &str) {
+    println!("Usage: {} [options] LOGIN", prg_name);
+    println!("Creates a new user account");
+    println!("Options:");
+    println!(
+        "\t-d, --home-dir <dir>: Set the home directory of the account. Defaults to /home/LOGIN"
+    );
+    println!("\t-g, --gid <group>: Set the name or gid of the primary group of the account. Defaults to the default_gid of ids.conf");
+    println!(
+        "\t-G, --groups <groups>: Set the comma-separated supplementary groups of the account"
+    );
+    println!("\t-h, --help: Print this message and exit");
+    println!("\t-k, --skel <dir>: With -m, copy the contents of dir instead of the default skeleton directory");
+    println!(
+        "\t-m, --create-home: Create the home directory, copying the skeleton directory into it"
+    );
+    println!("\t-M, --no-create-home: Do not create the home directory");
+    println!("\t-r, --system: Create a system account, with a uid from the system range");
+    println!("\t-R, --root: Make changes within the given sysroot");
+    println!("\t-s, --shell <shell>: Set the login shell of the account");
+    println!("\t-u, --uid <uid>: Set the uid of the account, instead of allocating one");
+}
+
+fn operand(prg_name: &str, opt: &str, rest: String, args: &mut std::env::Args) -> String {
+    if !rest.is_empty() {
+        return rest;
+    }
+    match args.next() {
+        Some(s) => s,
+        None => {
+            eprintln!("{}: Missing operand for {}", prg_name, opt);
+            std::process::exit(2)
+        }
+    }
+}
+
+/// Resolves a group given by name or gid
+fn resolve_group(group: &str, chroot: Option<&str>) -> lc_login::Result<u32> {
+    match group.parse::<u32>() {
+        Ok(gid) => {
+            let handle = match chroot {
+                Some(chroot) => GroupHandle::from_uid_in(gid, chroot),
+                None => GroupHandle::from_uid(gid),
+            };
+            if handle.user_dir().is_dir() {
+                Ok(gid)
+            } else {
+                Err(Error::NoSuchGroup)
+            }
+        }
+        Err(_) => match chroot {
+            Some(chroot) => GroupHandle::from_name_in(group, chroot),
+            None => GroupHandle::from_name(group),
+        }?
+        .gid(),
+    }
+}
+
+/// Copies the contents of `from` into `to`, owned by `uid` and `gid`
+fn copy_tree(from: &Path, to: &Path, uid: u32, gid: u32) -> std::io::Result<()> {
+    for entry in std::fs::read_dir(from)? {
+        let entry = entry?;
+        let src = entry.path();
+        let dest = to.join(entry.file_name());
+        let file_type = entry.file_type()?;
+        if file_type.is_dir() {
+            std::fs::create_dir(&dest)?;
+            std::fs::set_permissions(&dest, std::fs::metadata(&src)?.permissions())?;
+            copy_tree(&src, &dest, uid, gid)?;
+        } else if file_type.is_symlink() {
+            std::os::unix::fs::symlink(std::fs::read_link(&src)?, &dest)?;
+        } else {
+            std::fs::copy(&src, &dest)?;
+        }
+        std::os::unix::fs::lchown(&dest, Some(uid), Some(gid))?;
+    }
+    Ok(())
+}
+
+/// Creates the home directory at `home`, and copies `skel` into it
+fn create_home(
+    prg_name: &str,
+    home: &Path,
+    skel: &Path,
+    uid: u32,
+    gid: u32,
+) -> std::io::Result<()> {
+    if let Some(parent) = home.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    match std::fs::DirBuilder::new().mode(0o700).create(home) {
+        Ok(()) => {}
+        Err(e) if e.kind() == ErrorKind::AlreadyExists => {
+            eprintln!(
+                "{}: Warning: the home directory {} already exists, not copying the skeleton directory",
+                prg_name,
+                home.display()
+            );
+            return Ok(());
+        }
+        Err(e) => return Err(e),
+    }
+    std::os::unix::fs::chown(home, Some(uid), Some(gid))?;
+    match copy_tree(skel, home, uid, gid) {
+        Err(e) if e.kind() == ErrorKind::NotFound => Ok(()),
+        r => r,
+    }
+}
+
+pub fn main() {
+    let mut args = std::env::args();
+    let prg_name = args.next().unwrap();
+    let mut login_name = None;
+    let mut chroot = None;
+    let mut uid = None;
+    let mut group = None;
+    let mut groups = None;
+    let mut home = None;
+    let mut shell = None;
+    let mut skel = None;
+    let mut create_home_dir = false;
+    let mut system = false;
+
+    while let Some(s) = args.next() {
+        match &*s {
+            "--help" => {
+                print_help(&prg_name);
+                std::process::exit(0)
+            }
+            "--create-home" => create_home_dir = true,
+            "--no-create-home" => create_home_dir = false,
+            "--system" => system = true,
+            "--root" => chroot = Some(operand(&prg_name, &s, String::new(), &mut args)),
+            "--uid" => uid = Some(operand(&prg_name, &s, String::new(), &mut args)),
+            "--gid" => group = Some(operand(&prg_name, &s, String::new(), &mut args)),
+            "--groups" => groups = Some(operand(&prg_name, &s, String::new(), &mut args)),
+            "--home-dir" => home = Some(operand(&prg_name, &s, String::new(), &mut args)),
+            "--shell" => shell = Some(operand(&prg_name, &s, String::new(), &mut args)),
+            "--skel" => skel = Some(operand(&prg_name, &s, String::new(), &mut args)),
+            "--" => {
+                login_name = args.next();
+                break;
+            }
+            x if x.starts_with("--") => {
+                eprintln!("{}: Unrecognized option {}", prg_name, x);
+                std::process::exit(2)
+            }
+            x if x.starts_with('-') && x.len() > 1 => {
+                let mut chars = x[1..].chars();
+                while let Some(c) = chars.next() {
+                    let opt = format!("-{}", c);
+                    let target = match c {
+                        'h' => {
+                            print_help(&prg_name);
+                            std::process::exit(0)
+                        }
+                        'm' => {
+                            create_home_dir = true;
+                            continue;
+                        }
+                        'M' => {
+                            create_home_dir = false;
+                            continue;
+                        }
+                        'r' => {
+                            system = true;
+                            continue;
+                        }
+                        'R' => &mut chroot,
+                        'u' => &mut uid,
+                        'g' => &mut group,
+                        'G' => &mut groups,
+                        'd' => &mut home,
+                        's' => &mut shell,
+                        'k' => &mut skel,
+                        v => {
+                            eprintln!("{}: Unrecognized option -{}", prg_name, v);
+                            std::process::exit(2)
+                        }
+                    };
+                    *target = Some(operand(&prg_name, &opt, chars.collect(), &mut args));
+                    break;
+                }
+            }
+            x => login_name = Some(x.to_string()),
+        }
+    }
+
+    let login_name = match login_name {
+        Some(n) => n,
+        None => {
+            print_help(&prg_name);
+            std::process::exit(2)
+        }
+    };
+
+    if skel.is_some() && !create_home_dir {
+        eprintln!("{}: -k is only allowed with -m", prg_name);
+        std::process::exit(2)
+    }
+
+    if unsafe { libc::getuid() } != 0 {
+        eprintln!("{}: Permission Denied", prg_name);
+        std::process::exit(1)
+    }
+
+    let uid = uid.map(|uid| match uid.parse::<u32>() {
+        Ok(uid) => uid,
+        Err(e) => {
+            eprintln!("{}: Invalid uid {}: {}", prg_name, uid, e);
+            std::process::exit(3)
+        }
+    });
+    let resolve = |group: &str| match resolve_group(group, chroot.as_deref()) {
+        Ok(gid) => gid,
+        Err(Error::NoSuchGroup) => {
+            eprintln!("{}: Group {} does not exist", prg_name, group);
+            std::process::exit(6)
+        }
+        Err(e) => {
+            eprintln!("{}: {}", prg_name, e);
+            std::process::exit(10)
+        }
+    };
+    let group = match group.as_deref() {
+        Some(group) => resolve(group),
+        None => {
+            let ranges = match &chroot {
+                Some(chroot) => lc_login::ids::read_ranges_in(chroot),
+                None => lc_login::ids::read_ranges(),
+            };
+            match ranges {
+                Ok(ranges) => ranges.default_gid,
+                Err(e) => {
+                    eprintln!("{}: Failed to read ids.conf, {}", prg_name, e);
+                    std::process::exit(10)
+                }
+            }
+        }
+    };
+    let groups = groups
+        .as_deref()
+        .map(|groups| {
+            groups
+                .split(',')
+                .filter(|g| !g.is_empty())
+                .map(resolve)
+                .collect()
+        })
+        .unwrap_or_default();
+    let home = home.map_or_else(|| PathBuf::from("/home").join(&login_name), PathBuf::from);
+
+    let user = NewUser {
+        name: login_name.clone(),
+        uid,
+        system,
+        group,
+        groups,
+        home: Some(home.clone()),
+        shell: Some(PathBuf::from(
+            shell.unwrap_or_else(|| lc_login::session::DEFAULT_SHELL.to_string()),
+        )),
+    };
+    let handle = match &chroot {
+        Some(chroot) => UserHandle::create_in(&user, chroot),
+        None => UserHandle::create(&user),
+    };
+    let handle = match handle {
+        Ok(handle) => handle,
+        Err(Error::UserExists) if uid.is_some() => {
+            eprintln!(
+                "{}: User {} or uid {} already exists",
+                prg_name,
+                login_name,
+                uid.unwrap()
+            );
+            std::process::exit(4)
+        }
+        Err(Error::UserExists) => {
+            eprintln!("{}: User {} already exists", prg_name, login_name);
+            std::process::exit(9)
+        }
+        Err(Error::InvalidName) => {
+            eprintln!("{}: Invalid user name {}", prg_name, login_name);
+            std::process::exit(3)
+        }
+        // The groups given with -g and -G were checked above, so this is the default group
+        Err(Error::NoSuchGroup) => {
+            eprintln!("{}: Group {} does not exist", prg_name, group);
+            std::process::exit(6)
+        }
+        Err(e) => {
+            eprintln!("{}: Failed to create the user, {}", prg_name, e);
+            std::process::exit(1)
+        }
+    };
+
+    if create_home_dir {
+        let uid = match handle.uid() {
+            Ok(uid) => uid,
+            Err(e) => {
+                eprintln!("{}: {}", prg_name, e);
+                std::process::exit(12)
+            }
+        };
+        let in_root = |path: &Path| match &chroot {
+            Some(chroot) => Path::new(chroot).join(path.strip_prefix("/").unwrap_or(path)),
+            None => path.to_path_buf(),
+        };
+        let skel = skel.map_or_else(
+            || lc_login::dirs::INSTALL_DIRS.sysconfdir.join("skel"),
+            PathBuf::from,
+        );
+        if let Err(e) = create_home(&prg_name, &in_root(&home), &in_root(&skel), uid, group) {
+            eprintln!(
+                "{}: Failed to create the home directory {}, {}",
+                prg_name,
+                home.display(),
+                e
+            );
+            std::process::exit(12)
+        }
+    }
+}
@@ -0,0 +1,252 @@
+use std::{
+    io::ErrorKind,
+    os::unix::fs::MetadataExt,
+    path::{Path, PathBuf},
+};
+
+use lc_login::{users::UserHandle, Error};
+
+fn print_help(prg_name: &str) {
+    println!("Usage: {} [options] LOGIN", prg_name);
+    println!("Deletes a user account");
+    println!("Options:");
+    println!("\t-f, --force: Delete the account even if the user has running processes");
+    println!("\t-h, --help: Print this message and exit");
+    println!("\t-r, --remove: Remove the home directory and mail spool of the user. The home directory is only removed if the user owns it, and it is not / or the root directory of the user");
+    println!("\t-R, --root: Make changes within the given sysroot");
+}
+
+/// Checks whether any process runs with `uid` as its real, effective, saved or filesystem uid
+fn has_processes(uid: u32) -> std::io::Result<bool> {
+    for entry in std::fs::read_dir("/proc")? {
+        let entry = entry?;
+        if !entry
+            .file_name()
+            .to_str()
+            .is_some_and(|s| s.bytes().all(|b| b.is_ascii_digit()))
+        {
+            continue;
+        }
+        let status = match std::fs::read_to_string(entry.path().join("status")) {
+            Ok(status) => status,
+            // Exited while listing
+            Err(e) if e.kind() == ErrorKind::NotFound => continue,
+            Err(e) => return Err(e),
+        };
+        let uids = status
+            .lines()
+            .find_map(|line| line.strip_prefix("Uid:"))
+            .unwrap_or("");
+        if uids.split_whitespace().any(|id| id.parse() == Ok(uid)) {
+            return Ok(true);
+        }
+    }
+    Ok(false)
+}
+
+/// Removes the home directory at `home`, unless another user owns it, or it is one of the `protected` directories
+fn remove_home(
+    prg_name: &str,
+    home: &Path,
+    uid: u32,
+    protected: &[PathBuf],
+) -> std::io::Result<()> {
+    let meta = match std::fs::symlink_metadata(home) {
+        Ok(meta) => meta,
+        Err(e) if e.kind() == ErrorKind::NotFound => {
+            eprintln!(
+                "{}: Warning: the home directory {} does not exist",
+                prg_name,
+                home.display()
+            );
+            return Ok(());
+        }
+        Err(e) => return Err(e),
+    };
+    if meta.uid() != uid {
+        eprintln!(
+            "{}: Warning: the home directory {} is not owned by the user, not removing it",
+            prg_name,
+            home.display()
+        );
+        return Ok(());
+    }
+    let canonical = std::fs::canonicalize(home)?;
+    if protected
+        .iter()
+        .any(|dir| std::fs::canonicalize(dir).is_ok_and(|dir| dir == canonical))
+    {
+        eprintln!(
+            "{}: Warning: the home directory {} is / or the root directory of the user, not removing it",
+            prg_name,
+            home.display()
+        );
+        return Ok(());
+    }
+    std::fs::remove_dir_all(home)
+}
+
+fn operand(prg_name: &str, opt: &str, rest: String, args: &mut std::env::Args) -> String {
+    if !rest.is_empty() {
+        return rest;
+    }
+    match args.next() {
+        Some(s) => s,
+        None => {
+            eprintln!("{}: Missing operand for {}", prg_name, opt);
+            std::process::exit(2)
+        }
+    }
+}
+
+pub fn main() {
+    let mut args = std::env::args();
+    let prg_name = args.next().unwrap();
+    let mut login_name = None;
+    let mut chroot = None;
+    let mut force = false;
+    let mut remove = false;
+
+    while let Some(s) = args.next() {
+        match &*s {
+            "--help" => {
+                print_help(&prg_name);
+                std::process::exit(0)
+            }
+            "--force" => force = true,
+            "--remove" => remove = true,
+            "--root" => chroot = Some(operand(&prg_name, &s, String::new(), &mut args)),
+            "--" => {
+                login_name = args.next();
+                break;
+            }
+            x if x.starts_with("--") => {
+                eprintln!("{}: Unrecognized option {}", prg_name, x);
+                std::process::exit(2)
+            }
+            x if x.starts_with('-') && x.len() > 1 => {
+                let mut chars = x[1..].chars();
+                while let Some(c) = chars.next() {
+                    match c {
+                        'f' => force = true,
+                        'h' => {
+                            print_help(&prg_name);
+                            std::process::exit(0)
+                        }
+                        'r' => remove = true,
+                        'R' => {
+                            chroot = Some(operand(&prg_name, "-R", chars.collect(), &mut args));
+                            break;
+                        }
+                        v => {
+                            eprintln!("{}: Unrecognized option -{}", prg_name, v);
+                            std::process::exit(2)
+                        }
+                    }
+                }
+            }
+            x => login_name = Some(x.to_string()),
+        }
+    }
+
+    let login_name = match login_name {
+        Some(n) => n,
+        None => {
+            print_help(&prg_name);
+            std::process::exit(2)
+        }
+    };
+
+    if unsafe { libc::getuid() } != 0 {
+        eprintln!("{}: Permission Denied", prg_name);
+        std::process::exit(1)
+    }
+
+    let handle = match &chroot {
+        Some(chroot) => UserHandle::from_name_in(&*login_name, chroot),
+        None => UserHandle::from_name(&*login_name),
+    };
+    let handle = match handle {
+        Ok(handle) => handle,
+        Err(Error::NoSuchUser) => {
+            eprintln!("{}: User {} does not exist", prg_name, login_name);
+            std::process::exit(6)
+        }
+        Err(e) => {
+            eprintln!("{}: {}", prg_name, e);
+            std::process::exit(1)
+        }
+    };
+    let (uid, home, root) = match handle
+        .uid()
+        .and_then(|uid| Ok((uid, handle.home()?, handle.root()?)))
+    {
+        Ok(v) => v,
+        Err(e) => {
+            eprintln!("{}: {}", prg_name, e);
+            std::process::exit(1)
+        }
+    };
+
+    // Processes outside the sysroot say nothing about its users
+    if chroot.is_none() && !force {
+        match has_processes(uid) {
+            Ok(false) => {}
+            Ok(true) => {
+                eprintln!(
+                    "{}: User {} is currently logged in or has running processes",
+                    prg_name, login_name
+                );
+                std::process::exit(8)
+            }
+            Err(e) => {
+                eprintln!("{}: Failed to check for running processes, {}", prg_name, e);
+                std::process::exit(1)
+            }
+        }
+    }
+
+    if let Err(e) = handle.delete() {
+        eprintln!("{}: Failed to delete the user, {}", prg_name, e);
+        std::process::exit(1)
+    }
+
+    if remove {
+        let in_root = |path: &Path| match &chroot {
+            Some(chroot) => Path::new(chroot).join(path.strip_prefix("/").unwrap_or(path)),
+            None => path.to_path_buf(),
+        };
+        let mut code = 0;
+        let mut spool = PathBuf::from(&lc_login::dirs::INSTALL_DIRS.localstatedir);
+        spool.push("mail");
+        spool.push(&login_name);
+        match std::fs::remove_file(in_root(&spool)) {
+            Ok(()) => {}
+            Err(e) if e.kind() == ErrorKind::NotFound => {}
+            Err(e) => {
+                eprintln!(
+                    "{}: Failed to remove the mail spool {}, {}",
+                    prg_name,
+                    spool.display(),
+                    e
+                );
+                code = 12;
+            }
+        }
+        // Also protects the sysroot itself, which is where a home of / points within it
+        let mut protected = vec![PathBuf::from("/"), in_root(Path::new("/"))];
+        protected.extend(root.as_deref().map(in_root));
+        if let Some(home) = home {
+            if let Err(e) = remove_home(&prg_name, &in_root(&home), uid, &protected) {
+                eprintln!(
+                    "{}: Failed to remove the home directory {}, {}",
+                    prg_name,
+                    home.display(),
+                    e
+                );
+                code = 12;
+            }
+        }
+        std::process::exit(code)
+    }
+}
@@ -0,0 +1,421 @@
+use std::{
+    io::ErrorKind,
+    os::unix::fs::MetadataExt,
+    path::{Path, PathBuf},
+};
+
+use lc_login::{groups::GroupHandle, users::UserHandle, Error};
+
+fn print_help(prg_name: &str) {
+    println!("Usage: {} [options] LOGIN", prg_name);
+    println!("Modifies a user account");
+    println!("Options:");
+    println!("\t-a, --append: With -G, add the user to the groups instead of replacing its supplementary groups");
+    println!("\t-d, --home <dir>: Set the home directory of the account");
+    println!("\t-e, --expiredate <date>: Set the date the account expires, or -1 to remove the account expiry");
+    println!("\t-g, --gid <group>: Set the name or gid of the primary group of the account");
+    println!(
+        "\t-G, --groups <groups>: Set the comma-separated supplementary groups of the account"
+    );
+    println!("\t-h, --help: Print this message and exit");
+    println!("\t-l, --login <name>: Rename the account");
+    println!("\t-L, --lock: Disable the password of the account");
+    println!("\t-m, --move-home: With -d, move the contents of the home directory to the new home directory");
+    println!("\t-R, --root: Make changes within the given sysroot");
+    println!("\t-s, --shell <shell>: Set the login shell of the account");
+    println!("\t-u, --uid <uid>: Change the uid of the account");
+    println!("\t-U, --unlock: Enable the password of the account");
+}
+
+fn operand(prg_name: &str, opt: &str, rest: String, args: &mut std::env::Args) -> String {
+    if !rest.is_empty() {
+        return rest;
+    }
+    match args.next() {
+        Some(s) => s,
+        None => {
+            eprintln!("{}: Missing operand for {}", prg_name, opt);
+            std::process::exit(2)
+        }
+    }
+}
+
+/// Resolves a group given by name or gid
+fn resolve_group(group: &str, chroot: Option<&str>) -> lc_login::Result<u32> {
+    match group.parse::<u32>() {
+        Ok(gid) => {
+            let handle = match chroot {
+                Some(chroot) => GroupHandle::from_uid_in(gid, chroot),
+                None => GroupHandle::from_uid(gid),
+            };
+            if handle.user_dir().is_dir() {
+                Ok(gid)
+            } else {
+                Err(Error::NoSuchGroup)
+            }
+        }
+        Err(_) => match chroot {
+            Some(chroot) => GroupHandle::from_name_in(group, chroot),
+            None => GroupHandle::from_name(group),
+        }?
+        .gid(),
+    }
+}
+
+/// Copies the contents of `from` into `to`, preserving ownership
+fn copy_tree(from: &Path, to: &Path) -> std::io::Result<()> {
+    for entry in std::fs::read_dir(from)? {
+        let entry = entry?;
+        let src = entry.path();
+        let dest = to.join(entry.file_name());
+        let meta = std::fs::symlink_metadata(&src)?;
+        if meta.is_dir() {
+            std::fs::create_dir(&dest)?;
+            std::fs::set_permissions(&dest, meta.permissions())?;
+            copy_tree(&src, &dest)?;
+        } else if meta.file_type().is_symlink() {
+            std::os::unix::fs::symlink(std::fs::read_link(&src)?, &dest)?;
+        } else {
+            std::fs::copy(&src, &dest)?;
+        }
+        std::os::unix::fs::lchown(&dest, Some(meta.uid()), Some(meta.gid()))?;
+    }
+    Ok(())
+}
+
+/// Moves the home directory from `from` to `to`, copying it if they are on different filesystems
+fn move_home(prg_name: &str, from: &Path, to: &Path) -> std::io::Result<()> {
+    let meta = match std::fs::symlink_metadata(from) {
+        Ok(meta) => meta,
+        Err(e) if e.kind() == ErrorKind::NotFound => {
+            eprintln!(
+                "{}: Warning: the home directory {} does not exist, not moving it",
+                prg_name,
+                from.display()
+            );
+            return Ok(());
+        }
+        Err(e) => return Err(e),
+    };
+    if std::fs::symlink_metadata(to).is_ok() {
+        return Err(std::io::Error::new(
+            ErrorKind::AlreadyExists,
+            format!("{} already exists", to.display()),
+        ));
+    }
+    if let Some(parent) = to.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    match std::fs::rename(from, to) {
+        Ok(()) => Ok(()),
+        Err(e) if e.raw_os_error() == Some(libc::EXDEV) => {
+            std::fs::create_dir(to)?;
+            std::fs::set_permissions(to, meta.permissions())?;
+            std::os::unix::fs::lchown(to, Some(meta.uid()), Some(meta.gid()))?;
+            if let Err(e) = copy_tree(from, to) {
+                let _ = std::fs::remove_dir_all(to);
+                return Err(e);
+            }
+            std::fs::remove_dir_all(from)
+        }
+        Err(e) => Err(e),
+    }
+}
+
+/// Gives everything in `dir` owned by `from` to `to`
+fn chown_tree(dir: &Path, from: u32, to: u32) -> std::io::Result<()> {
+    let meta = std::fs::symlink_metadata(dir)?;
+    if meta.uid() == from {
+        std::os::unix::fs::lchown(dir, Some(to), None)?;
+    }
+    if meta.is_dir() {
+        for entry in std::fs::read_dir(dir)? {
+            chown_tree(&entry?.path(), from, to)?;
+        }
+    }
+    Ok(())
+}
+
+pub fn main() {
+    let mut args = std::env::args();
+    let prg_name = args.next().unwrap();
+    let mut login_name = None;
+    let mut chroot = None;
+    let mut new_name = None;
+    let mut uid = None;
+    let mut group = None;
+    let mut groups = None;
+    let mut home = None;
+    let mut shell = None;
+    let mut expire_date = None;
+    let mut append = false;
+    let mut move_home_dir = false;
+    let mut lock = false;
+    let mut unlock = false;
+
+    while let Some(s) = args.next() {
+        match &*s {
+            "--help" => {
+                print_help(&prg_name);
+                std::process::exit(0)
+            }
+            "--append" => append = true,
+            "--move-home" => move_home_dir = true,
+            "--lock" => lock = true,
+            "--unlock" => unlock = true,
+            "--root" => chroot = Some(operand(&prg_name, &s, String::new(), &mut args)),
+            "--login" => new_name = Some(operand(&prg_name, &s, String::new(), &mut args)),
+            "--uid" => uid = Some(operand(&prg_name, &s, String::new(), &mut args)),
+            "--gid" => group = Some(operand(&prg_name, &s, String::new(), &mut args)),
+            "--groups" => groups = Some(operand(&prg_name, &s, String::new(), &mut args)),
+            "--home" => home = Some(operand(&prg_name, &s, String::new(), &mut args)),
+            "--shell" => shell = Some(operand(&prg_name, &s, String::new(), &mut args)),
+            "--expiredate" => expire_date = Some(operand(&prg_name, &s, String::new(), &mut args)),
+            "--" => {
+                login_name = args.next();
+                break;
+            }
+            x if x.starts_with("--") => {
+                eprintln!("{}: Unrecognized option {}", prg_name, x);
+                std::process::exit(2)
+            }
+            x if x.starts_with('-') && x.len() > 1 => {
+                let mut chars = x[1..].chars();
+                while let Some(c) = chars.next() {
+                    let opt = format!("-{}", c);
+                    let target = match c {
+                        'h' => {
+                            print_help(&prg_name);
+                            std::process::exit(0)
+                        }
+                        'a' => {
+                            append = true;
+                            continue;
+                        }
+                        'm' => {
+                            move_home_dir = true;
+                            continue;
+                        }
+                        'L' => {
+                            lock = true;
+                            continue;
+                        }
+                        'U' => {
+                            unlock = true;
+                            continue;
+                        }
+                        'R' => &mut chroot,
+                        'l' => &mut new_name,
+                        'u' => &mut uid,
+                        'g' => &mut group,
+                        'G' => &mut groups,
+                        'd' => &mut home,
+                        's' => &mut shell,
+                        'e' => &mut expire_date,
+                        v => {
+                            eprintln!("{}: Unrecognized option -{}", prg_name, v);
+                            std::process::exit(2)
+                        }
+                    };
+                    *target = Some(operand(&prg_name, &opt, chars.collect(), &mut args));
+                    break;
+                }
+            }
+            x => login_name = Some(x.to_string()),
+        }
+    }
+
+    let login_name = match login_name {
+        Some(n) => n,
+        None => {
+            print_help(&prg_name);
+            std::process::exit(2)
+        }
+    };
+
+    if append && groups.is_none() {
+        eprintln!("{}: -a is only allowed with -G", prg_name);
+        std::process::exit(2)
+    }
+    if move_home_dir && home.is_none() {
+        eprintln!("{}: -m is only allowed with -d", prg_name);
+        std::process::exit(2)
+    }
+    if lock && unlock {
+        eprintln!("{}: -L and -U are mutually exclusive", prg_name);
+        std::process::exit(2)
+    }
+
+    if unsafe { libc::getuid() } != 0 {
+        eprintln!("{}: Permission Denied", prg_name);
+        std::process::exit(1)
+    }
+
+    let uid = uid.map(|uid| match uid.parse::<u32>() {
+        Ok(uid) => uid,
+        Err(e) => {
+            eprintln!("{}: Invalid uid {}: {}", prg_name, uid, e);
+            std::process::exit(3)
+        }
+    });
+    let expire_date = expire_date.map(|date| {
+        if date.is_empty() {
+            return None;
+        }
+        match lc_login::aging::parse_date(&date) {
+            Some(v) if v < 0 => None,
+            Some(v) => Some(v),
+            None => {
+                eprintln!("{}: Invalid date {}", prg_name, date);
+                std::process::exit(3)
+            }
+        }
+    });
+    let resolve = |group: &str| match resolve_group(group, chroot.as_deref()) {
+        Ok(gid) => gid,
+        Err(Error::NoSuchGroup) => {
+            eprintln!("{}: Group {} does not exist", prg_name, group);
+            std::process::exit(6)
+        }
+        Err(e) => {
+            eprintln!("{}: {}", prg_name, e);
+            std::process::exit(10)
+        }
+    };
+    let group = group.as_deref().map(resolve);
+    let groups = groups.as_deref().map(|groups| {
+        groups
+            .split(',')
+            .filter(|g| !g.is_empty())
+            .map(resolve)
+            .collect::<Vec<_>>()
+    });
+
+    let handle = match &chroot {
+        Some(chroot) => UserHandle::from_name_in(&*login_name, chroot),
+        None => UserHandle::from_name(&*login_name),
+    };
+    let mut handle = match handle {
+        Ok(handle) => handle,
+        Err(Error::NoSuchUser) => {
+            eprintln!("{}: User {} does not exist", prg_name, login_name);
+            std::process::exit(6)
+        }
+        Err(e) => {
+            eprintln!("{}: {}", prg_name, e);
+            std::process::exit(1)
+        }
+    };
+    let fail = |e: lc_login::Error| -> ! {
+        eprintln!("{}: Failed to modify the user, {}", prg_name, e);
+        std::process::exit(1)
+    };
+    let in_root = |path: &Path| match &chroot {
+        Some(chroot) => Path::new(chroot).join(path.strip_prefix("/").unwrap_or(path)),
+        None => path.to_path_buf(),
+    };
+    let old_home = handle.home().unwrap_or_else(|e| fail(e));
+
+    if let Some(uid) = uid {
+        let old_uid = handle.uid().unwrap_or_else(|e| fail(e));
+        match handle.set_uid(uid) {
+            Ok(()) => {}
+            Err(Error::UserExists) => {
+                eprintln!("{}: Uid {} already exists", prg_name, uid);
+                std::process::exit(4)
+            }
+            Err(e) => fail(e),
+        }
+        if let Some(old_home) = &old_home {
+            match chown_tree(&in_root(old_home), old_uid, uid) {
+                Ok(()) => {}
+                Err(e) if e.kind() == ErrorKind::NotFound => {}
+                Err(e) => eprintln!(
+                    "{}: Warning: failed to change the owner of the home directory {}, {}",
+                    prg_name,
+                    old_home.display(),
+                    e
+                ),
+            }
+        }
+    }
+
+    if let Some(new_name) = &new_name {
+        match handle.set_name(new_name) {
+            Ok(()) => {}
+            Err(Error::UserExists) => {
+                eprintln!("{}: User {} already exists", prg_name, new_name);
+                std::process::exit(9)
+            }
+            Err(Error::InvalidName) => {
+                eprintln!("{}: Invalid user name {}", prg_name, new_name);
+                std::process::exit(3)
+            }
+            Err(e) => fail(e),
+        }
+    }
+
+    if let Some(home) = home.map(PathBuf::from) {
+        if move_home_dir {
+            if let Some(old_home) = old_home.as_ref().filter(|old| **old != home) {
+                if let Err(e) = move_home(&prg_name, &in_root(old_home), &in_root(&home)) {
+                    eprintln!(
+                        "{}: Failed to move the home directory {} to {}, {}",
+                        prg_name,
+                        old_home.display(),
+                        home.display(),
+                        e
+                    );
+                    std::process::exit(12)
+                }
+            }
+        }
+        handle.set_home(&home).unwrap_or_else(|e| fail(e));
+    }
+
+    if let Some(shell) = shell {
+        handle.set_shell(shell).unwrap_or_else(|e| fail(e));
+    }
+
+    if let Some(group) = group {
+        handle.set_primary_group(group).unwrap_or_else(|e| fail(e));
+    }
+
+    if let Some(groups) = groups {
+        let result = if append {
+            groups
+                .into_iter()
+                .try_for_each(|group| handle.add_secondary_group(group))
+        } else {
+            handle.set_secondary_groups(&groups)
+        };
+        result.unwrap_or_else(|e| fail(e));
+    }
+
+    if lock || unlock {
+        let result = if lock {
+            handle.disable_password()
+        } else {
+            handle.enable_password()
+        };
+        match result {
+            Ok(()) => {}
+            Err(Error::Io(e)) if unlock && e.kind() == ErrorKind::NotFound => {
+                eprintln!("{}: User {} has no password", prg_name, login_name);
+                std::process::exit(1)
+            }
+            Err(Error::Locked) => {
+                eprintln!(
+                    "{}: User {} has no password to unlock, set one with passwd",
+                    prg_name, login_name
+                );
+                std::process::exit(1)
+            }
+            Err(e) => fail(e),
+        }
+    }
+
+    if let Some(day) = expire_date {
+        handle.set_account_expiry(day).unwrap_or_else(|e| fail(e));
+    }
+}
@@ -1,25 +1,108 @@
 use std::{
     ffi::OsStr,
-    io::{ErrorKind, Read, Write},
-    mem::forget,
+    io::ErrorKind,
     path::{Path, PathBuf},
-    time::{Duration, SystemTime},
+    time::SystemTime,
 };
 
 use unshare::Command;
 
-use std::os::unix::prelude::*;
+use crate::{
+    aging::{Aging, SECONDS_PER_DAY},
+    groups::GroupHandle,
+    password::{policy::Rejection, PasswordFile, PasswordRecord},
+    Error,
+};
+
+/// The number of days before a password expires during which [`UserHandle::authenticate`] reports [`AuthOutcome::Warning`], if the user's [`Aging::warn_days`] is not set
+pub const DEFAULT_WARN_DAYS: u32 = 7;
 
-use itertools::Itertools;
+/// The result of a call to [`UserHandle::authenticate`] that did not fail
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum AuthOutcome {
+    /// The password is correct
+    Success,
+    /// The password is correct, but has expired, and must be changed before logging in
+    MustChange,
+    /// The password is correct, and expires in `days_left` whole days
+    Warning { days_left: u32 },
+    /// Authentication is disabled for the account. The password was not checked
+    Locked,
+    /// The account has no password, so any password is accepted
+    NoPassword,
+}
+
+impl AuthOutcome {
+    /// Determines the outcome of a correct password that expires at `expiry`, if ever, at the time `now`.
+    ///
+    /// A password is expired from the moment `now` reaches `expiry`.
+    /// It is in its warning period if it expires within `warn_days` days of `now`.
+    pub fn for_expiry(expiry: Option<SystemTime>, now: SystemTime, warn_days: u32) -> Self {
+        let expiry = match expiry {
+            Some(expiry) => expiry,
+            None => return Self::Success,
+        };
+        match expiry.duration_since(now) {
+            Err(_) => Self::MustChange,
+            Ok(left) if left.is_zero() => Self::MustChange,
+            Ok(left) => {
+                let days_left = left.as_secs() / SECONDS_PER_DAY;
+                if days_left < u64::from(warn_days) {
+                    Self::Warning {
+                        days_left: days_left as u32,
+                    }
+                } else {
+                    Self::Success
+                }
+            }
+        }
+    }
+
+    /// Checks whether the user may log in, possibly after changing their password
+    pub fn allows_login(&self) -> bool {
+        !matches!(self, Self::Locked)
+    }
+
+    /// Checks whether the password must be changed before logging in
+    pub fn must_change(&self) -> bool {
+        matches!(self, Self::MustChange)
+    }
+}
+
+/// The account created by [`UserHandle::create`]
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct NewUser {
+    pub name: String,
+    /// The uid, or `None` to allocate one from the [`crate::ids`] ranges
+    pub uid: Option<u32>,
+    /// Allocates the uid from the system range, if `uid` is `None`
+    pub system: bool,
+    pub group: u32,
+    pub groups: Vec<u32>,
+    pub home: Option<PathBuf>,
+    pub shell: Option<PathBuf>,
+}
 
-use crate::password::PasswordHeader;
+/// Checks whether `name` can be used as a user or group name.
+///
+/// Names may not be empty, start with `-`, consist only of digits, which would collide with the id directories, or contain `/`, `:`, `,` or whitespace, which would break the passwd(5) and group(5) formats.
+pub fn is_valid_name(name: &str) -> bool {
+    !name.is_empty()
+        && name != "."
+        && name != ".."
+        && !name.starts_with('-')
+        && !name.bytes().all(|b| b.is_ascii_digit())
+        && !name
+            .chars()
+            .any(|c| c == '/' || c == ':' || c == ',' || c.is_whitespace() || c.is_control())
+}
 
 pub struct UserHandle {
     path: PathBuf,
 }
 
 impl UserHandle {
-    pub fn from_name<S: AsRef<OsStr>>(name: S) -> std::io::Result<Self> {
+    pub fn from_name<S: AsRef<OsStr>>(name: S) -> crate::Result<Self> {
         let mut path = PathBuf::from(*crate::dirs::USERS);
         path.push(name.as_ref());
         match std::fs::read_link(&path) {
@@ -28,7 +111,8 @@ impl UserHandle {
                 path.push(p);
             }
             Err(e) if e.kind() == ErrorKind::InvalidInput => {}
-            Err(e) => return Err(e),
+            Err(e) if e.kind() == ErrorKind::NotFound => return Err(Error::NoSuchUser),
+            Err(e) => return Err(e.into()),
         }
 
         Ok(Self { path })
@@ -43,7 +127,7 @@ impl UserHandle {
     pub fn from_name_in<S: AsRef<OsStr>, P: AsRef<Path>>(
         name: S,
         chroot: P,
-    ) -> std::io::Result<Self> {
+    ) -> crate::Result<Self> {
         let mut path = PathBuf::from(chroot.as_ref());
 
         path.push(crate::dirs::USERS.strip_prefix("/").unwrap());
@@ -59,7 +143,8 @@ impl UserHandle {
                 }
             }
             Err(e) if e.kind() == ErrorKind::InvalidInput => {}
-            Err(e) => return Err(e),
+            Err(e) if e.kind() == ErrorKind::NotFound => return Err(Error::NoSuchUser),
+            Err(e) => return Err(e.into()),
         }
 
         Ok(Self { path })
@@ -74,467 +159,688 @@ impl UserHandle {
         Self { path }
     }
 
+    /// Creates a new user in [`crate::dirs::USERS`]
+    pub fn create(user: &NewUser) -> crate::Result<Self> {
+        Self::create_from(user, None)
+    }
+
+    /// Creates a new user within `chroot`.
+    ///
+    /// The uid is allocated from the [`crate::ids`] ranges configured within `chroot`, unless [`NewUser::uid`] is set.
+    /// The user directory is built in full before it becomes visible, so a failed or concurrent creation never leaves a partial user behind.
+    /// The password starts out [`PasswordFile::locked`]. Fails with [`Error::NoSuchGroup`] if the primary group or one of the supplementary groups does not exist.
+    pub fn create_in<P: AsRef<Path>>(user: &NewUser, chroot: P) -> crate::Result<Self> {
+        Self::create_from(user, Some(chroot.as_ref()))
+    }
+
+    fn create_from(user: &NewUser, chroot: Option<&Path>) -> crate::Result<Self> {
+        if !is_valid_name(&user.name) {
+            return Err(Error::InvalidName);
+        }
+        let (mut dir, ranges) = match chroot {
+            Some(chroot) => (PathBuf::from(chroot), crate::ids::read_ranges_in(chroot)?),
+            None => (PathBuf::from("/"), crate::ids::read_ranges()?),
+        };
+        let root = chroot.unwrap_or_else(|| Path::new("/"));
+        for gid in std::iter::once(&user.group).chain(&user.groups) {
+            if !GroupHandle::from_uid_in(*gid, root).user_dir().is_dir() {
+                return Err(Error::NoSuchGroup);
+            }
+        }
+        dir.push(crate::dirs::USERS.strip_prefix("/").unwrap());
+        std::fs::create_dir_all(&dir)?;
+
+        let (path, uid) = crate::tree::create(
+            &dir,
+            &user.name,
+            user.uid,
+            ranges.uid_candidates(user.system),
+            "uid",
+            Error::UserExists,
+            |path| {
+                let mut handle = Self {
+                    path: path.to_path_buf(),
+                };
+                // Locked until a password is set
+                crate::password::update(handle.password_path(), |_| {
+                    Ok(Some(PasswordFile::locked()))
+                })?;
+                handle.set_primary_group(user.group)?;
+                handle.update_secondary_groups(|groups| *groups = user.groups.clone())?;
+                if let Some(home) = &user.home {
+                    handle.set_home(home)?;
+                }
+                if let Some(shell) = &user.shell {
+                    handle.set_shell(shell)?;
+                }
+                Ok(())
+            },
+        )?;
+        for gid in &user.groups {
+            GroupHandle::from_uid_in(*gid, root).update_members(|members| members.push(uid))?;
+        }
+        Ok(Self { path })
+    }
+
+    /// Deletes the user: its name symlink, and the user directory, along with the password, aging and history files, and removes it from the member and administrator lists of the groups.
+    ///
+    /// The directory is renamed to `<uid>-` before its contents are removed, so the user disappears at once, even if the removal fails part way.
+    pub fn delete(self) -> crate::Result<()> {
+        let uid = match self.uid() {
+            Ok(uid) => uid,
+            Err(Error::Io(e)) if e.kind() == ErrorKind::NotFound => return Err(Error::NoSuchUser),
+            Err(e) => return Err(e),
+        };
+        // Every group is checked, in case the member lists have drifted from the user's groups file
+        for group in crate::groups::iter_in(self.chroot()?)?.filter_map(Result::ok) {
+            if group.members()?.contains(&uid) {
+                group.update_members(|members| members.retain(|v| *v != uid))?;
+            }
+            if group.is_admin(uid)? {
+                group.update_admins(|admins| admins.retain(|v| *v != uid))?;
+            }
+        }
+        crate::tree::remove(&self.path, Error::NoSuchUser)
+    }
+
     pub fn user_dir(&self) -> &Path {
         &self.path
     }
 
-    pub fn name(&self) -> std::io::Result<Option<String>> {
+    pub fn name(&self) -> crate::Result<Option<String>> {
         let mut path = self.path.clone();
         path.push("name");
         match std::fs::read_link(path) {
-            Ok(p) => Some(
-                p.file_name()
-                    .ok_or_else(|| std::io::Error::new(ErrorKind::InvalidData, "Invalid name stem"))
-                    .and_then(|v| {
-                        v.to_str()
-                            .ok_or_else(|| std::io::Error::new(ErrorKind::InvalidData, "Non-UTF8"))
-                    })
-                    .map(<str>::to_string),
-            )
-            .transpose(),
+            Ok(p) => p
+                .file_name()
+                .and_then(OsStr::to_str)
+                .map(|v| Some(v.to_string()))
+                .ok_or(Error::Corrupt("Invalid name symlink")),
             Err(e) if e.kind() == ErrorKind::NotFound => Ok(None),
-            Err(e) => Err(e),
+            Err(e) => Err(e.into()),
         }
     }
 
-    pub fn set_name<S: AsRef<OsStr>>(&self, st: S) -> std::io::Result<()> {
-        if let Some(s) = self.name()? {
-            let mut path = self.path.clone();
-            path.push(s);
-            let _ = std::fs::remove_file(path);
+    /// Renames the user.
+    ///
+    /// The new name is claimed before the old one is released, so the user is reachable by at least one of them throughout.
+    /// Fails with [`Error::UserExists`] if another user has the name.
+    pub fn set_name<S: AsRef<str>>(&self, name: S) -> crate::Result<()> {
+        if !is_valid_name(name.as_ref()) {
+            return Err(Error::InvalidName);
         }
-        let mut path = self.path.clone();
-        path.pop();
-        path.push(st.as_ref());
-        std::os::unix::fs::symlink(&self.path, &path)?;
-        let mut path2 = self.path.clone();
-        path2.push("name");
-        std::os::unix::fs::symlink(path, path2)
+        crate::tree::rename(&self.path, name.as_ref(), Error::UserExists)
     }
 
-    pub fn shell(&self) -> std::io::Result<Option<PathBuf>> {
+    /// Changes the uid of the user, by renaming the user directory.
+    ///
+    /// The member and administrator lists of the groups are updated to the new uid.
+    /// Fails with [`Error::UserExists`] if another user has the uid. Files owned by the old uid are not changed.
+    pub fn set_uid(&mut self, uid: u32) -> crate::Result<()> {
+        if !self.path.is_dir() {
+            return Err(Error::NoSuchUser);
+        }
+        let old = self.uid()?;
+        self.path = crate::tree::renumber(&self.path, uid, "uid", Error::UserExists)?;
+        for gid in self.secondary_groups()? {
+            match self.group(gid)?.update_members(|members| {
+                members.retain(|v| *v != old);
+                members.push(uid);
+            }) {
+                Ok(()) | Err(Error::NoSuchGroup) => {}
+                Err(e) => return Err(e),
+            }
+        }
+        for group in crate::groups::iter_in(self.chroot()?)?.filter_map(Result::ok) {
+            if group.is_admin(old)? {
+                group.update_admins(|admins| {
+                    admins.retain(|v| *v != old);
+                    admins.push(uid);
+                })?;
+            }
+        }
+        Ok(())
+    }
+
+    pub fn shell(&self) -> crate::Result<Option<PathBuf>> {
         let mut path = self.path.clone();
         path.push("shell");
         match std::fs::read_link(path) {
             Ok(p) => Ok(Some(p)),
             Err(e) if e.kind() == ErrorKind::NotFound => Ok(None),
-            Err(e) => Err(e),
+            Err(e) => Err(e.into()),
         }
     }
 
-    pub fn home(&self) -> std::io::Result<Option<PathBuf>> {
+    pub fn home(&self) -> crate::Result<Option<PathBuf>> {
         let mut path = self.path.clone();
         path.push("home");
         match std::fs::read_link(path) {
             Ok(p) => Ok(Some(p)),
             Err(e) if e.kind() == ErrorKind::NotFound => Ok(None),
-            Err(e) => Err(e),
+            Err(e) => Err(e.into()),
         }
     }
 
-    pub fn root(&self) -> std::io::Result<Option<PathBuf>> {
+    pub fn root(&self) -> crate::Result<Option<PathBuf>> {
         let mut path = self.path.clone();
         path.push("root");
         match std::fs::read_link(path) {
             Ok(p) => Ok(Some(p)),
             Err(e) if e.kind() == ErrorKind::NotFound => Ok(None),
-            Err(e) => Err(e),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// The GECOS field of passwd(5), such as the user's full name, kept in the `gecos` file
+    pub fn gecos(&self) -> crate::Result<Option<String>> {
+        let mut path = self.path.clone();
+        path.push("gecos");
+        match std::fs::read(path) {
+            Ok(bytes) => String::from_utf8(bytes)
+                .map(|s| Some(s.trim_end_matches('\n').to_string()))
+                .map_err(|_| Error::Corrupt("Invalid gecos file")),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e.into()),
         }
     }
 
-    pub fn set_home<P: AsRef<Path>>(&mut self, p: P) -> std::io::Result<()> {
+    pub fn set_home<P: AsRef<Path>>(&mut self, p: P) -> crate::Result<()> {
         let mut path = self.path.clone();
         path.push("home");
-        std::os::unix::fs::symlink(p, path)
+        crate::atomic::replace_symlink(p, &path)
     }
 
-    pub fn set_shell<P: AsRef<Path>>(&mut self, p: P) -> std::io::Result<()> {
+    pub fn set_shell<P: AsRef<Path>>(&mut self, p: P) -> crate::Result<()> {
         let mut path = self.path.clone();
         path.push("shell");
-        std::os::unix::fs::symlink(p, path)
+        crate::atomic::replace_symlink(p, &path)
     }
 
-    pub fn set_root<P: AsRef<Path>>(&mut self, p: P) -> std::io::Result<()> {
+    pub fn set_root<P: AsRef<Path>>(&mut self, p: P) -> crate::Result<()> {
         let mut path = self.path.clone();
         path.push("root");
-        std::os::unix::fs::symlink(p, path)
+        crate::atomic::replace_symlink(p, &path)
     }
 
-    pub fn has_password(&self) -> std::io::Result<bool> {
+    pub fn has_password(&self) -> crate::Result<bool> {
         let mut path = self.path.clone();
         path.push("password");
         match std::fs::metadata(path) {
             Ok(_) => Ok(true),
             Err(e) if e.kind() == ErrorKind::NotFound => Ok(false),
-            Err(e) => Err(e),
+            Err(e) => Err(e.into()),
         }
     }
 
-    pub fn remove_password(&self) -> std::io::Result<()> {
+    pub fn remove_password(&self) -> crate::Result<()> {
         let mut path = self.path.clone();
         path.push("password");
         match std::fs::remove_file(path) {
             Ok(_) => Ok(()),
             Err(e) if e.kind() == ErrorKind::NotFound => Ok(()),
-            Err(e) => Err(e),
+            Err(e) => Err(e.into()),
         }
     }
 
-    pub fn authenticate(&self, passwd: &str) -> std::io::Result<bool> {
+    fn password_path(&self) -> PathBuf {
         let mut path = self.path.clone();
         path.push("password");
-        let mut file = std::fs::File::open(path)?;
-        let mut header = PasswordHeader::default();
-        file.read_exact(bytemuck::bytes_of_mut(&mut header))?;
-        if header.version == crate::password::INVALID_VERSION {
-            return Err(std::io::Error::new(
-                ErrorKind::InvalidData,
-                "Invalid Authentication File",
-            ));
-        }
+        path
+    }
 
-        if header.algorithm == crate::password::algorithms::DISABLED
-            || header.salt_and_repetition & crate::password::salting::MASK
-                == crate::password::salting::DISABLED
-        {
-            return Err(std::io::Error::new(
-                ErrorKind::Other,
-                "Account has authentication disabled",
-            ));
+    /// Reads and parses the user's password file
+    pub fn password_file(&self) -> crate::Result<PasswordFile> {
+        match PasswordFile::open(self.password_path()) {
+            Err(Error::Io(e)) if e.kind() == ErrorKind::NotFound && !self.path.exists() => {
+                Err(Error::NoSuchUser)
+            }
+            r => r,
         }
+    }
 
-        let mut salt = vec![0u8; header.salt_size as usize];
-        file.read_exact(&mut salt)?;
-        let mut bytes = Vec::new();
-        file.read_to_end(&mut bytes)?;
-        let mut checked = Vec::new();
-        crate::password::write_password(
-            passwd,
-            &salt,
-            header.algorithm,
-            header.salt_and_repetition,
-            &mut checked,
-        )?;
-        if bytes.len() != checked.len() {
-            return Err(std::io::Error::new(
-                ErrorKind::Other,
-                "Password is incorrect",
-            ));
+    /// Checks `passwd` against the user's password, and applies the user's [`Aging`] rules.
+    ///
+    /// The password expires at the earlier of the expiry in the password file and [`Aging::password_expiry`].
+    /// Fails with [`Error::WrongPassword`] if the password is incorrect, and with [`Error::Expired`] if the account has expired or the password has been expired for longer than the inactivity period.
+    pub fn authenticate(&self, passwd: &str) -> crate::Result<AuthOutcome> {
+        let now = SystemTime::now();
+        let today = crate::aging::day_of(now);
+        let record = match self.password_file() {
+            Ok(PasswordFile::Enabled(record)) => record,
+            Ok(PasswordFile::Disabled(_)) => return Ok(AuthOutcome::Locked),
+            Err(Error::Io(e)) if e.kind() == ErrorKind::NotFound => {
+                return if self.aging()?.is_account_expired(today) {
+                    Err(Error::Expired)
+                } else {
+                    Ok(AuthOutcome::NoPassword)
+                };
+            }
+            Err(e) => return Err(e),
+        };
+        if !record.verify(passwd)? {
+            return Err(Error::WrongPassword);
         }
-        if openssl::memcmp::eq(&bytes, &checked) {
-            Ok(header.expiry_seconds != 0
-                && matches!(
-                    (SystemTime::UNIX_EPOCH + Duration::from_secs(header.expiry_seconds)).elapsed(),
-                    Ok(_)
-                ))
-        } else {
-            Err(std::io::Error::new(
-                ErrorKind::Other,
-                "Password is incorrect",
-            ))
+        let aging = self.aging()?;
+        if aging.is_account_expired(today) || aging.is_inactive(today) {
+            return Err(Error::Expired);
         }
+        let expiry = match (
+            record.expiry,
+            aging.password_expiry().map(crate::aging::start_of_day),
+        ) {
+            (Some(a), Some(b)) => Some(a.min(b)),
+            (a, b) => a.or(b),
+        };
+        let warn_days = aging
+            .warn_days
+            .map_or(DEFAULT_WARN_DAYS, |days| days.max(0) as u32);
+        Ok(AuthOutcome::for_expiry(expiry, now, warn_days))
     }
 
-    pub fn expire_password(&self, at: Option<SystemTime>) -> std::io::Result<()> {
+    fn update_password_file<F: FnOnce(PasswordFile) -> Option<PasswordFile>>(
+        &self,
+        f: F,
+    ) -> crate::Result<()> {
+        crate::password::update(self.password_path(), |file| match file {
+            Some(file) => Ok(f(file)),
+            None => Err(Error::Io(std::io::Error::new(
+                ErrorKind::NotFound,
+                "User has no password",
+            ))),
+        })
+    }
+
+    pub fn expire_password(&self, at: Option<SystemTime>) -> crate::Result<()> {
         let at = at.unwrap_or_else(SystemTime::now);
+        self.update_password_file(|mut file| {
+            // The password file stores no expiry as 0, so expiring at the epoch removes the expiry
+            file.record_mut().expiry = Some(at).filter(|at| *at != SystemTime::UNIX_EPOCH);
+            Some(file)
+        })
+    }
 
-        let mut path = self.path.clone();
-        path.push("password-");
-        let mut passwd_path = self.path.clone();
-        passwd_path.push("password");
-        let mut backup = std::fs::OpenOptions::new()
-            .write(true)
-            .create_new(true)
-            .mode(0o600)
-            .open(&path)?;
-        let defer = defer::defer(|| drop(std::fs::remove_file(&path)));
-        let mut file = std::fs::File::open(&passwd_path)?;
-        let mut header = PasswordHeader::default();
-        file.read_exact(bytemuck::bytes_of_mut(&mut header))?;
-        if header.version == crate::password::INVALID_VERSION {
-            return Err(std::io::Error::new(
-                ErrorKind::InvalidData,
-                "Invalid Authentication File",
-            ));
+    pub fn unexpire_password(&self) -> crate::Result<()> {
+        self.update_password_file(|mut file| {
+            file.record_mut().expiry = None;
+            Some(file)
+        })
+    }
+
+    /// Locks the password, keeping it to be restored by [`UserHandle::enable_password`].
+    ///
+    /// A user without a password is locked with [`PasswordFile::locked`].
+    pub fn disable_password(&self) -> crate::Result<()> {
+        if !self.path.is_dir() {
+            return Err(Error::NoSuchUser);
         }
-        header.expiry_seconds = at
-            .duration_since(SystemTime::UNIX_EPOCH)
-            .map_err(|e| std::io::Error::new(ErrorKind::InvalidData, e))?
-            .as_secs();
-        let mut salt = vec![0u8; header.salt_size as usize];
-        file.read_exact(&mut salt)?;
-        let mut bytes = Vec::new();
-        file.read_to_end(&mut bytes)?;
-        backup.write_all(bytemuck::bytes_of(&header))?;
-        backup.write_all(&salt)?;
-        backup.write_all(&bytes)?;
-        std::fs::rename(&path, &passwd_path)?;
-        forget(defer);
-        Ok(())
+        crate::password::update(self.password_path(), |file| match file {
+            Some(PasswordFile::Enabled(record)) => Ok(Some(PasswordFile::Disabled(record))),
+            Some(PasswordFile::Disabled(_)) => Ok(None), // Already Disabled, no need to disable multiple times
+            None => Ok(Some(PasswordFile::locked())),
+        })
+    }
+
+    /// Unlocks the password, restoring the one that was locked.
+    ///
+    /// Fails with [`Error::Locked`] if there is no password to restore, as for a user that was locked by [`PasswordFile::locked`].
+    pub fn enable_password(&self) -> crate::Result<()> {
+        crate::password::update(self.password_path(), |file| match file {
+            Some(PasswordFile::Disabled(record)) if !record.is_usable() => Err(Error::Locked),
+            Some(PasswordFile::Disabled(record)) => Ok(Some(PasswordFile::Enabled(record))),
+            Some(PasswordFile::Enabled(_)) => Ok(None), // Already Enabled
+            None => Err(Error::Io(std::io::Error::new(
+                ErrorKind::NotFound,
+                "User has no password",
+            ))),
+        })
+    }
+
+    /// Hashes `passwd` with the current authtemplate, and records today as the [`Aging::last_change`].
+    ///
+    /// The replaced password is kept in the `password-history`, up to the depth configured by the password policy.
+    pub fn set_password(&self, passwd: &str) -> crate::Result<()> {
+        let (header, params) = crate::password::read_template()?;
+        let remember = crate::password::policy::read_policy()?.remember;
+        let record = PasswordRecord::new(passwd, &header, params)?;
+        self.replace_password(record, remember)?;
+        self.set_last_change(Some(crate::aging::today()))
     }
 
-    pub fn unexpire_password(&self) -> std::io::Result<()> {
+    /// Replaces the password with `record`, keeping the replaced one in the `password-history`, up to `remember` deep.
+    ///
+    /// The history is updated first, while the password file is locked, so if that fails, the password is left unchanged.
+    fn replace_password(&self, record: PasswordRecord, remember: usize) -> crate::Result<()> {
+        crate::password::update(self.password_path(), |current| {
+            if let Some(current) = current.filter(|file| file.record().is_usable()) {
+                self.push_history(current.record().clone(), remember)?;
+            }
+            Ok(Some(PasswordFile::Enabled(record)))
+        })
+    }
+
+    fn history_path(&self) -> PathBuf {
         let mut path = self.path.clone();
-        path.push("password-");
-        let mut passwd_path = self.path.clone();
-        passwd_path.push("password");
-        let mut backup = std::fs::OpenOptions::new()
-            .write(true)
-            .create_new(true)
-            .mode(0o600)
-            .open(&path)?;
-        let defer = defer::defer(|| drop(std::fs::remove_file(&path)));
-        let mut file = std::fs::File::open(&passwd_path)?;
-        let mut header = PasswordHeader::default();
-        file.read_exact(bytemuck::bytes_of_mut(&mut header))?;
-        if header.version == crate::password::INVALID_VERSION {
-            return Err(std::io::Error::new(
-                ErrorKind::InvalidData,
-                "Invalid Authentication File",
-            ));
+        path.push("password-history");
+        path
+    }
+
+    /// Reads the user's previous passwords, newest first.
+    ///
+    /// The `password-history` file is a sequence of password files, each preceded by its length as a little-endian `u32`.
+    pub fn password_history(&self) -> crate::Result<Vec<PasswordRecord>> {
+        match std::fs::read(self.history_path()) {
+            Ok(bytes) => parse_history(&bytes),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(Vec::new()),
+            Err(e) => Err(e.into()),
         }
-        header.expiry_seconds = 0;
-        let mut salt = vec![0u8; header.salt_size as usize];
-        file.read_exact(&mut salt)?;
-        let mut bytes = Vec::new();
-        file.read_to_end(&mut bytes)?;
-        backup.write_all(bytemuck::bytes_of(&header))?;
-        backup.write_all(&salt)?;
-        backup.write_all(&bytes)?;
-        std::fs::rename(&path, &passwd_path)?;
-        forget(defer);
-        Ok(())
     }
 
-    pub fn disable_password(&self) -> std::io::Result<()> {
-        let mut path = self.path.clone();
-        path.push("password-");
-        let mut passwd_path = self.path.clone();
-        passwd_path.push("password");
-        let mut backup = std::fs::OpenOptions::new()
-            .write(true)
-            .create_new(true)
-            .mode(0o600)
-            .open(&path)?;
-        let defer = defer::defer(|| drop(std::fs::remove_file(&path)));
-        let mut file = std::fs::File::open(&passwd_path)?;
-        let mut header = PasswordHeader::default();
-        file.read_exact(bytemuck::bytes_of_mut(&mut header))?;
-        if header.version == crate::password::INVALID_VERSION {
-            return Err(std::io::Error::new(
-                ErrorKind::InvalidData,
-                "Invalid Authentication File",
-            ));
+    fn push_history(&self, record: PasswordRecord, remember: usize) -> crate::Result<()> {
+        if remember == 0 {
+            return Ok(());
         }
-        if header.algorithm == crate::password::algorithms::DISABLED
-            || header.salt_and_repetition & crate::password::salting::MASK
-                == crate::password::salting::DISABLED
-        {
-            return Ok(()); // Already Disabled, no need to disable multiple times
+        crate::atomic::update(&self.history_path(), 0o600, |current| {
+            let mut history = match current {
+                Some(bytes) => parse_history(&bytes)?,
+                None => Vec::new(),
+            };
+            history.insert(0, record);
+            history.truncate(remember);
+            let mut bytes = Vec::new();
+            for record in history {
+                let file = PasswordFile::Enabled(record).to_bytes();
+                bytes.extend_from_slice(&(file.len() as u32).to_le_bytes());
+                bytes.extend_from_slice(&file);
+            }
+            Ok(Some(bytes))
+        })
+    }
+
+    /// Checks whether `passwd` is the current password, or one of the previous passwords in the `password-history`
+    pub fn is_password_reused(&self, passwd: &str) -> crate::Result<bool> {
+        match self.password_file() {
+            Ok(file) if file.record().is_usable() && file.record().verify(passwd)? => {
+                return Ok(true)
+            }
+            Ok(_) => {}
+            Err(Error::Io(e)) if e.kind() == ErrorKind::NotFound => {}
+            Err(e) => return Err(e),
+        }
+        for record in self.password_history()? {
+            if record.is_usable() && record.verify(passwd)? {
+                return Ok(true);
+            }
         }
+        Ok(false)
+    }
 
-        let disabled_header = PasswordHeader {
-            version: crate::password::CURRENT_VERSION,
-            salt_size: 0,
-            ..PasswordHeader::default()
+    /// Checks `passwd` against the password policy, including the user name and the `password-history`.
+    ///
+    /// The `old` password is only compared if it is known, such as when the user has just entered it.
+    pub fn check_new_password(
+        &self,
+        passwd: &str,
+        old: Option<&str>,
+    ) -> crate::Result<Option<Rejection>> {
+        let policy = crate::password::policy::read_policy()?;
+        let name = self.name()?;
+        if let Some(rejection) = policy.check(passwd, name.as_deref(), old)? {
+            return Ok(Some(rejection));
+        }
+        if policy.remember != 0 && self.is_password_reused(passwd)? {
+            return Ok(Some(Rejection::Reused));
+        }
+        Ok(None)
+    }
+
+    /// Checks whether the password file was hashed with a different algorithm, rounds, salt size or cost than the current authtemplate, or is a [`crate::password::LEGACY_VERSION`] file.
+    ///
+    /// Disabled passwords never need an upgrade.
+    pub fn needs_upgrade(&self) -> crate::Result<bool> {
+        let record = match self.password_file()? {
+            PasswordFile::Enabled(record) => record,
+            PasswordFile::Disabled(_) => return Ok(false),
         };
-        let mut salt = vec![0u8; header.salt_size as usize];
-        file.read_exact(&mut salt)?;
-        let mut bytes = Vec::new();
-        file.read_to_end(&mut bytes)?;
-        backup.write_all(bytemuck::bytes_of(&disabled_header))?;
-        backup.write_all(bytemuck::bytes_of(&header))?;
-        backup.write_all(&salt)?;
-        backup.write_all(&bytes)?;
-        std::fs::rename(&path, &passwd_path)?;
-        forget(defer);
-        Ok(())
+        let (template, params) = crate::password::read_template()?;
+        Ok(record.version == crate::password::LEGACY_VERSION
+            || record.algorithm != template.algorithm
+            || record.salt_and_repetition != template.salt_and_repetition
+            || record.salt.len() != template.salt_size as usize
+            || record.params != params)
     }
 
-    pub fn enable_password(&self) -> std::io::Result<()> {
-        let mut path = self.path.clone();
-        path.push("password-");
-        let mut passwd_path = self.path.clone();
-        passwd_path.push("password");
-        let mut backup = std::fs::OpenOptions::new()
-            .write(true)
-            .create_new(true)
-            .mode(0o600)
-            .open(&path)?;
-        let defer = defer::defer(|| drop(std::fs::remove_file(&path)));
-        let mut file = std::fs::File::open(&passwd_path)?;
-        let mut header = PasswordHeader::default();
-        file.read_exact(bytemuck::bytes_of_mut(&mut header))?;
-        if header.version == crate::password::INVALID_VERSION {
-            return Err(std::io::Error::new(
-                ErrorKind::InvalidData,
-                "Invalid Authentication File",
-            ));
-        }
-        if header.algorithm != crate::password::algorithms::DISABLED
-            && header.salt_and_repetition & crate::password::salting::MASK
-                != crate::password::salting::DISABLED
-        {
-            return Ok(()); // Already Enabled
+    /// Authenticates like [`UserHandle::authenticate`], then rehashes the password with the current authtemplate if [`UserHandle::needs_upgrade`].
+    ///
+    /// The upgrade is best-effort: if it fails (for example, because the password file is busy), the old hash is kept and the result of authentication is still returned.
+    /// The expiry time of the password is preserved.
+    pub fn authenticate_and_upgrade(&self, passwd: &str) -> crate::Result<AuthOutcome> {
+        let outcome = self.authenticate(passwd)?;
+        match outcome {
+            AuthOutcome::Locked | AuthOutcome::NoPassword => {}
+            _ => {
+                if let Ok(true) = self.needs_upgrade() {
+                    let _ = self.upgrade_password(passwd);
+                }
+            }
         }
+        Ok(outcome)
+    }
 
-        file.read_exact(bytemuck::bytes_of_mut(&mut header))?;
-        let mut salt = vec![0u8; header.salt_size as usize];
-        file.read_exact(&mut salt)?;
-        let mut bytes = Vec::new();
-        file.read_to_end(&mut bytes)?;
+    fn upgrade_password(&self, passwd: &str) -> crate::Result<()> {
+        let (header, params) = crate::password::read_template()?;
+        let mut record = PasswordRecord::new(passwd, &header, params)?;
+        crate::password::update(self.password_path(), |file| match file {
+            // Only the password that was authenticated is replaced, not one set since then
+            Some(PasswordFile::Enabled(old)) if old.verify(passwd)? => {
+                record.expiry = old.expiry;
+                Ok(Some(PasswordFile::Enabled(record)))
+            }
+            _ => Ok(None), // Changed under us, leave it alone
+        })
+    }
 
-        backup.write_all(bytemuck::bytes_of(&header))?;
-        backup.write_all(&salt)?;
-        backup.write_all(&bytes)?;
-        std::fs::rename(&path, &passwd_path)?;
-        forget(defer);
-        Ok(())
+    /// Sets the password to a crypt(3) hash migrated from a shadow file, which is verified as-is until the next [`UserHandle::set_password`]
+    pub fn set_crypt_password(&self, hash: &str) -> crate::Result<()> {
+        let record = PasswordRecord::crypt(hash)?;
+        crate::password::update(self.password_path(), |_| {
+            Ok(Some(PasswordFile::Enabled(record)))
+        })
     }
 
-    pub fn set_password(&self, passwd: &str) -> std::io::Result<()> {
+    /// Replaces the password file with `file`, for example one copied from another host, and records today as the [`Aging::last_change`]
+    pub fn set_password_file(&self, file: PasswordFile) -> crate::Result<()> {
+        crate::password::update(self.password_path(), |_| Ok(Some(file)))?;
+        self.set_last_change(Some(crate::aging::today()))
+    }
+
+    fn aging_path(&self) -> PathBuf {
         let mut path = self.path.clone();
-        path.push("password-"); // Use the password write file, so that authenticate never observes a broken write
-        let mut file = std::fs::OpenOptions::new()
-            .write(true)
-            .create_new(true)
-            .mode(0o600)
-            .open(&path)?;
-        let defer = defer::defer(|| drop(std::fs::remove_file(&path)));
-        let mut passwd_path = self.path.clone();
-        passwd_path.push("password");
-        let mut authtemplate = crate::dirs::INSTALL_DIRS.sysconfdir.clone();
-        authtemplate.push("authtemplate");
-        let mut header = PasswordHeader::default();
-        match std::fs::File::open(authtemplate) {
-            Ok(mut f) => f.read_exact(bytemuck::bytes_of_mut(&mut header))?,
-            Err(e) if e.kind() == ErrorKind::NotFound => {
-                header = PasswordHeader {
-                    version: crate::password::CURRENT_VERSION,
-                    algorithm: crate::password::DEFAULT_ALGORITHM,
-                    salt_and_repetition: crate::password::DEFAULT_SALT
-                        | crate::password::DEFAULT_ROUNDS,
-                    salt_size: 31,
-                    expiry_seconds: 0,
-                }
-            }
-            Err(e) => return Err(e),
+        path.push("aging");
+        path
+    }
+
+    /// Reads the user's password aging metadata. A user without an aging file has no aging rules
+    pub fn aging(&self) -> crate::Result<Aging> {
+        match std::fs::read_to_string(self.aging_path()) {
+            Ok(s) => Aging::parse(&s),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(Aging::default()),
+            Err(e) => Err(e.into()),
         }
-        file.write_all(bytemuck::bytes_of(&header))?;
-        let mut salt = vec![0u8; header.salt_size as usize];
-        openssl::rand::rand_bytes(&mut salt)
-            .map_err(|e| std::io::Error::new(ErrorKind::Other, e))?;
-        file.write_all(&salt)?;
-        crate::password::write_password(
-            passwd,
-            &salt,
-            header.algorithm,
-            header.salt_and_repetition,
-            file,
-        )?;
-        std::fs::rename(&path, &passwd_path)?;
-        forget(defer);
-        Ok(())
     }
 
-    pub fn uid(&self) -> std::io::Result<libc::uid_t> {
+    /// Atomically replaces the user's password aging metadata
+    pub fn set_aging(&self, aging: &Aging) -> crate::Result<()> {
+        self.update_aging(|current| *current = *aging)
+    }
+
+    /// Atomically modifies the user's password aging metadata
+    pub fn update_aging<F: FnOnce(&mut Aging)>(&self, f: F) -> crate::Result<()> {
+        // Not world-readable, like shadow(5)
+        crate::atomic::update(&self.aging_path(), 0o600, |current| {
+            let mut aging = match current {
+                Some(bytes) => Aging::parse(
+                    std::str::from_utf8(&bytes)
+                        .map_err(|_| Error::Corrupt("Invalid aging file"))?,
+                )?,
+                None => Aging::default(),
+            };
+            f(&mut aging);
+            Ok(Some(format!("{}\n", aging).into_bytes()))
+        })
+    }
+
+    pub fn set_last_change(&self, day: Option<i64>) -> crate::Result<()> {
+        self.update_aging(|aging| aging.last_change = day)
+    }
+
+    pub fn set_min_days(&self, days: Option<i64>) -> crate::Result<()> {
+        self.update_aging(|aging| aging.min_days = days)
+    }
+
+    pub fn set_max_days(&self, days: Option<i64>) -> crate::Result<()> {
+        self.update_aging(|aging| aging.max_days = days)
+    }
+
+    pub fn set_warn_days(&self, days: Option<i64>) -> crate::Result<()> {
+        self.update_aging(|aging| aging.warn_days = days)
+    }
+
+    pub fn set_inactive_days(&self, days: Option<i64>) -> crate::Result<()> {
+        self.update_aging(|aging| aging.inactive_days = days)
+    }
+
+    pub fn set_account_expiry(&self, day: Option<i64>) -> crate::Result<()> {
+        self.update_aging(|aging| aging.expire_date = day)
+    }
+
+    pub fn uid(&self) -> crate::Result<libc::uid_t> {
         let mut path = self.path.clone();
         path.push("uid");
-        std::fs::read_link(path)
-            .and_then(|e| {
-                e.file_name()
-                    .ok_or_else(|| {
-                        std::io::Error::new(ErrorKind::InvalidData, "Invalid path in symlink")
-                    })
-                    .map(|s| s.to_owned())
-            })
-            .and_then(|s| {
-                s.into_string().map_err(|_| {
-                    std::io::Error::new(ErrorKind::InvalidData, "Non-UTF path in symlink")
-                })
-            })
-            .and_then(|s| {
-                s.parse()
-                    .map_err(|e| std::io::Error::new(ErrorKind::InvalidData, e))
-            })
+        std::fs::read_link(path)?
+            .file_name()
+            .and_then(OsStr::to_str)
+            .ok_or(Error::Corrupt("Invalid path in uid symlink"))?
+            .parse()
+            .map_err(|_| Error::Corrupt("Invalid path in uid symlink"))
     }
 
-    pub fn primary_group(&self) -> std::io::Result<libc::gid_t> {
+    pub fn primary_group(&self) -> crate::Result<libc::gid_t> {
         let mut path = self.path.clone();
         path.push("group");
-        std::fs::read_link(path)
-            .and_then(|e| {
-                e.file_name()
-                    .ok_or_else(|| {
-                        std::io::Error::new(ErrorKind::InvalidData, "Invalid path in symlink")
-                    })
-                    .map(|s| s.to_owned())
-            })
-            .and_then(|s| {
-                s.into_string().map_err(|_| {
-                    std::io::Error::new(ErrorKind::InvalidData, "Non-UTF path in symlink")
-                })
-            })
-            .and_then(|s| {
-                s.parse()
-                    .map_err(|e| std::io::Error::new(ErrorKind::InvalidData, e))
-            })
+        std::fs::read_link(path)?
+            .file_name()
+            .and_then(OsStr::to_str)
+            .ok_or(Error::Corrupt("Invalid path in group symlink"))?
+            .parse()
+            .map_err(|_| Error::Corrupt("Invalid path in group symlink"))
     }
 
-    pub fn secondary_groups(&self) -> std::io::Result<Vec<libc::gid_t>> {
+    /// The supplementary groups of the user. A missing or empty `groups` file means there are none
+    pub fn secondary_groups(&self) -> crate::Result<Vec<libc::gid_t>> {
         let mut path = self.path.clone();
         path.push("groups");
-        let mut file = std::fs::File::open(path)?;
-        let mut bytes = String::new();
-        file.read_to_string(&mut bytes)?;
-        bytes
-            .split(',')
-            .map(|v| v.parse())
-            .collect::<Result<_, _>>()
-            .map_err(|e| std::io::Error::new(ErrorKind::InvalidData, e))
+        match std::fs::read(path) {
+            Ok(bytes) => crate::tree::parse_ids(&bytes, "Invalid groups list"),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(Vec::new()),
+            Err(e) => Err(e.into()),
+        }
     }
 
-    pub fn set_primary_group(&self, group: u32) -> std::io::Result<()> {
+    pub fn set_primary_group(&self, group: u32) -> crate::Result<()> {
         let mut path = self.path.clone();
         path.push("group");
         let mut group_path = PathBuf::from(&*crate::dirs::GROUPS);
         group_path.push(group.to_string());
-        std::os::unix::fs::symlink(group_path, path)
+        crate::atomic::replace_symlink(group_path, &path)
     }
 
-    pub fn add_secondary_group(&self, group: u32) -> std::io::Result<()> {
-        let mut groups = self.secondary_groups()?;
-        groups.push(group);
-        groups.sort_unstable();
-        let mut path = self.path.clone();
-        path.push("groups");
-        let mut file = std::fs::File::create(path)?;
-        file.write_all(
-            groups
-                .iter()
-                .dedup()
-                .map(|i| i.to_string())
-                .join(",")
-                .as_bytes(),
-        )
+    fn chroot(&self) -> crate::Result<PathBuf> {
+        let dir = self
+            .path
+            .parent()
+            .ok_or(Error::Corrupt("Invalid user directory"))?;
+        crate::tree::chroot_of(dir, &crate::dirs::USERS)
     }
 
-    pub fn remove_secondary_group(&self, group: u32) -> std::io::Result<()> {
-        let groups = self.secondary_groups()?;
+    /// The group `gid` in the same chroot as the user
+    fn group(&self, gid: u32) -> crate::Result<GroupHandle> {
+        Ok(GroupHandle::from_uid_in(gid, self.chroot()?))
+    }
+
+    /// Replaces the user's supplementary groups, and adds the user to or removes it from the member lists of those groups.
+    ///
+    /// Fails with [`Error::NoSuchGroup`] if one of the new groups does not exist.
+    pub fn set_secondary_groups(&self, groups: &[u32]) -> crate::Result<()> {
+        let uid = self.uid()?;
+        let old = self.secondary_groups()?;
+        let added: Vec<_> = groups.iter().filter(|gid| !old.contains(gid)).collect();
+        // Checked up front, so a missing group does not leave the other member lists half updated
+        for gid in &added {
+            if !self.group(**gid)?.user_dir().is_dir() {
+                return Err(Error::NoSuchGroup);
+            }
+        }
+        for gid in added {
+            self.group(*gid)?
+                .update_members(|members| members.push(uid))?;
+        }
+        for gid in old.iter().filter(|gid| !groups.contains(gid)) {
+            match self
+                .group(*gid)?
+                .update_members(|members| members.retain(|v| *v != uid))
+            {
+                Ok(()) | Err(Error::NoSuchGroup) => {}
+                Err(e) => return Err(e),
+            }
+        }
+        self.update_secondary_groups(|current| *current = groups.to_vec())
+    }
+
+    /// Adds the user to the group `group`, on both sides, like [`GroupHandle::add_member`]
+    pub fn add_secondary_group(&self, group: u32) -> crate::Result<()> {
+        let uid = self.uid()?;
+        self.group(group)?
+            .update_members(|members| members.push(uid))?;
+        self.update_secondary_groups(|groups| groups.push(group))
+    }
+
+    /// Removes the user from the group `group`, on both sides, like [`GroupHandle::remove_member`]
+    pub fn remove_secondary_group(&self, group: u32) -> crate::Result<()> {
+        let uid = self.uid()?;
+        match self
+            .group(group)?
+            .update_members(|members| members.retain(|v| *v != uid))
+        {
+            Ok(()) | Err(Error::NoSuchGroup) => {}
+            Err(e) => return Err(e),
+        }
+        self.update_secondary_groups(|groups| groups.retain(|v| *v != group))
+    }
+
+    /// Atomically modifies the user's `groups` file, without updating the member lists of the groups.
+    ///
+    /// The user side is the one [`crate::groups::repair_membership_in`] keeps, so callers that change both sides update the group first: if they are interrupted, the repair undoes the change.
+    pub(crate) fn update_secondary_groups<F: FnOnce(&mut Vec<u32>)>(
+        &self,
+        f: F,
+    ) -> crate::Result<()> {
+        if !self.path.is_dir() {
+            return Err(Error::NoSuchUser);
+        }
         let mut path = self.path.clone();
         path.push("groups");
-        let mut file = std::fs::File::create(path)?;
-        file.write_all(
-            groups
-                .iter()
-                .filter(|v| **v != group)
-                .map(|i| i.to_string())
-                .join(",")
-                .as_bytes(),
-        )
+        crate::atomic::update(&path, 0o644, |current| {
+            let mut groups = match current {
+                Some(bytes) => crate::tree::parse_ids(&bytes, "Invalid groups list")?,
+                None => Vec::new(),
+            };
+            f(&mut groups);
+            Ok(Some(crate::tree::format_ids(&mut groups)))
+        })
     }
 
-    pub fn login<'a>(&self, cmd: &'a mut Command) -> std::io::Result<&'a mut Command> {
+    pub fn login<'a>(&self, cmd: &'a mut Command) -> crate::Result<&'a mut Command> {
         cmd.groups(self.secondary_groups()?)
             .uid(self.uid()?)
             .gid(self.primary_group()?);
@@ -546,3 +852,574 @@ impl UserHandle {
         Ok(cmd)
     }
 }
+
+/// Lists every user in [`crate::dirs::USERS`], see [`iter_in`]
+pub fn iter() -> crate::Result<impl Iterator<Item = crate::Result<UserHandle>>> {
+    iter_from(PathBuf::from(*crate::dirs::USERS), None)
+}
+
+/// Lists every user within `chroot`.
+///
+/// Each user is yielded once, whether it is found through its uid directory or its name symlink, in order of uid.
+/// Malformed entries, like dangling name symlinks, are yielded as errors, so they can be reported or skipped.
+pub fn iter_in<P: AsRef<Path>>(
+    chroot: P,
+) -> crate::Result<impl Iterator<Item = crate::Result<UserHandle>>> {
+    let mut path = PathBuf::from(chroot.as_ref());
+    path.push(crate::dirs::USERS.strip_prefix("/").unwrap());
+    iter_from(path, Some(chroot.as_ref()))
+}
+
+fn iter_from(
+    dir: PathBuf,
+    chroot: Option<&Path>,
+) -> crate::Result<impl Iterator<Item = crate::Result<UserHandle>>> {
+    Ok(crate::tree::entries(&dir, chroot)?
+        .into_iter()
+        .map(|path| path.map(|path| UserHandle { path })))
+}
+
+fn parse_history(mut bytes: &[u8]) -> crate::Result<Vec<PasswordRecord>> {
+    let mut history = Vec::new();
+    while !bytes.is_empty() {
+        if bytes.len() < 4 {
+            return Err(Error::Corrupt("Truncated password history"));
+        }
+        let (len, rest) = bytes.split_at(4);
+        let len = u32::from_le_bytes([len[0], len[1], len[2], len[3]]) as usize;
+        if rest.len() < len {
+            return Err(Error::Corrupt("Truncated password history"));
+        }
+        let (file, rest) = rest.split_at(len);
+        history.push(PasswordFile::parse(file)?.record().clone());
+        bytes = rest;
+    }
+    Ok(history)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{os::unix::fs::PermissionsExt, time::Duration};
+
+    use super::*;
+
+    fn at(days: u64, secs: u64) -> SystemTime {
+        SystemTime::UNIX_EPOCH + Duration::from_secs(days * SECONDS_PER_DAY + secs)
+    }
+
+    #[test]
+    fn no_expiry() {
+        assert_eq!(
+            AuthOutcome::for_expiry(None, at(100, 0), 7),
+            AuthOutcome::Success
+        );
+    }
+
+    #[test]
+    fn past_expiry() {
+        let now = at(100, 0);
+        assert_eq!(
+            AuthOutcome::for_expiry(Some(at(99, 0)), now, 7),
+            AuthOutcome::MustChange
+        );
+        // Expired from the moment it is reached
+        assert_eq!(
+            AuthOutcome::for_expiry(Some(now), now, 7),
+            AuthOutcome::MustChange
+        );
+    }
+
+    #[test]
+    fn inside_warn_window() {
+        let now = at(100, 0);
+        assert_eq!(
+            AuthOutcome::for_expiry(Some(at(103, 0)), now, 7),
+            AuthOutcome::Warning { days_left: 3 }
+        );
+        assert_eq!(
+            AuthOutcome::for_expiry(Some(at(100, 1)), now, 7),
+            AuthOutcome::Warning { days_left: 0 }
+        );
+    }
+
+    #[test]
+    fn outside_warn_window() {
+        let now = at(100, 0);
+        assert_eq!(
+            AuthOutcome::for_expiry(Some(at(130, 0)), now, 7),
+            AuthOutcome::Success
+        );
+        assert_eq!(
+            AuthOutcome::for_expiry(Some(at(101, 0)), now, 0),
+            AuthOutcome::Success
+        );
+    }
+
+    #[test]
+    fn warn_window_boundary() {
+        let now = at(100, 0);
+        // Exactly warn_days whole days left is outside the window, a second less is inside it
+        assert_eq!(
+            AuthOutcome::for_expiry(Some(at(107, 0)), now, 7),
+            AuthOutcome::Success
+        );
+        assert_eq!(
+            AuthOutcome::for_expiry(Some(at(106, SECONDS_PER_DAY - 1)), now, 7),
+            AuthOutcome::Warning { days_left: 6 }
+        );
+    }
+
+    const PASSWD: &str = "correct horse battery staple";
+    /// [`PASSWD`] in crypt(3) form, which always needs an upgrade
+    const MD5_CRYPT: &str = "$1$saltsalt$BsXyQbZiQujHkdhwPwdol.";
+    /// `Tr0ub4dor&3` in crypt(3) form
+    const OTHER_CRYPT: &str = "$1$saltsalt$ueg/7ujBFDpyKLR2vs28p1";
+
+    /// A sysroot with the group `users` (gid 100) and the user `alice` (uid 1000) in it
+    fn sysroot() -> (tempfile::TempDir, UserHandle) {
+        let root = tempfile::tempdir().unwrap();
+        group(root.path(), "users", 100);
+        let alice = UserHandle::create_in(&new_user("alice"), root.path()).unwrap();
+        (root, alice)
+    }
+
+    #[test]
+    fn upgrade_rehashes_password() {
+        let (_root, alice) = sysroot();
+        let expiry = at(30000, 0);
+        alice.set_crypt_password(MD5_CRYPT).unwrap();
+        alice.expire_password(Some(expiry)).unwrap();
+        assert!(alice.needs_upgrade().unwrap());
+        assert_eq!(
+            alice.authenticate_and_upgrade(PASSWD).unwrap(),
+            AuthOutcome::Success
+        );
+        let record = alice.password_file().unwrap().record().clone();
+        assert_ne!(record.algorithm, crate::password::algorithms::CRYPT);
+        assert_eq!(record.expiry, Some(expiry));
+        assert!(record.verify(PASSWD).unwrap());
+        assert!(!alice.needs_upgrade().unwrap());
+    }
+
+    #[test]
+    fn upgrade_keeps_password_changed_since_authentication() {
+        let (_root, alice) = sysroot();
+        alice.set_crypt_password(MD5_CRYPT).unwrap();
+        assert_eq!(alice.authenticate(PASSWD).unwrap(), AuthOutcome::Success);
+        // An administrator changes the password before the upgrade runs
+        alice.set_crypt_password(OTHER_CRYPT).unwrap();
+        alice.upgrade_password(PASSWD).unwrap();
+        let record = alice.password_file().unwrap().record().clone();
+        assert_eq!(record.hash, OTHER_CRYPT.as_bytes());
+        assert!(!record.verify(PASSWD).unwrap());
+    }
+
+    #[test]
+    fn aging_is_private() {
+        let (_root, alice) = sysroot();
+        alice.set_max_days(Some(90)).unwrap();
+        let mode = std::fs::metadata(alice.aging_path())
+            .unwrap()
+            .permissions()
+            .mode();
+        assert_eq!(mode & 0o777, 0o600);
+        assert_eq!(alice.aging().unwrap().max_days, Some(90));
+    }
+
+    #[test]
+    fn authenticate_applies_aging() {
+        let (_root, alice) = sysroot();
+        alice.set_crypt_password(MD5_CRYPT).unwrap();
+        let today = crate::aging::today();
+        let check = |aging: Aging| {
+            alice.set_aging(&aging).unwrap();
+            alice.authenticate(PASSWD)
+        };
+        let aging = Aging {
+            last_change: Some(today - 25),
+            max_days: Some(30),
+            ..Aging::default()
+        };
+        // Expires in 5 days, less the part of today that has passed
+        assert_eq!(check(aging).unwrap(), AuthOutcome::Warning { days_left: 4 });
+        assert_eq!(
+            check(Aging {
+                warn_days: Some(3),
+                ..aging
+            })
+            .unwrap(),
+            AuthOutcome::Success
+        );
+        // Expired, but still within the inactivity period
+        let expired = Aging {
+            last_change: Some(today - 31),
+            inactive_days: Some(2),
+            ..aging
+        };
+        assert_eq!(check(expired).unwrap(), AuthOutcome::MustChange);
+        assert!(matches!(
+            check(Aging {
+                inactive_days: Some(1),
+                ..expired
+            }),
+            Err(Error::Expired)
+        ));
+        assert_eq!(
+            check(Aging {
+                last_change: Some(0),
+                ..Aging::default()
+            })
+            .unwrap(),
+            AuthOutcome::MustChange
+        );
+        assert!(matches!(
+            check(Aging {
+                expire_date: Some(today),
+                ..Aging::default()
+            }),
+            Err(Error::Expired)
+        ));
+        assert!(matches!(
+            alice.authenticate("Tr0ub4dor&3"),
+            Err(Error::WrongPassword)
+        ));
+    }
+
+    /// Hashes `passwd` cheaply, for tests that need many passwords
+    fn record(passwd: &str) -> PasswordRecord {
+        let template = crate::password::PasswordHeader {
+            version: crate::password::CURRENT_VERSION,
+            algorithm: crate::password::algorithms::SHA_256,
+            salt_and_repetition: crate::password::salting::CONCAT,
+            salt_size: 8,
+            expiry_seconds: 0,
+        };
+        PasswordRecord::new(passwd, &template, None).unwrap()
+    }
+
+    #[test]
+    fn history_keeps_the_newest_passwords() {
+        let (_root, alice) = sysroot();
+        for passwd in ["first", "second", "third", "fourth"] {
+            alice.replace_password(record(passwd), 2).unwrap();
+        }
+        let history = alice.password_history().unwrap();
+        assert_eq!(history.len(), 2);
+        assert!(history[0].verify("third").unwrap());
+        assert!(history[1].verify("second").unwrap());
+        // Lowering the depth trims the history on the next change
+        alice.replace_password(record("fifth"), 1).unwrap();
+        let history = alice.password_history().unwrap();
+        assert_eq!(history.len(), 1);
+        assert!(history[0].verify("fourth").unwrap());
+    }
+
+    #[test]
+    fn history_skips_placeholders() {
+        let (_root, alice) = sysroot();
+        // The account starts out locked, which is not a password to remember
+        alice.replace_password(record("first"), 5).unwrap();
+        assert!(alice.password_history().unwrap().is_empty());
+        alice.replace_password(record("second"), 0).unwrap();
+        assert!(!alice.history_path().exists());
+    }
+
+    #[test]
+    fn reused_passwords() {
+        let (_root, alice) = sysroot();
+        for passwd in ["first", "second", "third", "fourth"] {
+            alice.replace_password(record(passwd), 2).unwrap();
+        }
+        assert!(alice.is_password_reused("fourth").unwrap());
+        assert!(alice.is_password_reused("third").unwrap());
+        assert!(alice.is_password_reused("second").unwrap());
+        // Trimmed from the history
+        assert!(!alice.is_password_reused("first").unwrap());
+        assert!(!alice.is_password_reused("fifth").unwrap());
+    }
+
+    #[test]
+    fn failed_history_update_keeps_password() {
+        let (_root, alice) = sysroot();
+        alice.replace_password(record("first"), 5).unwrap();
+        // Another process is updating the history
+        let mut lock = alice.user_dir().to_path_buf();
+        lock.push(".password-history.lc-login.lock");
+        std::fs::write(&lock, "").unwrap();
+        assert!(matches!(
+            alice.replace_password(record("second"), 5),
+            Err(Error::Busy)
+        ));
+        assert!(alice
+            .password_file()
+            .unwrap()
+            .record()
+            .verify("first")
+            .unwrap());
+        std::fs::remove_file(lock).unwrap();
+        alice.replace_password(record("second"), 5).unwrap();
+        assert!(alice.password_history().unwrap()[0]
+            .verify("first")
+            .unwrap());
+    }
+
+    #[test]
+    fn corrupt_history() {
+        let file = PasswordFile::Enabled(record("first")).to_bytes();
+        let mut bytes = (file.len() as u32).to_le_bytes().to_vec();
+        bytes.extend_from_slice(&file);
+        assert_eq!(parse_history(&bytes).unwrap().len(), 1);
+        for len in [2, bytes.len() - 1] {
+            assert!(matches!(
+                parse_history(&bytes[..len]),
+                Err(Error::Corrupt(_))
+            ));
+        }
+    }
+
+    fn group(root: &Path, name: &str, gid: u32) -> GroupHandle {
+        GroupHandle::create_in(
+            &crate::groups::NewGroup {
+                name: name.to_string(),
+                gid: Some(gid),
+                system: false,
+            },
+            root,
+        )
+        .unwrap()
+    }
+
+    fn new_user(name: &str) -> NewUser {
+        NewUser {
+            name: name.to_string(),
+            group: 100,
+            ..NewUser::default()
+        }
+    }
+
+    #[test]
+    fn create_allocates_from_ids_conf() {
+        let root = tempfile::tempdir().unwrap();
+        crate::ids::write_ranges_in(
+            root.path(),
+            "uid_min = 2000\nuid_max = 2001\nsys_uid_min = 500\nsys_uid_max = 501\n",
+        );
+        group(root.path(), "users", 100);
+        let create = |user: &NewUser| UserHandle::create_in(user, root.path());
+
+        assert_eq!(create(&new_user("alice")).unwrap().uid().unwrap(), 2000);
+        assert_eq!(create(&new_user("bob")).unwrap().uid().unwrap(), 2001);
+        assert!(matches!(create(&new_user("carol")), Err(Error::NoFreeId)));
+        let daemon = NewUser {
+            system: true,
+            ..new_user("daemon")
+        };
+        assert_eq!(create(&daemon).unwrap().uid().unwrap(), 501);
+        let fixed = NewUser {
+            uid: Some(3000),
+            ..new_user("dave")
+        };
+        assert_eq!(create(&fixed).unwrap().uid().unwrap(), 3000);
+        assert_eq!(
+            UserHandle::from_name_in("dave", root.path())
+                .unwrap()
+                .uid()
+                .unwrap(),
+            3000
+        );
+    }
+
+    #[test]
+    fn create_sets_up_the_user() {
+        let root = tempfile::tempdir().unwrap();
+        group(root.path(), "users", 100);
+        let wheel = group(root.path(), "wheel", 10);
+        let alice = UserHandle::create_in(
+            &NewUser {
+                uid: Some(1000),
+                groups: vec![10],
+                home: Some("/home/alice".into()),
+                shell: Some("/bin/sh".into()),
+                ..new_user("alice")
+            },
+            root.path(),
+        )
+        .unwrap();
+
+        assert_eq!(alice.name().unwrap().as_deref(), Some("alice"));
+        assert_eq!(alice.primary_group().unwrap(), 100);
+        assert_eq!(alice.secondary_groups().unwrap(), [10]);
+        assert_eq!(wheel.members().unwrap(), [1000]);
+        assert_eq!(alice.home().unwrap(), Some("/home/alice".into()));
+        assert_eq!(alice.shell().unwrap(), Some("/bin/sh".into()));
+        assert_eq!(alice.password_file().unwrap(), PasswordFile::locked());
+    }
+
+    #[test]
+    fn create_refuses_conflicts() {
+        let root = tempfile::tempdir().unwrap();
+        group(root.path(), "users", 100);
+        let create = |user: &NewUser| UserHandle::create_in(user, root.path());
+        let alice = NewUser {
+            uid: Some(1000),
+            ..new_user("alice")
+        };
+        create(&alice).unwrap();
+
+        assert!(matches!(create(&alice), Err(Error::UserExists)));
+        let same_uid = NewUser {
+            name: "bob".to_string(),
+            ..alice.clone()
+        };
+        assert!(matches!(create(&same_uid), Err(Error::UserExists)));
+        assert!(matches!(
+            create(&NewUser {
+                uid: Some(1001),
+                ..new_user("alice")
+            }),
+            Err(Error::UserExists)
+        ));
+        assert!(matches!(
+            create(&NewUser {
+                groups: vec![10],
+                ..new_user("bob")
+            }),
+            Err(Error::NoSuchGroup)
+        ));
+        assert!(matches!(create(&new_user("1234")), Err(Error::InvalidName)));
+        // None of the failures left anything behind
+        let names: Vec<_> = iter_in(root.path())
+            .unwrap()
+            .map(|user| user.unwrap().name().unwrap())
+            .collect();
+        assert_eq!(names, [Some("alice".to_string())]);
+    }
+
+    #[test]
+    fn delete_cleans_up_groups() {
+        let (root, alice) = sysroot();
+        let wheel = group(root.path(), "wheel", 10);
+        let staff = group(root.path(), "staff", 20);
+        let bob = UserHandle::create_in(&new_user("bob"), root.path()).unwrap();
+        let (uid, bob_uid) = (alice.uid().unwrap(), bob.uid().unwrap());
+        alice.add_secondary_group(10).unwrap();
+        bob.add_secondary_group(10).unwrap();
+        wheel.set_admins(&[uid, bob_uid]).unwrap();
+        // A member list that has drifted from alice's groups file
+        staff.update_members(|members| members.push(uid)).unwrap();
+        alice.set_password("correct horse").unwrap();
+        let dir = alice.user_dir().to_path_buf();
+
+        alice.delete().unwrap();
+        assert!(!dir.exists());
+        assert!(matches!(
+            UserHandle::from_name_in("alice", root.path()),
+            Err(Error::NoSuchUser)
+        ));
+        assert_eq!(wheel.members().unwrap(), [bob_uid]);
+        assert_eq!(wheel.admins().unwrap(), [bob_uid]);
+        assert!(staff.members().unwrap().is_empty());
+        assert!(matches!(
+            UserHandle::from_uid_in(uid, root.path()).delete(),
+            Err(Error::NoSuchUser)
+        ));
+        assert!(crate::groups::check_membership_in(root.path())
+            .unwrap()
+            .is_empty());
+    }
+
+    #[test]
+    fn rename() {
+        let (root, alice) = sysroot();
+        UserHandle::create_in(&new_user("bob"), root.path()).unwrap();
+
+        assert!(matches!(alice.set_name("bob"), Err(Error::UserExists)));
+        assert!(matches!(alice.set_name("a b"), Err(Error::InvalidName)));
+        assert_eq!(alice.name().unwrap().as_deref(), Some("alice"));
+        alice.set_name("carol").unwrap();
+        assert_eq!(alice.name().unwrap().as_deref(), Some("carol"));
+        assert_eq!(
+            UserHandle::from_name_in("carol", root.path())
+                .unwrap()
+                .user_dir(),
+            alice.user_dir()
+        );
+        assert!(matches!(
+            UserHandle::from_name_in("alice", root.path()),
+            Err(Error::NoSuchUser)
+        ));
+    }
+
+    #[test]
+    fn change_uid() {
+        let (root, mut alice) = sysroot();
+        let bob = UserHandle::create_in(&new_user("bob"), root.path()).unwrap();
+        let wheel = group(root.path(), "wheel", 10);
+        alice.add_secondary_group(10).unwrap();
+        wheel.set_admins(&[alice.uid().unwrap()]).unwrap();
+
+        assert!(matches!(
+            alice.set_uid(bob.uid().unwrap()),
+            Err(Error::UserExists)
+        ));
+        alice.set_uid(2000).unwrap();
+        assert_eq!(alice.uid().unwrap(), 2000);
+        assert_eq!(alice.secondary_groups().unwrap(), [10]);
+        assert_eq!(wheel.members().unwrap(), [2000]);
+        assert_eq!(wheel.admins().unwrap(), [2000]);
+        assert_eq!(
+            UserHandle::from_name_in("alice", root.path())
+                .unwrap()
+                .uid()
+                .unwrap(),
+            2000
+        );
+        assert!(crate::groups::check_membership_in(root.path())
+            .unwrap()
+            .is_empty());
+    }
+
+    #[test]
+    fn setters_replace_links() {
+        let (root, mut alice) = sysroot();
+        group(root.path(), "staff", 20);
+        for home in ["/home/alice", "/srv/alice"] {
+            alice.set_home(home).unwrap();
+            assert_eq!(alice.home().unwrap(), Some(home.into()));
+        }
+        for shell in ["/bin/sh", "/bin/bash"] {
+            alice.set_shell(shell).unwrap();
+            assert_eq!(alice.shell().unwrap(), Some(shell.into()));
+        }
+        alice.set_primary_group(20).unwrap();
+        assert_eq!(alice.primary_group().unwrap(), 20);
+    }
+
+    #[test]
+    fn secondary_groups() {
+        let (root, alice) = sysroot();
+        let wheel = group(root.path(), "wheel", 10);
+        let staff = group(root.path(), "staff", 20);
+        let uid = alice.uid().unwrap();
+
+        alice.add_secondary_group(10).unwrap();
+        assert!(matches!(
+            alice.add_secondary_group(30),
+            Err(Error::NoSuchGroup)
+        ));
+        assert_eq!(alice.secondary_groups().unwrap(), [10]);
+        alice.set_secondary_groups(&[20]).unwrap();
+        assert_eq!(alice.secondary_groups().unwrap(), [20]);
+        assert!(wheel.members().unwrap().is_empty());
+        assert_eq!(staff.members().unwrap(), [uid]);
+        assert!(matches!(
+            alice.set_secondary_groups(&[10, 30]),
+            Err(Error::NoSuchGroup)
+        ));
+        alice.remove_secondary_group(20).unwrap();
+        assert!(alice.secondary_groups().unwrap().is_empty());
+        assert!(crate::groups::check_membership_in(root.path())
+            .unwrap()
+            .is_empty());
+    }
+}